@@ -102,7 +102,7 @@ impl Input {
         samples_count * output_bytes_per_input_sample
     }
 
-    fn to_info(&self) -> Info {
+    fn to_info(&self) -> Info<'_> {
         create_info_from_plte_trns_bitdepth(&self.palette, self.trns.as_deref(), self.src_bit_depth)
     }
 }
@@ -117,8 +117,8 @@ fn bench_create_fn(c: &mut Criterion, plte_size: usize, trns_size: usize) {
     group.sample_size(1000);
 
     let mut rng = rand::thread_rng();
-    let plte = get_random_bytes(&mut rng, 3 * plte_size as usize);
-    let trns = get_random_bytes(&mut rng, trns_size as usize);
+    let plte = get_random_bytes(&mut rng, 3 * plte_size);
+    let trns = get_random_bytes(&mut rng, trns_size);
     let info = create_info_from_plte_trns_bitdepth(&plte, Some(&trns), 8);
     group.bench_with_input(
         format!("plte={plte_size}/trns={trns_size:?}"),
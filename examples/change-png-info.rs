@@ -21,14 +21,9 @@ fn main() -> BoxResult<()> {
     // # Encode
     let path_out = Path::new(r"./target/test_modified.png");
     let file = File::create(path_out)?;
-    let ref mut w = BufWriter::new(file);
+    let w = &mut BufWriter::new(file);
 
-    // Get defaults for interlaced parameter.
-    let mut info_out = png_info.clone();
-    let info_default = png::Info::default();
-
-    // Edit previous info
-    info_out.interlaced = info_default.interlaced;
+    let info_out = png_info.clone();
     let mut encoder = png::Encoder::with_info(w, info_out)?;
     encoder.set_depth(png_info.bit_depth);
 
@@ -44,7 +39,7 @@ fn main() -> BoxResult<()> {
     while let Ok(info) = reader.next_frame(&mut buf) {
         let bytes = &buf[..info.buffer_size()];
         println!("{} {}", info.buffer_size(), reader.output_buffer_size());
-        writer.write_image_data(&bytes)?;
+        writer.write_image_data(bytes)?;
         counter += 1;
         println!("Written frame: {}", counter);
     }
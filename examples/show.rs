@@ -14,36 +14,16 @@ use std::{borrow::Cow, env, fs::File, io, path};
 fn load_image(path: &path::PathBuf) -> io::Result<RawImage2d<'static, u8>> {
     use png::ColorType::*;
     let mut decoder = png::Decoder::new(File::open(path)?);
-    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    decoder.set_transformations(
+        png::Transformations::normalize_to_color8() | png::Transformations::GRAY_TO_RGB,
+    );
     let mut reader = decoder.read_info()?;
-    let mut img_data = vec![0; reader.output_buffer_size()];
-    let info = reader.next_frame(&mut img_data)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
 
-    let (data, format) = match info.color_type {
-        Rgb => (img_data, ClientFormat::U8U8U8),
-        Rgba => (img_data, ClientFormat::U8U8U8U8),
-        Grayscale => (
-            {
-                let mut vec = Vec::with_capacity(img_data.len() * 3);
-                for g in img_data {
-                    vec.extend([g, g, g].iter().cloned())
-                }
-                vec
-            },
-            ClientFormat::U8U8U8,
-        ),
-        GrayscaleAlpha => (
-            {
-                let mut vec = Vec::with_capacity(img_data.len() * 3);
-                for ga in img_data.chunks(2) {
-                    let g = ga[0];
-                    let a = ga[1];
-                    vec.extend([g, g, g, a].iter().cloned())
-                }
-                vec
-            },
-            ClientFormat::U8U8U8U8,
-        ),
+    let format = match info.color_type {
+        Rgb => ClientFormat::U8U8U8,
+        Rgba => ClientFormat::U8U8U8U8,
         _ => unreachable!("uncovered color type"),
     };
 
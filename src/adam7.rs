@@ -166,6 +166,70 @@ pub fn expand_pass(
     }
 }
 
+/// Like `expand_pass` for an image of 16-bit samples, converting each sample of the `scanline`
+/// from its two bytes with `from_bytes`.
+pub fn expand_pass_u16(
+    img: &mut [u16],
+    width: u32,
+    scanline: &[u8],
+    pass: u8,
+    line_no: u32,
+    samples_pp: u8,
+    from_bytes: fn([u8; 2]) -> u16,
+) {
+    // pass is out of range but don't blow up
+    if pass == 0 || pass > 7 {
+        return;
+    }
+
+    let samples_pp = usize::from(samples_pp);
+    let bit_indices = expand_adam7_bits(pass, width as usize, line_no as usize, 16 * samples_pp);
+    for (bitpos, px) in bit_indices.zip(scanline.chunks_exact(2 * samples_pp)) {
+        let pixel = &mut img[bitpos / 16..][..samples_pp];
+        for (sample, bytes) in pixel.iter_mut().zip(px.chunks_exact(2)) {
+            *sample = from_bytes([bytes[0], bytes[1]]);
+        }
+    }
+}
+
+/// Extracts a line of an Adam 7 pass from a deinterlaced image, the inverse of `expand_pass`.
+///
+/// The `scanline` has to be zeroed for sub-byte bit depths, bits are only ever set.
+pub fn extract_pass(
+    img: &[u8],
+    width: u32,
+    scanline: &mut [u8],
+    pass: u8,
+    line_no: u32,
+    bits_pp: u8,
+) {
+    let width = width as usize;
+    let line_no = line_no as usize;
+    let bits_pp = bits_pp as usize;
+
+    // pass is out of range but don't blow up
+    if pass == 0 || pass > 7 {
+        return;
+    }
+
+    let bit_indices = expand_adam7_bits(pass, width, line_no, bits_pp);
+
+    if bits_pp < 8 {
+        let mask = (1u8 << bits_pp) - 1;
+        for (idx, pos) in bit_indices.enumerate() {
+            let px = (img[pos / 8] >> (8 - pos % 8 - bits_pp)) & mask;
+            let out = idx * bits_pp;
+            scanline[out / 8] |= px << (8 - out % 8 - bits_pp);
+        }
+    } else {
+        let bytes_pp = bits_pp / 8;
+
+        for (bitpos, px) in bit_indices.zip(scanline.chunks_mut(bytes_pp)) {
+            px.copy_from_slice(&img[bitpos / 8..][..bytes_pp]);
+        }
+    }
+}
+
 #[test]
 fn test_adam7() {
     /*
@@ -382,3 +446,30 @@ fn test_expand_pass_subbyte() {
         img
     );
 }
+
+#[test]
+fn test_extract_pass_inverts_expand_pass() {
+    for &bits_pp in &[1u8, 2, 4, 8, 24, 64] {
+        let (width, height) = (13, 11);
+        let line_len = (width as usize * bits_pp as usize + 7) / 8;
+        let img: Vec<u8> = (0..line_len * height as usize)
+            .map(|i| (i * 37 % 251) as u8)
+            .collect();
+        // Padding bits at the end of each line are not part of any pass.
+        let mut img = img;
+        if bits_pp < 8 {
+            let pad = (line_len * 8 - width as usize * bits_pp as usize) as u32;
+            for line in img.chunks_mut(line_len) {
+                *line.last_mut().unwrap() &= !((1u16 << pad) - 1) as u8;
+            }
+        }
+
+        let mut expanded = vec![0; img.len()];
+        for (pass, line_no, pass_width) in Adam7Iterator::new(width, height) {
+            let mut scanline = vec![0; (pass_width as usize * bits_pp as usize + 7) / 8];
+            extract_pass(&img, width, &mut scanline, pass, line_no, bits_pp);
+            expand_pass(&mut expanded, width, &scanline, pass, line_no, bits_pp);
+        }
+        assert_eq!(expanded, img, "bits_pp {}", bits_pp);
+    }
+}
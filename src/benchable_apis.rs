@@ -3,7 +3,7 @@
 
 use crate::common::BytesPerPixel;
 use crate::filter::FilterType;
-use crate::{BitDepth, ColorType, Info};
+use crate::{BitDepth, ColorType, DecodingError, Info, Transformations};
 
 /// Re-exporting `unfilter` to make it easier to benchmark, despite some items being only
 /// `pub(crate)`: `fn unfilter`, `enum BytesPerPixel`.
@@ -12,7 +12,28 @@ pub fn unfilter(filter: FilterType, tbpp: u8, previous: &[u8], current: &mut [u8
     crate::filter::unfilter(filter, tbpp, previous, current)
 }
 
-pub use crate::decoder::transform::{create_transform_fn, TransformFn};
+pub use crate::decoder::transform::TransformFn;
+
+/// Re-exporting `create_transform_fn` to make it easier to benchmark, despite its parameters for
+/// the non-flag transformations being only `pub(crate)`.
+pub fn create_transform_fn(
+    info: &Info,
+    transform: Transformations,
+) -> Result<TransformFn, DecodingError> {
+    use crate::decoder::transform::{ScratchRows, TransformStage};
+    let transform_fn = crate::decoder::transform::create_transform_fn(
+        info,
+        transform,
+        &Default::default(),
+        TransformStage::All,
+    )?;
+    // The rows are kept between calls as in the `Reader`, behind a lock as the function is `Fn`.
+    let scratch_rows = std::sync::Mutex::new(ScratchRows::default());
+    Ok(Box::new(move |input, output, info| {
+        let mut scratch_rows = scratch_rows.lock().unwrap();
+        transform_fn(input, output, info, &mut scratch_rows)
+    }))
+}
 
 pub fn create_info_from_plte_trns_bitdepth<'a>(
     plte: &'a [u8],
@@ -23,6 +23,8 @@ pub const IEND: ChunkType = ChunkType(*b"IEND");
 pub const tRNS: ChunkType = ChunkType(*b"tRNS");
 /// Background colour
 pub const bKGD: ChunkType = ChunkType(*b"bKGD");
+/// Significant bits
+pub const sBIT: ChunkType = ChunkType(*b"sBIT");
 /// Image last-modification time
 pub const tIME: ChunkType = ChunkType(*b"tIME");
 /// Physical pixel dimensions
@@ -35,6 +37,14 @@ pub const gAMA: ChunkType = ChunkType(*b"gAMA");
 pub const sRGB: ChunkType = ChunkType(*b"sRGB");
 /// ICC profile chunk
 pub const iCCP: ChunkType = ChunkType(*b"iCCP");
+/// Coding-independent code points for video signal type identification
+pub const cICP: ChunkType = ChunkType(*b"cICP");
+/// Mastering display color volume
+pub const mDCV: ChunkType = ChunkType(*b"mDCV");
+/// Content light level information
+pub const cLLI: ChunkType = ChunkType(*b"cLLI");
+/// Exchangeable image file format (Exif) metadata
+pub const eXIf: ChunkType = ChunkType(*b"eXIf");
 /// Latin-1 uncompressed textual data
 pub const tEXt: ChunkType = ChunkType(*b"tEXt");
 /// Latin-1 compressed textual data
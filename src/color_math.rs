@@ -0,0 +1,138 @@
+//! Matrix arithmetic on CIE XYZ shared by the color conversions of the decoder, the encoder and
+//! the ICC profile support.
+
+use crate::SourceChromaticities;
+
+/// A 3×3 matrix in row-major order.
+pub(crate) type Matrix = [[f64; 3]; 3];
+
+/// Chromaticities of white, red, green and blue of the sRGB color space.
+pub(crate) const SRGB_PRIMARIES: [(f64, f64); 4] = [
+    (0.3127, 0.3290),
+    (0.640, 0.330),
+    (0.300, 0.600),
+    (0.150, 0.060),
+];
+
+/// Returns the chromaticities of white, red, green and blue for the `ColourPrimaries` code
+/// points of ITU-T H.273 that are commonly used with still images.
+pub(crate) fn cicp_primaries(code: u8) -> Option<[(f64, f64); 4]> {
+    match code {
+        // BT.709, the primaries of sRGB
+        1 => Some(SRGB_PRIMARIES),
+        // BT.2020 and BT.2100
+        9 => Some([
+            (0.3127, 0.3290),
+            (0.708, 0.292),
+            (0.170, 0.797),
+            (0.131, 0.046),
+        ]),
+        // Display P3
+        12 => Some([
+            (0.3127, 0.3290),
+            (0.680, 0.320),
+            (0.265, 0.690),
+            (0.150, 0.060),
+        ]),
+        _ => None,
+    }
+}
+
+/// Converts the chromaticities of a `cHRM` chunk to white, red, green and blue as floats.
+pub(crate) fn chromaticities_to_f64(chromaticities: SourceChromaticities) -> [(f64, f64); 4] {
+    let SourceChromaticities {
+        white,
+        red,
+        green,
+        blue,
+    } = chromaticities;
+    [white, red, green, blue].map(|(x, y)| {
+        (
+            f64::from(x.into_scaled()) / 100_000.0,
+            f64::from(y.into_scaled()) / 100_000.0,
+        )
+    })
+}
+
+/// Builds the matrix from linear RGB with the given primaries to linear sRGB, adapting the white
+/// point with the Bradford transform.
+pub(crate) fn to_srgb_matrix(primaries: [(f64, f64); 4]) -> Option<Matrix> {
+    let to_xyz = rgb_to_xyz(primaries)?;
+    let srgb_to_xyz = rgb_to_xyz(SRGB_PRIMARIES)?;
+    let adapt = adaptation(xy_to_xyz(primaries[0])?, xy_to_xyz(SRGB_PRIMARIES[0])?)?;
+    Some(multiply(&invert(&srgb_to_xyz)?, &multiply(&adapt, &to_xyz)))
+}
+
+/// Converts a chromaticity to XYZ with a luminance of 1.
+pub(crate) fn xy_to_xyz((x, y): (f64, f64)) -> Option<[f64; 3]> {
+    if y <= 0.0 {
+        return None;
+    }
+    Some([x / y, 1.0, (1.0 - x - y) / y])
+}
+
+/// Builds the matrix from linear RGB to XYZ, scaled such that white has a luminance of 1.
+pub(crate) fn rgb_to_xyz(primaries: [(f64, f64); 4]) -> Option<Matrix> {
+    let [white, red, green, blue] = primaries;
+    let (r, g, b) = (xy_to_xyz(red)?, xy_to_xyz(green)?, xy_to_xyz(blue)?);
+    let columns = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+    let scale = apply(&invert(&columns)?, xy_to_xyz(white)?);
+
+    let mut matrix = columns;
+    for row in matrix.iter_mut() {
+        for (value, scale) in row.iter_mut().zip(scale) {
+            *value *= scale;
+        }
+    }
+    Some(matrix)
+}
+
+/// Builds the Bradford chromatic adaptation between two white points given in XYZ.
+pub(crate) fn adaptation(source: [f64; 3], target: [f64; 3]) -> Option<Matrix> {
+    const BRADFORD: Matrix = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+    let source = apply(&BRADFORD, source);
+    let target = apply(&BRADFORD, target);
+    let mut scale = [[0.0; 3]; 3];
+    for i in 0..3 {
+        scale[i][i] = target[i] / source[i];
+    }
+    Some(multiply(&invert(&BRADFORD)?, &multiply(&scale, &BRADFORD)))
+}
+
+pub(crate) fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    result
+}
+
+pub(crate) fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    [0, 1, 2].map(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+pub(crate) fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    if det.abs() < 1e-12 || !det.is_finite() {
+        return None;
+    }
+
+    let mut result = [[0.0; 3]; 3];
+    for (i, row) in result.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / det;
+        }
+    }
+    Some(result)
+}
@@ -154,6 +154,199 @@ impl Unit {
     }
 }
 
+/// Default background color, as stored in the `bKGD` chunk.
+///
+/// Which variant is valid depends on the color type of the image. Gray and RGB samples are stored
+/// at the bit depth of the image, i.e. their value must fit into `bit_depth` bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BackgroundColor {
+    /// Gray level, for `Grayscale` and `GrayscaleAlpha` images.
+    Grayscale(u16),
+    /// Red, green and blue samples, for `Rgb` and `Rgba` images.
+    Rgb { red: u16, green: u16, blue: u16 },
+    /// Index of the palette entry, for `Indexed` images.
+    Indexed(u8),
+}
+
+impl BackgroundColor {
+    /// Parse the contents of a `bKGD` chunk for an image of the given color type.
+    ///
+    /// Returns `None` if the length of the data does not match the color type.
+    pub(crate) fn from_be_bytes(color_type: ColorType, data: &[u8]) -> Option<Self> {
+        let sample = |i: usize| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+        match color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha if data.len() == 2 => {
+                Some(BackgroundColor::Grayscale(sample(0)))
+            }
+            ColorType::Rgb | ColorType::Rgba if data.len() == 6 => Some(BackgroundColor::Rgb {
+                red: sample(0),
+                green: sample(1),
+                blue: sample(2),
+            }),
+            ColorType::Indexed if data.len() == 1 => Some(BackgroundColor::Indexed(data[0])),
+            _ => None,
+        }
+    }
+
+    /// Checks that this color can be used with an image of the given format.
+    ///
+    /// The variant must match the color type, samples must fit into the bit depth and palette
+    /// indices must refer to an entry of the palette (given in bytes, 3 per entry).
+    pub(crate) fn is_valid_for(
+        self,
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        palette: Option<&[u8]>,
+    ) -> bool {
+        let max = ((1u32 << bit_depth as u8) - 1) as u16;
+        match (self, color_type) {
+            (
+                BackgroundColor::Grayscale(gray),
+                ColorType::Grayscale | ColorType::GrayscaleAlpha,
+            ) => gray <= max,
+            (BackgroundColor::Rgb { red, green, blue }, ColorType::Rgb | ColorType::Rgba) => {
+                red <= max && green <= max && blue <= max
+            }
+            (BackgroundColor::Indexed(index), ColorType::Indexed) => {
+                palette.map_or(false, |palette| usize::from(index) < palette.len() / 3)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
+        match self {
+            BackgroundColor::Grayscale(gray) => {
+                encoder::write_chunk(w, chunk::bKGD, &gray.to_be_bytes())
+            }
+            BackgroundColor::Rgb { red, green, blue } => {
+                let mut data = [0; 6];
+                data[..2].copy_from_slice(&red.to_be_bytes());
+                data[2..4].copy_from_slice(&green.to_be_bytes());
+                data[4..].copy_from_slice(&blue.to_be_bytes());
+                encoder::write_chunk(w, chunk::bKGD, &data)
+            }
+            BackgroundColor::Indexed(index) => encoder::write_chunk(w, chunk::bKGD, &[index]),
+        }
+    }
+}
+
+/// Weights of the red, green and blue samples for [`Transformations::RGB_TO_GRAY`], see
+/// [`Decoder::set_gray_coefficients`](crate::Decoder::set_gray_coefficients).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrayCoefficients {
+    /// The luma coefficients of ITU-R BT.601: 0.299, 0.587 and 0.114.
+    Rec601,
+    /// The luma coefficients of ITU-R BT.709: 0.2126, 0.7152 and 0.0722. This is the default.
+    Rec709,
+    /// Custom weights, which should add up to 1. Results out of range are clamped.
+    Custom { red: f32, green: f32, blue: f32 },
+}
+
+impl GrayCoefficients {
+    pub(crate) fn weights(self) -> [f32; 3] {
+        match self {
+            GrayCoefficients::Rec601 => [0.299, 0.587, 0.114],
+            GrayCoefficients::Rec709 => [0.2126, 0.7152, 0.0722],
+            GrayCoefficients::Custom { red, green, blue } => [red, green, blue],
+        }
+    }
+}
+
+impl Default for GrayCoefficients {
+    fn default() -> Self {
+        GrayCoefficients::Rec709
+    }
+}
+
+/// The color to composite transparent pixels over when decoding, see
+/// [`Decoder::set_background`](crate::Decoder::set_background).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Background {
+    /// The default background color of the `bKGD` chunk. Images without a valid `bKGD` chunk keep
+    /// their alpha channel.
+    File,
+    /// A color with 16 bits per sample, in the color space of the image. It is scaled to the bit
+    /// depth of the output, grayscale images use its luma with the coefficients of
+    /// [`Decoder::set_gray_coefficients`](crate::Decoder::set_gray_coefficients).
+    Color { red: u16, green: u16, blue: u16 },
+}
+
+/// Time of the last image modification, as stored in the `tIME` chunk.
+///
+/// The time is given in Universal Time (UTC). The PNG specification restricts the fields to a
+/// valid calendar range, see [`Time::is_valid`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Time {
+    /// Complete year, e.g. 1995 and not 95.
+    pub year: u16,
+    /// Month of the year, 1-12.
+    pub month: u8,
+    /// Day of the month, 1-31.
+    pub day: u8,
+    /// Hour of the day, 0-23.
+    pub hour: u8,
+    /// Minute of the hour, 0-59.
+    pub minute: u8,
+    /// Second of the minute, 0-60 to allow for leap seconds.
+    pub second: u8,
+}
+
+impl Time {
+    /// Creates a new time, returning `None` if any of the fields is out of range.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let time = Time {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        Some(time).filter(Time::is_valid)
+    }
+
+    /// Checks that all fields are within the ranges allowed by the PNG specification.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour <= 23
+            && self.minute <= 59
+            && self.second <= 60
+    }
+
+    /// Parse the contents of a `tIME` chunk.
+    ///
+    /// Returns `None` if the chunk has the wrong length. The fields are not validated.
+    pub(crate) fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [y0, y1, month, day, hour, minute, second] => Some(Time {
+                year: u16::from_be_bytes([y0, y1]),
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
+        let [y0, y1] = self.year.to_be_bytes();
+        let data = [
+            y0,
+            y1,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+        ];
+        encoder::write_chunk(w, chunk::tIME, &data)
+    }
+}
+
 /// How to reset buffer of an animated png (APNG) at the end of a frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -470,6 +663,272 @@ impl SrgbRenderingIntent {
     }
 }
 
+/// Coding-independent code points, as stored in the `cICP` chunk.
+///
+/// The values identify the color space and transfer function of the image as defined by ITU-T
+/// H.273, e.g. for HDR content with the PQ or HLG transfer functions. PNG only supports RGB
+/// encoding, so `matrix_coefficients` must be 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CodingIndependentCodePoints {
+    /// Color primaries, e.g. 1 for BT.709 and sRGB or 9 for BT.2020.
+    pub color_primaries: u8,
+    /// Transfer characteristics, e.g. 13 for sRGB, 16 for PQ or 18 for HLG.
+    pub transfer_function: u8,
+    /// Matrix coefficients, always 0 (identity) in PNG.
+    pub matrix_coefficients: u8,
+    /// Whether the samples use the full range instead of the narrow range of video signals.
+    pub is_video_full_range_image: bool,
+}
+
+impl CodingIndependentCodePoints {
+    /// Parse the contents of a `cICP` chunk.
+    ///
+    /// Returns `None` if the chunk has the wrong length, non-zero matrix coefficients or a full
+    /// range flag other than 0 or 1.
+    pub(crate) fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [color_primaries, transfer_function, 0, full_range @ (0 | 1)] => {
+                Some(CodingIndependentCodePoints {
+                    color_primaries,
+                    transfer_function,
+                    matrix_coefficients: 0,
+                    is_video_full_range_image: full_range == 1,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
+        let data = [
+            self.color_primaries,
+            self.transfer_function,
+            self.matrix_coefficients,
+            self.is_video_full_range_image as u8,
+        ];
+        encoder::write_chunk(w, chunk::cICP, &data)
+    }
+}
+
+/// The color space of an image, as determined by [`Info::color_space`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace<'a> {
+    /// The color space identified by the `cICP` chunk.
+    CodingIndependentCodePoints(CodingIndependentCodePoints),
+    /// The color space described by the ICC profile of the `iCCP` chunk.
+    IccProfile(&'a [u8]),
+    /// The sRGB color space, with the rendering intent of the `sRGB` chunk.
+    Srgb(SrgbRenderingIntent),
+    /// The transfer function and primaries of the `gAMA` and `cHRM` chunks, at least one of
+    /// which is present.
+    GammaChromaticities {
+        gamma: Option<ScaledFloat>,
+        chromaticities: Option<SourceChromaticities>,
+    },
+    /// The image does not specify its color space, it is commonly assumed to be sRGB.
+    Unspecified,
+}
+
+/// A color space to declare with [`Encoder::set_color_space`](crate::Encoder::set_color_space).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpacePreset {
+    /// sRGB, with the given rendering intent.
+    Srgb(SrgbRenderingIntent),
+    /// Display P3, the primaries of DCI-P3 with a D65 white point and the sRGB transfer function.
+    DisplayP3,
+    /// The primaries of ITU-R BT.2020 with the transfer function of BT.709.
+    Rec2020,
+    /// The primaries of sRGB with linear samples.
+    LinearSrgb,
+    /// The given primaries and white point, with samples encoded as `linear ^ gamma`.
+    Custom {
+        gamma: ScaledFloat,
+        chromaticities: SourceChromaticities,
+    },
+}
+
+/// Luminance in candelas per square meter (cd/m²), stored as a multiple of 0.0001 cd/m².
+///
+/// This is the fixed-point format of the `mDCV` and `cLLI` chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Luminance(u32);
+
+impl Luminance {
+    const SCALING: f32 = 10_000.0;
+
+    /// Slightly inaccurate scaling and quantization.
+    /// Clamps the value into the representable range if it is negative or too large.
+    pub fn new(value: f32) -> Self {
+        Self((value.max(0.0) * Self::SCALING).floor() as u32)
+    }
+
+    /// Fully accurate construction from a value scaled as per specification.
+    pub fn from_scaled(val: u32) -> Self {
+        Self(val)
+    }
+
+    /// Get the accurate encoded value.
+    pub fn into_scaled(self) -> u32 {
+        self.0
+    }
+
+    /// Get the unscaled value in cd/m² as a floating point.
+    pub fn into_value(self) -> f32 {
+        self.0 as f32 / Self::SCALING
+    }
+}
+
+/// Color volume of the display used to master the content, as stored in the `mDCV` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MasteringDisplayColorVolume {
+    /// Chromaticities of the display primaries and white point.
+    ///
+    /// The chunk stores these in units of 0.00002, half the precision of `cHRM`. Odd multiples
+    /// of 0.00001 are rounded down when encoding.
+    pub chromaticities: SourceChromaticities,
+    /// Maximum luminance of the display.
+    pub max_luminance: Luminance,
+    /// Minimum luminance of the display.
+    pub min_luminance: Luminance,
+}
+
+impl MasteringDisplayColorVolume {
+    /// Parse the contents of a `mDCV` chunk.
+    ///
+    /// Returns `None` if the chunk is not exactly 24 bytes long.
+    pub(crate) fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != 24 {
+            return None;
+        }
+        let chromaticity = |i: usize| {
+            let value = u16::from_be_bytes([data[2 * i], data[2 * i + 1]]);
+            ScaledFloat::from_scaled(u32::from(value) * 2)
+        };
+        let luminance = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&data[offset..offset + 4]);
+            Luminance::from_scaled(u32::from_be_bytes(bytes))
+        };
+        Some(MasteringDisplayColorVolume {
+            chromaticities: SourceChromaticities {
+                red: (chromaticity(0), chromaticity(1)),
+                green: (chromaticity(2), chromaticity(3)),
+                blue: (chromaticity(4), chromaticity(5)),
+                white: (chromaticity(6), chromaticity(7)),
+            },
+            max_luminance: luminance(16),
+            min_luminance: luminance(20),
+        })
+    }
+
+    pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
+        let c = self.chromaticities;
+        let mut data = Vec::with_capacity(24);
+        for value in [c.red, c.green, c.blue, c.white]
+            .iter()
+            .flat_map(|&(x, y)| [x, y])
+        {
+            let value = (value.into_scaled() / 2).min(u16::MAX.into()) as u16;
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&self.max_luminance.into_scaled().to_be_bytes());
+        data.extend_from_slice(&self.min_luminance.into_scaled().to_be_bytes());
+        encoder::write_chunk(w, chunk::mDCV, &data)
+    }
+}
+
+/// Light level of the content, as stored in the `cLLI` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ContentLightLevelInfo {
+    /// Maximum light level of any single pixel (MaxCLL).
+    pub max_content_light_level: Luminance,
+    /// Maximum average light level of any single frame (MaxFALL).
+    pub max_frame_average_light_level: Luminance,
+}
+
+impl ContentLightLevelInfo {
+    /// Parse the contents of a `cLLI` chunk.
+    ///
+    /// Returns `None` if the chunk is not exactly 8 bytes long.
+    pub(crate) fn from_be_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [a0, a1, a2, a3, b0, b1, b2, b3] => Some(ContentLightLevelInfo {
+                max_content_light_level: Luminance::from_scaled(u32::from_be_bytes([
+                    a0, a1, a2, a3,
+                ])),
+                max_frame_average_light_level: Luminance::from_scaled(u32::from_be_bytes([
+                    b0, b1, b2, b3,
+                ])),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn encode<W: Write>(self, w: &mut W) -> encoder::Result<()> {
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&self.max_content_light_level.into_scaled().to_be_bytes());
+        data[4..].copy_from_slice(
+            &self
+                .max_frame_average_light_level
+                .into_scaled()
+                .to_be_bytes(),
+        );
+        encoder::write_chunk(w, chunk::cLLI, &data)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum ExifByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+/// Reads the byte order from the TIFF header at the start of Exif data.
+///
+/// Returns `None` if the data does not start with a valid header.
+fn exif_byte_order(exif: &[u8]) -> Option<ExifByteOrder> {
+    match exif.get(..4)? {
+        b"II\x2a\x00" => Some(ExifByteOrder::LittleEndian),
+        b"MM\x00\x2a" => Some(ExifByteOrder::BigEndian),
+        _ => None,
+    }
+}
+
+/// Checks that the Exif data starts with the TIFF header required by the `eXIf` chunk.
+pub(crate) fn is_valid_exif(exif: &[u8]) -> bool {
+    exif_byte_order(exif).is_some()
+}
+
+/// Position of a chunk relative to the `PLTE` and `IDAT` chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkPosition {
+    /// After `IHDR` and before `PLTE`, or before `IDAT` in images without a palette.
+    BeforePlte,
+    /// After `PLTE` and before `IDAT`.
+    BeforeIdat,
+    /// After `IDAT` and before `IEND`.
+    AfterIdat,
+}
+
+/// An ancillary chunk the decoder does not interpret, kept to write it out again unchanged.
+///
+/// See [`DecodeOptions::set_retain_unknown_chunks`](crate::DecodeOptions::set_retain_unknown_chunks).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownChunk {
+    /// The type of the chunk.
+    pub chunk_type: chunk::ChunkType,
+    /// The data of the chunk, without length, type and CRC.
+    pub data: Vec<u8>,
+    /// Where the chunk appeared in the image.
+    pub position: ChunkPosition,
+}
+
+impl UnknownChunk {
+    pub fn encode<W: Write>(&self, w: &mut W) -> encoder::Result<()> {
+        encoder::write_chunk(w, self.chunk_type, &self.data)
+    }
+}
+
 /// PNG info struct
 #[derive(Clone, Debug)]
 #[non_exhaustive]
@@ -483,8 +942,21 @@ pub struct Info<'a> {
     /// The image's `tRNS` chunk, if present; contains the alpha channel of the image's palette, 1 byte per entry.
     pub trns: Option<Cow<'a, [u8]>>,
     pub pixel_dims: Option<PixelDimensions>,
+    /// The image's `sBIT` chunk, if present; the number of significant bits of each channel of
+    /// the original image data, 1 byte per channel.
+    ///
+    /// Grayscale images have 1 entry, truecolor and indexed images 3 (red, green, blue), and the
+    /// alpha variants of grayscale and truecolor 1 more for the alpha channel.
+    pub sbit: Option<Cow<'a, [u8]>>,
     /// The image's `PLTE` chunk, if present; contains the RGB channels (in that order) of the image's palettes, 3 bytes per entry (1 per channel).
     pub palette: Option<Cow<'a, [u8]>>,
+    /// The image's `bKGD` chunk, if present; the default background color to display the image on.
+    pub bkgd: Option<BackgroundColor>,
+    /// The image's `tIME` chunk, if present; the time of the last modification of the image.
+    ///
+    /// When decoding, a `tIME` chunk after the image data is only available after
+    /// [`Reader::finish`](crate::Reader::finish).
+    pub last_modified: Option<Time>,
     /// The contents of the image's gAMA chunk, if present.
     /// Prefer `source_gamma` to also get the derived replacement gamma from sRGB chunks.
     pub gama_chunk: Option<ScaledFloat>,
@@ -507,6 +979,31 @@ pub struct Info<'a> {
     pub srgb: Option<SrgbRenderingIntent>,
     /// The ICC profile for the image.
     pub icc_profile: Option<Cow<'a, [u8]>>,
+    /// The name of the ICC profile, stored in the `iCCP` chunk together with the profile.
+    ///
+    /// It must be between 1 and 79 printable Latin-1 characters, without leading, trailing or
+    /// consecutive spaces. A generic name is written if this is not set. The decoder leaves it
+    /// unset if the name in the image is invalid.
+    pub icc_profile_name: Option<String>,
+    /// The image's `cICP` chunk, if present; identifies the color space and transfer function.
+    pub coding_independent_code_points: Option<CodingIndependentCodePoints>,
+    /// The image's `eXIf` chunk, if present; Exif metadata as raw TIFF data, starting with the
+    /// `II` or `MM` byte order mark.
+    ///
+    /// When decoding, an `eXIf` chunk after the image data is only available after
+    /// [`Reader::finish`](crate::Reader::finish).
+    pub exif_metadata: Option<Cow<'a, [u8]>>,
+    /// The image's `mDCV` chunk, if present; describes the display the content was mastered on.
+    pub mastering_display_color_volume: Option<MasteringDisplayColorVolume>,
+    /// The image's `cLLI` chunk, if present; the light levels of HDR content.
+    pub content_light_level: Option<ContentLightLevelInfo>,
+    /// Ancillary chunks not interpreted by this crate, in the order they appeared.
+    ///
+    /// Only collected by the decoder when requested with
+    /// [`DecodeOptions::set_retain_unknown_chunks`](crate::DecodeOptions::set_retain_unknown_chunks).
+    /// When encoding, chunks that are safe to copy are written again at their position, others
+    /// are dropped since they may depend on image data that can't be verified to be unchanged.
+    pub unknown_chunks: Vec<UnknownChunk>,
     /// tEXt field
     pub uncompressed_latin1_text: Vec<TEXtChunk>,
     /// zTXt field
@@ -525,6 +1022,9 @@ impl Default for Info<'_> {
             interlaced: false,
             palette: None,
             trns: None,
+            sbit: None,
+            bkgd: None,
+            last_modified: None,
             gama_chunk: None,
             chrm_chunk: None,
             pixel_dims: None,
@@ -537,6 +1037,12 @@ impl Default for Info<'_> {
             source_chromaticities: None,
             srgb: None,
             icc_profile: None,
+            icc_profile_name: None,
+            coding_independent_code_points: None,
+            exif_metadata: None,
+            mastering_display_color_volume: None,
+            content_light_level: None,
+            unknown_chunks: Vec::new(),
             uncompressed_latin1_text: Vec::new(),
             compressed_latin1_text: Vec::new(),
             utf8_text: Vec::new(),
@@ -564,6 +1070,71 @@ impl Info<'_> {
         self.frame_control.is_some() && self.animation_control.is_some()
     }
 
+    /// Returns the Exif orientation of the image, if present in the `eXIf` chunk.
+    ///
+    /// The value is between 1 and 8, where 1 means the image is displayed as stored. The other
+    /// values combine a rotation by multiples of 90 degrees with an optional mirroring, as
+    /// defined by the TIFF Orientation tag.
+    pub fn exif_orientation(&self) -> Option<u16> {
+        let exif = self.exif_metadata.as_deref()?;
+        let byte_order = exif_byte_order(exif)?;
+        // Offsets come from the file, so they are checked for overflow.
+        let read_u16 = |offset: usize| -> Option<u16> {
+            let bytes = [*exif.get(offset)?, *exif.get(offset.checked_add(1)?)?];
+            Some(match byte_order {
+                ExifByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+                ExifByteOrder::BigEndian => u16::from_be_bytes(bytes),
+            })
+        };
+        let read_u32 = |offset: usize| -> Option<u32> {
+            let (high, low) = match byte_order {
+                ExifByteOrder::LittleEndian => {
+                    (read_u16(offset.checked_add(2)?)?, read_u16(offset)?)
+                }
+                ExifByteOrder::BigEndian => (read_u16(offset)?, read_u16(offset.checked_add(2)?)?),
+            };
+            Some(u32::from(high) << 16 | u32::from(low))
+        };
+
+        const ORIENTATION: u16 = 0x0112;
+        const SHORT: u16 = 3;
+
+        // Scan the entries of the first image file directory, 12 bytes each.
+        let ifd = usize::try_from(read_u32(4)?).ok()?;
+        let entries = read_u16(ifd)?;
+        let entry = (0..usize::from(entries))
+            .map_while(|i| ifd.checked_add(2)?.checked_add(i.checked_mul(12)?))
+            .find(|&entry| read_u16(entry) == Some(ORIENTATION))?;
+        if read_u16(entry.checked_add(2)?) != Some(SHORT)
+            || read_u32(entry.checked_add(4)?) != Some(1)
+        {
+            return None;
+        }
+        read_u16(entry.checked_add(8)?).filter(|orientation| (1..=8).contains(orientation))
+    }
+
+    /// Returns the color space of the image.
+    ///
+    /// An image may contain several of the chunks describing it. As required by the
+    /// specification, `cICP` takes precedence over `iCCP`, which takes precedence over `sRGB`,
+    /// which takes precedence over `gAMA` and `cHRM`.
+    pub fn color_space(&self) -> ColorSpace<'_> {
+        if let Some(cicp) = self.coding_independent_code_points {
+            ColorSpace::CodingIndependentCodePoints(cicp)
+        } else if let Some(profile) = &self.icc_profile {
+            ColorSpace::IccProfile(profile)
+        } else if let Some(intent) = self.srgb {
+            ColorSpace::Srgb(intent)
+        } else if self.source_gamma.is_some() || self.source_chromaticities.is_some() {
+            ColorSpace::GammaChromaticities {
+                gamma: self.source_gamma,
+                chromaticities: self.source_chromaticities,
+            }
+        } else {
+            ColorSpace::Unspecified
+        }
+    }
+
     /// Returns the frame control information of the image.
     pub fn animation_control(&self) -> Option<&AnimationControl> {
         self.animation_control.as_ref()
@@ -618,6 +1189,18 @@ impl Info<'_> {
             .raw_row_length_from_width(self.bit_depth, width)
     }
 
+    /// Checks that the contents of an `sBIT` chunk match the color type and bit depth.
+    ///
+    /// There must be one entry per channel (alpha of `tRNS` not included) and each must be
+    /// between 1 and the sample depth, which is 8 for the palette entries of indexed images.
+    pub(crate) fn is_valid_sbit(&self, sbit: &[u8]) -> bool {
+        let (channels, sample_depth) = match self.color_type {
+            ColorType::Indexed => (3, 8),
+            color_type => (color_type.samples(), self.bit_depth as u8),
+        };
+        sbit.len() == channels && sbit.iter().all(|&bits| (1..=sample_depth).contains(&bits))
+    }
+
     /// Encode this header to the writer.
     ///
     /// Note that this does _not_ include the PNG signature, it starts with the IHDR chunk and then
@@ -643,6 +1226,29 @@ impl Info<'_> {
             encoder::write_chunk(&mut w, chunk::pHYs, &phys_data)?;
         }
 
+        if let Some(sbit) = &self.sbit {
+            encoder::write_chunk(&mut w, chunk::sBIT, sbit)?;
+        }
+
+        if let Some(profile) = &self.icc_profile {
+            let name = self.icc_profile_name.as_deref().unwrap_or("ICC profile");
+            encoder::write_iccp_chunk(&mut w, name, profile, self.compression)?;
+        }
+
+        if let Some(cicp) = self.coding_independent_code_points {
+            cicp.encode(&mut w)?;
+        }
+
+        if let Some(mdcv) = self.mastering_display_color_volume {
+            mdcv.encode(&mut w)?;
+        }
+
+        if let Some(clli) = self.content_light_level {
+            clli.encode(&mut w)?;
+        }
+
+        self.encode_unknown_chunks(&mut w, ChunkPosition::BeforePlte)?;
+
         if let Some(p) = &self.palette {
             encoder::write_chunk(&mut w, chunk::PLTE, p)?;
         };
@@ -651,6 +1257,10 @@ impl Info<'_> {
             encoder::write_chunk(&mut w, chunk::tRNS, t)?;
         }
 
+        if let Some(bkgd) = self.bkgd {
+            bkgd.encode(&mut w)?;
+        }
+
         // If specified, the sRGB information overrides the source gamma and chromaticities.
         if let Some(srgb) = &self.srgb {
             let gamma = crate::srgb::substitute_gamma();
@@ -670,6 +1280,14 @@ impl Info<'_> {
             actl.encode(&mut w)?;
         }
 
+        if let Some(time) = self.last_modified {
+            time.encode(&mut w)?;
+        }
+
+        if let Some(exif) = &self.exif_metadata {
+            encoder::write_chunk(&mut w, chunk::eXIf, exif)?;
+        }
+
         for text_chunk in &self.uncompressed_latin1_text {
             text_chunk.encode(&mut w)?;
         }
@@ -682,6 +1300,22 @@ impl Info<'_> {
             text_chunk.encode(&mut w)?;
         }
 
+        self.encode_unknown_chunks(&mut w, ChunkPosition::BeforeIdat)?;
+
+        Ok(())
+    }
+
+    /// Writes the unknown chunks at `position` that are safe to copy.
+    pub(crate) fn encode_unknown_chunks<W: Write>(
+        &self,
+        w: &mut W,
+        position: ChunkPosition,
+    ) -> encoder::Result<()> {
+        for unknown in &self.unknown_chunks {
+            if unknown.position == position && chunk::safe_to_copy(unknown.chunk_type) {
+                unknown.encode(w)?;
+            }
+        }
         Ok(())
     }
 }
@@ -711,33 +1345,13 @@ bitflags::bitflags! {
     ///
     #[doc = "
     ```c
-    /// Discard the alpha channel
-    const STRIP_ALPHA         = 0x0002; // read only
-    /// Expand 1; 2 and 4-bit samples to bytes
-    const PACKING             = 0x0004; // read and write
     /// Change order of packed pixels to LSB first
     const PACKSWAP            = 0x0008; // read and write
     /// Invert monochrome images
     const INVERT_MONO         = 0x0020; // read and write
-    /// Normalize pixels to the sBIT depth
-    const SHIFT               = 0x0040; // read and write
-    /// Flip RGB to BGR; RGBA to BGRA
-    const BGR                 = 0x0080; // read and write
-    /// Flip RGBA to ARGB or GA to AG
-    const SWAP_ALPHA          = 0x0100; // read and write
-    /// Byte-swap 16-bit samples
-    const SWAP_ENDIAN         = 0x0200; // read and write
-    /// Change alpha from opacity to transparency
-    const INVERT_ALPHA        = 0x0400; // read and write
     const STRIP_FILLER        = 0x0800; // write only
     const STRIP_FILLER_BEFORE = 0x0800; // write only
     const STRIP_FILLER_AFTER  = 0x1000; // write only
-    const GRAY_TO_RGB         = 0x2000; // read only
-    const EXPAND_16           = 0x4000; // read only
-    /// Similar to STRIP_16 but in libpng considering gamma?
-    /// Not entirely sure the documentation says it is more
-    /// accurate but doesn't say precisely how.
-    const SCALE_16            = 0x8000; // read only
     ```
     "]
     pub struct Transformations: u32 {
@@ -745,12 +1359,47 @@ bitflags::bitflags! {
         const IDENTITY            = 0x00000; // read and write */
         /// Strip 16-bit samples to 8 bits
         const STRIP_16            = 0x00001; // read only */
+        /// Discard the alpha channel, including one created from `tRNS` by `EXPAND`. A background
+        /// set with `Decoder::set_background` is composited instead where it applies.
+        const STRIP_ALPHA         = 0x00002; // read only */
+        /// Unpack 1, 2 and 4-bit palette indices and grayscale samples to one byte each, keeping
+        /// their values. Has no effect if the samples are expanded to 8 bits by `EXPAND`,
+        /// `ALPHA` or `GRAY_TO_RGB`.
+        const PACKING             = 0x00004; // read only */
         /// Expand paletted images to RGB; expand grayscale images of
         /// less than 8-bit depth to 8-bit depth; and expand tRNS chunks
         /// to alpha channels.
         const EXPAND              = 0x00010; // read only */
+        /// Shift samples right to the number of significant bits given by the `sBIT` chunk,
+        /// undoing the scaling of the original samples to the bit depth of the image.
+        /// Has no effect on images without an `sBIT` chunk and on palette indices.
+        const SHIFT               = 0x00040; // read only */
+        /// Flip RGB to BGR and RGBA to BGRA.
+        const BGR                 = 0x00080; // read and write */
+        /// Move alpha in front of the color samples, RGBA to ARGB and GA to AG.
+        const SWAP_ALPHA          = 0x00100; // read and write */
+        /// Byte-swap 16-bit samples, which are then little endian.
+        const SWAP_ENDIAN         = 0x00200; // read and write */
+        /// Change alpha from opacity to transparency.
+        const INVERT_ALPHA        = 0x00400; // read and write */
+        /// Expand grayscale images to RGB and grayscale images with alpha to RGBA. Grayscale
+        /// images of less than 8-bit depth are expanded to 8-bit depth first.
+        const GRAY_TO_RGB         = 0x02000; // read only */
+        /// Expand all samples to 16 bits, replicating the bits of samples with fewer bits.
+        /// Implies `EXPAND` and takes precedence over `STRIP_16`.
+        const EXPAND_16           = 0x04000; // read only */
+        /// Scale 16-bit samples to 8 bits, rounding to the nearest value. Use this instead of
+        /// `STRIP_16`, which truncates and biases the samples downwards. Takes precedence over
+        /// `STRIP_16`.
+        const SCALE_16            = 0x08000; // read only */
         /// Expand paletted images to include an alpha channel. Implies `EXPAND`.
         const ALPHA               = 0x10000; // read only */
+        /// Multiply the color samples with alpha, rounding to the nearest value. For writing, the
+        /// samples are premultiplied and divided by alpha before encoding.
+        const PREMULTIPLY_ALPHA   = 0x20000; // read and write */
+        /// Convert RGB to grayscale and RGBA to grayscale with alpha, weighting the samples with
+        /// the coefficients set by `Decoder::set_gray_coefficients`. Implies `EXPAND`.
+        const RGB_TO_GRAY         = 0x40000; // read only */
     }
 }
 
@@ -791,6 +1440,19 @@ pub(crate) enum ParameterErrorKind {
     /// library will perform the checks necessary to ensure that data was accurate or error with a
     /// format error otherwise.
     PolledAfterEndOfImage,
+    /// A method requires output with a different bit depth than the transformations produce.
+    OutputBitDepth {
+        expected: BitDepth,
+        actual: BitDepth,
+    },
+    /// The requested output format can not be produced from the image.
+    OutputFormat {
+        color_type: ColorType,
+        bit_depth: BitDepth,
+    },
+    /// The transparency of animation frames from the `tRNS` chunk can not be blended in the
+    /// format the frames are composited in.
+    CompositedTransparency,
 }
 
 impl From<ParameterErrorKind> for ParameterError {
@@ -807,6 +1469,23 @@ impl fmt::Display for ParameterError {
                 write!(fmt, "wrong data size, expected {} got {}", expected, actual)
             }
             PolledAfterEndOfImage => write!(fmt, "End of image has been reached"),
+            OutputBitDepth { expected, actual } => write!(
+                fmt,
+                "wrong output bit depth, expected {} got {}",
+                expected as u8, actual as u8
+            ),
+            OutputFormat {
+                color_type,
+                bit_depth,
+            } => write!(
+                fmt,
+                "can not decode to {:?} with {} bits per sample",
+                color_type, bit_depth as u8
+            ),
+            CompositedTransparency => write!(
+                fmt,
+                "transparency from the tRNS chunk can only be composited with EXPAND"
+            ),
         }
     }
 }
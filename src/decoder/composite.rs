@@ -0,0 +1,685 @@
+//! Compositing of APNG frames onto a full canvas.
+//!
+//! The canvas uses the color type and bit depth of the expanded samples, before the remaining
+//! transformations of the `Reader` are applied to it. Frames are blended with `BlendOp::Over`
+//! according to their alpha channel. For frames without an alpha channel the `tRNS` chunk is
+//! used instead, a pixel is either kept or replaced as a whole.
+
+use crate::common::{BitDepth, BlendOp, ColorType, DisposeOp, FrameControl, Info};
+
+/// Describes which pixels of a frame let the canvas show through when blending.
+enum Transparency {
+    /// All pixels are opaque.
+    Opaque,
+    /// The last sample of each pixel is its alpha.
+    Alpha,
+    /// Pixels with exactly these bytes are fully transparent, from the `tRNS` of a grayscale or
+    /// RGB image with at least 8 bits per sample.
+    Key(Vec<u8>),
+    /// Packed grayscale pixels with this value are fully transparent.
+    PackedKey(u8),
+    /// Palette indices that are fully transparent.
+    Palette(Vec<bool>),
+}
+
+/// Full-canvas state for `Reader::next_composited_frame`.
+pub(crate) struct Compositor {
+    canvas: Vec<u8>,
+    /// Copy of the canvas from before the last frame was drawn, for `DisposeOp::Previous`.
+    previous: Vec<u8>,
+    /// Buffer the next frame is decoded into, the size of the canvas.
+    frame: Vec<u8>,
+    /// The last frame drawn, its region is disposed of before drawing the next one.
+    dispose: Option<FrameControl>,
+    width: u32,
+    line_size: usize,
+    bits_pp: usize,
+    bit_depth: BitDepth,
+    transparency: Transparency,
+}
+
+impl Compositor {
+    /// Creates an empty, fully transparent canvas for frames of the given format.
+    pub(crate) fn new(info: &Info, color_type: ColorType, bit_depth: BitDepth) -> Self {
+        let line_size = color_type.raw_row_length_from_width(bit_depth, info.width) - 1;
+        let transparency = match color_type {
+            ColorType::GrayscaleAlpha | ColorType::Rgba => Transparency::Alpha,
+            // Transformations may have changed the samples the tRNS values refer to.
+            _ if (color_type, bit_depth) != (info.color_type, info.bit_depth) => {
+                Transparency::Opaque
+            }
+            ColorType::Indexed => match info.trns.as_deref() {
+                Some(trns) => {
+                    let mut transparent = vec![false; 256];
+                    for (entry, &alpha) in transparent.iter_mut().zip(trns) {
+                        *entry = alpha == 0;
+                    }
+                    Transparency::Palette(transparent)
+                }
+                None => Transparency::Opaque,
+            },
+            // The decoder already reduced the values to the sample size below 16 bits.
+            _ => match info.trns.as_deref() {
+                Some(trns) if (bit_depth as u8) < 8 => Transparency::PackedKey(trns[0]),
+                Some(trns) => Transparency::Key(trns.to_vec()),
+                None => Transparency::Opaque,
+            },
+        };
+
+        let size = line_size * info.height as usize;
+        Compositor {
+            canvas: vec![0; size],
+            previous: Vec::new(),
+            frame: vec![0; size],
+            dispose: None,
+            width: info.width,
+            line_size,
+            bits_pp: color_type.samples() * bit_depth as usize,
+            bit_depth,
+            transparency,
+        }
+    }
+
+    /// The number of bytes needed to hold the canvas, the previous canvas for disposal and the
+    /// frame buffer.
+    pub(crate) fn buffer_size(&self) -> usize {
+        3 * self.canvas.len()
+    }
+
+    /// Takes the buffer to decode the next frame into, to be returned with `put_frame`.
+    pub(crate) fn take_frame(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.frame)
+    }
+
+    /// Returns the frame buffer to be reused for the next frame.
+    pub(crate) fn put_frame(&mut self, frame: Vec<u8>) {
+        self.frame = frame;
+    }
+
+    /// Returns the rows of the canvas.
+    pub(crate) fn canvas_rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.canvas.chunks_exact(self.line_size)
+    }
+
+    /// Draws a frame onto the canvas after disposing of the region of the previous frame.
+    ///
+    /// Without frame control the frame covers the whole canvas and is never disposed of.
+    pub(crate) fn composite(&mut self, frame: &[u8], fctl: Option<&FrameControl>) {
+        if let Some(last) = self.dispose.take() {
+            match last.dispose_op {
+                DisposeOp::None => {}
+                DisposeOp::Background => self.clear(&last),
+                DisposeOp::Previous => self.restore(&last),
+            }
+        }
+
+        let fctl = match fctl {
+            Some(fctl) => *fctl,
+            None => FrameControl {
+                width: self.width,
+                height: (self.canvas.len() / self.line_size) as u32,
+                ..FrameControl::default()
+            },
+        };
+
+        if fctl.dispose_op == DisposeOp::Previous {
+            // Before the first frame this is the transparent canvas, which makes this behave as
+            // `DisposeOp::Background` as required by the specification.
+            self.previous.clear();
+            self.previous.extend_from_slice(&self.canvas);
+        }
+
+        let frame_line_size = (fctl.width as usize * self.bits_pp + 7) / 8;
+        for (y, src) in frame
+            .chunks_exact(frame_line_size)
+            .take(fctl.height as usize)
+            .enumerate()
+        {
+            let start = (fctl.y_offset as usize + y) * self.line_size;
+            let dst = &mut self.canvas[start..][..self.line_size];
+            match fctl.blend_op {
+                BlendOp::Source => copy_region(dst, src, 0, &fctl, self.bits_pp),
+                BlendOp::Over => blend_over(
+                    dst,
+                    src,
+                    &fctl,
+                    self.bits_pp,
+                    self.bit_depth,
+                    &self.transparency,
+                ),
+            }
+        }
+
+        self.dispose = Some(fctl);
+    }
+
+    /// Resets the region of a frame to transparent black.
+    fn clear(&mut self, fctl: &FrameControl) {
+        let blank = vec![0; (fctl.width as usize * self.bits_pp + 7) / 8];
+        for y in 0..fctl.height as usize {
+            let start = (fctl.y_offset as usize + y) * self.line_size;
+            let dst = &mut self.canvas[start..][..self.line_size];
+            copy_region(dst, &blank, 0, fctl, self.bits_pp);
+        }
+    }
+
+    /// Restores the region of a frame from the canvas before it was drawn.
+    fn restore(&mut self, fctl: &FrameControl) {
+        for y in 0..fctl.height as usize {
+            let start = (fctl.y_offset as usize + y) * self.line_size;
+            let dst = &mut self.canvas[start..][..self.line_size];
+            let src = &self.previous[start..][..self.line_size];
+            copy_region(dst, src, fctl.x_offset as usize, fctl, self.bits_pp);
+        }
+    }
+}
+
+/// Copies the pixels of `fctl.width` starting at `src_x` into the frame region of a canvas row.
+fn copy_region(dst: &mut [u8], src: &[u8], src_x: usize, fctl: &FrameControl, bits_pp: usize) {
+    let x = fctl.x_offset as usize;
+    let width = fctl.width as usize;
+    if bits_pp >= 8 {
+        let bytes_pp = bits_pp / 8;
+        dst[x * bytes_pp..][..width * bytes_pp]
+            .copy_from_slice(&src[src_x * bytes_pp..][..width * bytes_pp]);
+    } else {
+        for i in 0..width {
+            set_packed(dst, x + i, bits_pp, get_packed(src, src_x + i, bits_pp));
+        }
+    }
+}
+
+/// Blends a row of a frame over the frame region of a canvas row.
+fn blend_over(
+    dst: &mut [u8],
+    src: &[u8],
+    fctl: &FrameControl,
+    bits_pp: usize,
+    bit_depth: BitDepth,
+    transparency: &Transparency,
+) {
+    let x = fctl.x_offset as usize;
+    let width = fctl.width as usize;
+
+    if bits_pp < 8 {
+        for i in 0..width {
+            let value = get_packed(src, i, bits_pp);
+            let transparent = match transparency {
+                Transparency::PackedKey(key) => value == *key,
+                Transparency::Palette(transparent) => transparent[usize::from(value)],
+                _ => false,
+            };
+            if !transparent {
+                set_packed(dst, x + i, bits_pp, value);
+            }
+        }
+        return;
+    }
+
+    let bytes_pp = bits_pp / 8;
+    let dst = dst[x * bytes_pp..][..width * bytes_pp].chunks_exact_mut(bytes_pp);
+    let src = src[..width * bytes_pp].chunks_exact(bytes_pp);
+    match transparency {
+        Transparency::Opaque | Transparency::PackedKey(_) => {
+            dst.zip(src).for_each(|(d, s)| d.copy_from_slice(s))
+        }
+        Transparency::Key(key) => dst
+            .zip(src)
+            .filter(|(_, s)| s != key)
+            .for_each(|(d, s)| d.copy_from_slice(s)),
+        Transparency::Palette(transparent) => dst
+            .zip(src)
+            .filter(|(_, s)| !transparent[usize::from(s[0])])
+            .for_each(|(d, s)| d.copy_from_slice(s)),
+        Transparency::Alpha if bit_depth == BitDepth::Sixteen => {
+            let samples = bytes_pp / 2;
+            for (d, s) in dst.zip(src) {
+                let (mut dst_px, mut src_px) = ([0; 4], [0; 4]);
+                for i in 0..samples {
+                    dst_px[i] = u64::from(u16::from_be_bytes([d[2 * i], d[2 * i + 1]]));
+                    src_px[i] = u64::from(u16::from_be_bytes([s[2 * i], s[2 * i + 1]]));
+                }
+                blend_pixel(&mut dst_px[..samples], &src_px[..samples], 0xffff);
+                for (v, out) in dst_px.iter().zip(d.chunks_exact_mut(2)) {
+                    out.copy_from_slice(&(*v as u16).to_be_bytes());
+                }
+            }
+        }
+        Transparency::Alpha => {
+            for (d, s) in dst.zip(src) {
+                let (mut dst_px, mut src_px) = ([0; 4], [0; 4]);
+                for i in 0..bytes_pp {
+                    dst_px[i] = u64::from(d[i]);
+                    src_px[i] = u64::from(s[i]);
+                }
+                blend_pixel(&mut dst_px[..bytes_pp], &src_px[..bytes_pp], 0xff);
+                for (v, out) in dst_px.iter().zip(d.iter_mut()) {
+                    *out = *v as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Alpha composites the non-premultiplied pixel `src` over `dst`, alpha being the last sample.
+fn blend_pixel(dst: &mut [u64], src: &[u64], max: u64) {
+    let alpha = src.len() - 1;
+    let (src_alpha, dst_alpha) = (src[alpha], dst[alpha]);
+    if src_alpha == max || dst_alpha == 0 {
+        dst.copy_from_slice(src);
+        return;
+    } else if src_alpha == 0 {
+        return;
+    }
+
+    // Both alphas scaled by `max`.
+    let dst_weight = dst_alpha * (max - src_alpha);
+    let out_alpha = src_alpha * max + dst_weight;
+    for (d, s) in dst[..alpha].iter_mut().zip(&src[..alpha]) {
+        *d = (*s * src_alpha * max + *d * dst_weight + out_alpha / 2) / out_alpha;
+    }
+    dst[alpha] = (out_alpha + max / 2) / max;
+}
+
+fn get_packed(row: &[u8], x: usize, bits_pp: usize) -> u8 {
+    let bit = x * bits_pp;
+    let mask = (1u8 << bits_pp) - 1;
+    (row[bit / 8] >> (8 - bit % 8 - bits_pp)) & mask
+}
+
+fn set_packed(row: &mut [u8], x: usize, bits_pp: usize, value: u8) {
+    let bit = x * bits_pp;
+    let shift = 8 - bit % 8 - bits_pp;
+    let mask = ((1u8 << bits_pp) - 1) << shift;
+    row[bit / 8] = (row[bit / 8] & !mask) | (value << shift);
+}
+
+/// Returns whether the `tRNS` transparency of frames decoded to this format can be blended
+/// exactly, which needs an alpha channel for partially transparent palette entries and the
+/// samples of the image to compare the transparent color against.
+pub(crate) fn blends_trns(info: &Info, color_type: ColorType, bit_depth: BitDepth) -> bool {
+    match info.trns.as_deref() {
+        None => true,
+        Some(_) if matches!(color_type, ColorType::GrayscaleAlpha | ColorType::Rgba) => true,
+        Some(_) if (color_type, bit_depth) != (info.color_type, info.bit_depth) => false,
+        Some(trns) if color_type == ColorType::Indexed => {
+            trns.iter().all(|&alpha| alpha == 0 || alpha == 255)
+        }
+        Some(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Background, BitDepth, BlendOp, ColorType, Decoder, DecodingError, DisposeOp, Encoder,
+        Transformations,
+    };
+
+    struct Frame {
+        rect: (u32, u32, u32, u32),
+        dispose_op: DisposeOp,
+        blend_op: BlendOp,
+        data: Vec<u8>,
+    }
+
+    impl Frame {
+        fn new(rect: (u32, u32, u32, u32), data: &[u8]) -> Self {
+            Frame {
+                rect,
+                dispose_op: DisposeOp::None,
+                blend_op: BlendOp::Source,
+                data: data.to_vec(),
+            }
+        }
+
+        fn dispose(mut self, dispose_op: DisposeOp) -> Self {
+            self.dispose_op = dispose_op;
+            self
+        }
+
+        fn blend(mut self, blend_op: BlendOp) -> Self {
+            self.blend_op = blend_op;
+            self
+        }
+    }
+
+    /// Encodes an animation on a `width`×`height` canvas, optionally with a separate default image.
+    fn encode(
+        (width, height): (u32, u32),
+        (color_type, bit_depth): (ColorType, BitDepth),
+        configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>),
+        default_image: Option<&[u8]>,
+        frames: &[Frame],
+    ) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        encoder.set_animated(frames.len() as u32, 0).unwrap();
+        encoder.set_sep_def_img(default_image.is_some()).unwrap();
+        configure(&mut encoder);
+
+        let mut writer = encoder.write_header().unwrap();
+        if let Some(data) = default_image {
+            writer.write_image_data(data).unwrap();
+        }
+        for frame in frames {
+            let (x, y, w, h) = frame.rect;
+            writer.reset_frame_position().unwrap();
+            writer.set_frame_dimension(w, h).unwrap();
+            writer.set_frame_position(x, y).unwrap();
+            writer.set_frame_delay(1, 10).unwrap();
+            writer.set_dispose_op(frame.dispose_op).unwrap();
+            writer.set_blend_op(frame.blend_op).unwrap();
+            writer.write_image_data(&frame.data).unwrap();
+        }
+        writer.finish().unwrap();
+        png
+    }
+
+    fn composited(png: &[u8], transformations: Transformations) -> Vec<Vec<u8>> {
+        composited_with(png, |decoder| decoder.set_transformations(transformations))
+    }
+
+    fn composited_with(png: &[u8], configure: impl FnOnce(&mut Decoder<&[u8]>)) -> Vec<Vec<u8>> {
+        let mut decoder = Decoder::new(png);
+        configure(&mut decoder);
+        let mut reader = decoder.read_info().unwrap();
+        let frames = reader.info().animation_control().unwrap().num_frames;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        (0..frames)
+            .map(|_| {
+                let info = reader.next_composited_frame(&mut buf).unwrap();
+                assert_eq!(info.output.buffer_size(), buf.len());
+                assert_eq!(info.delay(), std::time::Duration::from_millis(100));
+                buf.clone()
+            })
+            .collect()
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const NONE: [u8; 4] = [0, 0, 0, 0];
+
+    #[test]
+    fn dispose_background_and_blend_over() {
+        let png = encode(
+            (2, 1),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            None,
+            &[
+                Frame::new((0, 0, 2, 1), &[RED, RED].concat()),
+                Frame::new((1, 0, 1, 1), &[0, 0, 255, 128])
+                    .blend(BlendOp::Over)
+                    .dispose(DisposeOp::Background),
+                Frame::new((0, 0, 1, 1), &BLUE),
+            ],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[0], [RED, RED].concat());
+        assert_eq!(frames[1], [RED, [127, 0, 128, 255]].concat());
+        assert_eq!(frames[2], [BLUE, NONE].concat());
+    }
+
+    #[test]
+    fn blend_over_with_swizzled_output() {
+        let png = encode(
+            (1, 1),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            None,
+            &[
+                Frame::new((0, 0, 1, 1), &RED),
+                Frame::new((0, 0, 1, 1), &[0, 0, 255, 128]).blend(BlendOp::Over),
+            ],
+        );
+
+        // Blended as RGBA, then reordered to ABGR with alpha as transparency.
+        let frames = composited(
+            &png,
+            Transformations::BGR | Transformations::SWAP_ALPHA | Transformations::INVERT_ALPHA,
+        );
+        assert_eq!(frames[0], [0, 0, 0, 255]);
+        assert_eq!(frames[1], [0, 128, 0, 127]);
+    }
+
+    #[test]
+    fn blend_over_with_premultiplied_output() {
+        let png = encode(
+            (1, 1),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            None,
+            &[
+                Frame::new((0, 0, 1, 1), &[0, 0, 255, 128]),
+                Frame::new((0, 0, 1, 1), &[255, 0, 0, 128]).blend(BlendOp::Over),
+                // Premultiplying this before blending would lose the colors.
+                Frame::new((0, 0, 1, 1), &[90, 30, 200, 3]),
+                Frame::new((0, 0, 1, 1), &[10, 250, 60, 2]).blend(BlendOp::Over),
+            ],
+        );
+
+        // Blended with straight alpha, then premultiplied.
+        let straight = composited(&png, Transformations::IDENTITY);
+        let premultiplied = composited(&png, Transformations::PREMULTIPLY_ALPHA);
+        assert_eq!(premultiplied[0], [0, 0, 128, 128]);
+        for (straight, premultiplied) in straight.iter().zip(&premultiplied) {
+            let alpha = u32::from(straight[3]);
+            let expected: Vec<u8> = straight[..3]
+                .iter()
+                .map(|&c| ((u32::from(c) * alpha + 127) / 255) as u8)
+                .chain([straight[3]])
+                .collect();
+            assert_eq!(*premultiplied, expected);
+        }
+    }
+
+    #[test]
+    fn blend_over_before_removing_alpha() {
+        let png = encode(
+            (1, 1),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            None,
+            &[
+                Frame::new((0, 0, 1, 1), &RED),
+                Frame::new((0, 0, 1, 1), &NONE).blend(BlendOp::Over),
+            ],
+        );
+
+        // The transparent frame leaves the canvas unchanged, whichever way alpha is removed.
+        let white = Background::Color {
+            red: 0xFFFF,
+            green: 0xFFFF,
+            blue: 0xFFFF,
+        };
+        let frames = composited_with(&png, |decoder| decoder.set_background(Some(white), false));
+        assert_eq!(frames, [[255, 0, 0], [255, 0, 0]]);
+        let frames = composited(&png, Transformations::STRIP_ALPHA);
+        assert_eq!(frames, [[255, 0, 0], [255, 0, 0]]);
+        let frames = composited_with(&png, |decoder| {
+            decoder.set_output_format(ColorType::Grayscale, BitDepth::Eight)
+        });
+        assert_eq!(frames[0], frames[1]);
+    }
+
+    #[test]
+    fn dispose_previous() {
+        let png = encode(
+            (2, 2),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            None,
+            &[
+                // Treated as background on the first frame.
+                Frame::new((0, 0, 1, 1), &RED).dispose(DisposeOp::Previous),
+                Frame::new((1, 1, 1, 1), &BLUE),
+                Frame::new((0, 0, 2, 2), &[RED, RED, RED, RED].concat())
+                    .dispose(DisposeOp::Previous),
+                Frame::new((1, 0, 1, 1), &BLUE).blend(BlendOp::Over),
+            ],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[0], [RED, NONE, NONE, NONE].concat());
+        assert_eq!(frames[1], [NONE, NONE, NONE, BLUE].concat());
+        assert_eq!(frames[2], [RED, RED, RED, RED].concat());
+        assert_eq!(frames[3], [NONE, BLUE, NONE, BLUE].concat());
+    }
+
+    #[test]
+    fn skips_separate_default_image() {
+        let png = encode(
+            (1, 1),
+            (ColorType::Rgba, BitDepth::Eight),
+            |_| {},
+            Some(&BLUE),
+            &[Frame::new((0, 0, 1, 1), &RED)],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames, [RED.to_vec()]);
+    }
+
+    #[test]
+    fn blend_over_sixteen_bit() {
+        let png = encode(
+            (1, 1),
+            (ColorType::GrayscaleAlpha, BitDepth::Sixteen),
+            |_| {},
+            None,
+            &[
+                Frame::new((0, 0, 1, 1), &[0xff, 0xff, 0x80, 0x00]),
+                Frame::new((0, 0, 1, 1), &[0x00, 0x00, 0x80, 0x00]).blend(BlendOp::Over),
+            ],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[1], [0x55, 0x55, 0xc0, 0x00]);
+    }
+
+    #[test]
+    fn packed_grayscale_with_trns() {
+        // Frames at odd offsets within a byte, 0b01 is transparent.
+        let png = encode(
+            (5, 1),
+            (ColorType::Grayscale, BitDepth::Two),
+            |encoder| encoder.set_trns(vec![0, 1]),
+            None,
+            &[
+                Frame::new((0, 0, 5, 1), &[0b1111_1111, 0b1100_0000]),
+                Frame::new((1, 0, 3, 1), &[0b0001_1000]).blend(BlendOp::Over),
+                Frame::new((3, 0, 2, 1), &[0b1000_0000]).dispose(DisposeOp::Background),
+                Frame::new((0, 0, 1, 1), &[0b0000_0000]),
+            ],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[0], [0b1111_1111, 0b1100_0000]);
+        assert_eq!(frames[1], [0b1100_1110, 0b1100_0000]);
+        assert_eq!(frames[2], [0b1100_1110, 0b0000_0000]);
+        assert_eq!(frames[3], [0b0000_1100, 0b0000_0000]);
+    }
+
+    #[test]
+    fn rgb_with_trns() {
+        let png = encode(
+            (2, 1),
+            (ColorType::Rgb, BitDepth::Eight),
+            |encoder| encoder.set_trns(vec![0, 1, 0, 2, 0, 3]),
+            None,
+            &[
+                Frame::new((0, 0, 2, 1), &[9; 6]),
+                Frame::new((0, 0, 2, 1), &[1, 2, 3, 1, 2, 4]).blend(BlendOp::Over),
+            ],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[1], [9, 9, 9, 1, 2, 4]);
+    }
+
+    #[test]
+    fn indexed_with_trns() {
+        let encode_with_trns = |trns: &[u8]| {
+            encode(
+                (3, 1),
+                (ColorType::Indexed, BitDepth::Eight),
+                |encoder| {
+                    encoder.set_palette(vec![0, 0, 0, 255, 0, 0, 0, 0, 255]);
+                    encoder.set_trns(trns.to_vec());
+                },
+                None,
+                &[
+                    Frame::new((0, 0, 3, 1), &[1, 1, 1]),
+                    Frame::new((0, 0, 3, 1), &[0, 2, 0]).blend(BlendOp::Over),
+                ],
+            )
+        };
+
+        let png = encode_with_trns(&[0, 255, 255]);
+        let frames = composited(&png, Transformations::IDENTITY);
+        assert_eq!(frames[1], [1, 2, 1]);
+
+        // Expanded to RGBA the partially transparent entry is blended.
+        let png = encode_with_trns(&[0, 255, 128]);
+        let frames = composited(&png, Transformations::EXPAND);
+        assert_eq!(frames[1], [RED, [127, 0, 128, 255], RED].concat());
+
+        // The palette indices can't represent the blended colors.
+        let mut reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        assert!(matches!(
+            reader.next_composited_frame(&mut buf),
+            Err(DecodingError::Parameter(_))
+        ));
+    }
+
+    #[test]
+    fn transformed_samples_with_trns() {
+        let png = encode(
+            (2, 1),
+            (ColorType::Grayscale, BitDepth::Sixteen),
+            |encoder| encoder.set_trns(vec![0x12, 0x34]),
+            None,
+            &[
+                Frame::new((0, 0, 2, 1), &[0, 0, 0, 0]),
+                Frame::new((0, 0, 2, 1), &[0x12, 0x34, 0x12, 0x00]).blend(BlendOp::Over),
+            ],
+        );
+
+        // After stripping, the transparent color can't be told apart from the other samples.
+        let mut decoder = Decoder::new(png.as_slice());
+        decoder.set_transformations(Transformations::STRIP_16);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        assert!(matches!(
+            reader.next_composited_frame(&mut buf),
+            Err(DecodingError::Parameter(_))
+        ));
+
+        let frames = composited(&png, Transformations::EXPAND | Transformations::STRIP_16);
+        assert_eq!(frames[1], [0, 255, 0x12, 255]);
+    }
+
+    #[test]
+    fn interlaced_subframes() {
+        let data: Vec<u8> = (0..3 * 5 * 3).collect();
+        let png = encode(
+            (4, 6),
+            (ColorType::Rgb, BitDepth::Eight),
+            |encoder| encoder.set_interlaced(true),
+            None,
+            &[Frame::new((1, 1, 3, 5), &data)],
+        );
+
+        let frames = composited(&png, Transformations::IDENTITY);
+        let mut expected = vec![0; 4 * 6 * 3];
+        for (row, line) in data.chunks(9).enumerate() {
+            expected[(row + 1) * 12 + 3..][..9].copy_from_slice(line);
+        }
+        assert_eq!(frames[0], expected);
+    }
+}
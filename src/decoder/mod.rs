@@ -1,19 +1,25 @@
+mod composite;
 mod stream;
 pub(crate) mod transform;
 mod zlib;
 
+use self::composite::{blends_trns, Compositor};
 pub use self::stream::{DecodeOptions, Decoded, DecodingError, StreamingDecoder};
 use self::stream::{FormatErrorInner, CHUNK_BUFFER_SIZE};
-use self::transform::{create_transform_fn, TransformFn};
+use self::transform::{
+    create_transform_fn, RowTransformFn, ScratchRows, TransformSettings, TransformStage,
+};
 
 use std::io::{BufRead, BufReader, Read};
 use std::mem;
 use std::ops::Range;
+use std::time::Duration;
 
 use crate::adam7;
 use crate::chunk;
 use crate::common::{
-    BitDepth, BytesPerPixel, ColorType, Info, ParameterErrorKind, Transformations,
+    Background, BitDepth, BytesPerPixel, ColorType, GrayCoefficients, Info, ParameterErrorKind,
+    Transformations,
 };
 use crate::filter::{unfilter, FilterType};
 
@@ -54,6 +60,31 @@ impl OutputInfo {
     }
 }
 
+/// Information about a frame composited onto the full canvas.
+///
+/// This is returned by [`Reader::next_composited_frame`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompositedFrameInfo {
+    /// The canvas that was written into the output buffer, which always has the image size.
+    pub output: OutputInfo,
+    /// Frame delay fraction numerator, zero for images that are not animated.
+    pub delay_num: u16,
+    /// Frame delay fraction denominator, a value of 0 is to be treated as 100.
+    pub delay_den: u16,
+}
+
+impl CompositedFrameInfo {
+    /// Returns how long the frame is to be displayed.
+    pub fn delay(&self) -> Duration {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        Duration::from_secs_f64(f64::from(self.delay_num) / f64::from(den))
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 /// Limits on the resources the `Decoder` is allowed too use
 pub struct Limits {
@@ -85,6 +116,8 @@ pub struct Decoder<R: Read> {
     read_decoder: ReadDecoder<R>,
     /// Output transformations
     transform: Transformations,
+    /// Parameters of the output transformations
+    transform_settings: TransformSettings,
 }
 
 /// A row of data with interlace information attached.
@@ -153,6 +186,7 @@ impl<R: Read> Decoder<R> {
                 at_eof: false,
             },
             transform: Transformations::IDENTITY,
+            transform_settings: TransformSettings::default(),
         }
     }
 
@@ -168,6 +202,7 @@ impl<R: Read> Decoder<R> {
                 at_eof: false,
             },
             transform: Transformations::IDENTITY,
+            transform_settings: TransformSettings::default(),
         }
     }
 
@@ -229,10 +264,16 @@ impl<R: Read> Decoder<R> {
             prev_start: 0,
             current_start: 0,
             transform: self.transform,
+            transform_settings: self.transform_settings,
             transform_fn: None,
+            scratch_rows: ScratchRows::default(),
             scratch_buffer: Vec::new(),
+            compositor: None,
+            canvas_transform_fn: None,
         };
 
+        transform::check_output_format(reader.info(), &reader.transform_settings)?;
+
         // Check if the decoding buffer of a single raw line has a valid size.
         if reader.info().checked_raw_row_length().is_none() {
             return Err(DecodingError::LimitsExceeded);
@@ -263,6 +304,82 @@ impl<R: Read> Decoder<R> {
         self.transform = transform;
     }
 
+    /// Correct the decoded samples for a display with the given gamma.
+    ///
+    /// The samples are converted from the transfer function of the image to the inverse of
+    /// `display_gamma`, for example 2.2 for a typical display or 1.0 for linear light. As required
+    /// by the specification an `sRGB` chunk takes precedence over `gAMA`, and a `cICP` chunk over
+    /// both. Images without such information are left unchanged, as are alpha samples and the
+    /// indices of palette images, which need [`Transformations::EXPAND`] to be corrected.
+    ///
+    /// `None`, or a gamma that is not positive and finite, disables the correction.
+    pub fn set_display_gamma(&mut self, display_gamma: Option<f32>) {
+        self.transform_settings.display_gamma = display_gamma;
+    }
+
+    /// Convert the decoded samples to the sRGB color space.
+    ///
+    /// The conversion uses the primaries and white point of the `cHRM` chunk, adapted to the D65
+    /// white point of sRGB, and the transfer function of the `gAMA` chunk. A `cICP` chunk takes
+    /// precedence if it describes BT.709, BT.2020 or Display P3 primaries with the sRGB, BT.709 or
+    /// linear transfer function. Otherwise an ICC profile is used if it is a matrix/TRC profile
+    /// matching the color type, see [`IccProfile`](crate::icc::IccProfile). Images tagged as sRGB,
+    /// either with an `sRGB` chunk or an ICC profile describing it, and images without any of this
+    /// information are left unchanged.
+    ///
+    /// Only output with 8 or 16 bits per sample is converted, which requires
+    /// [`Transformations::EXPAND`] for palette and low bit depth images. Alpha is kept as is and
+    /// colors outside of the sRGB gamut are clipped. Gamma correction with
+    /// [`Decoder::set_display_gamma`] is applied to the converted sRGB samples.
+    pub fn set_convert_to_srgb(&mut self, convert_to_srgb: bool) {
+        self.transform_settings.convert_to_srgb = convert_to_srgb;
+    }
+
+    /// Composite transparent pixels over a background color, removing the alpha channel.
+    ///
+    /// The output of images with alpha changes to [`ColorType::Rgb`] or [`ColorType::Grayscale`].
+    /// Transparency from a `tRNS` chunk needs [`Transformations::EXPAND`] to be composited. The
+    /// background is blended before any color correction, in the color space of the image.
+    ///
+    /// With `linear` set the samples are blended in linear light, using the transfer function
+    /// of the image as for [`Decoder::set_display_gamma`], or that of sRGB for images without such
+    /// information. Otherwise the encoded samples are blended directly, which darkens edges.
+    ///
+    /// `None` keeps the alpha channel.
+    pub fn set_background(&mut self, background: Option<Background>, linear: bool) {
+        self.transform_settings.background = background;
+        self.transform_settings.linear_background = linear;
+    }
+
+    /// Set the weights of the red, green and blue samples for [`Transformations::RGB_TO_GRAY`].
+    ///
+    /// The encoded samples are weighted, after any color correction. The default are the
+    /// coefficients of BT.709. They also give the gray level of a [`Background::Color`] for
+    /// grayscale images.
+    pub fn set_gray_coefficients(&mut self, coefficients: GrayCoefficients) {
+        self.transform_settings.gray_coefficients = coefficients;
+    }
+
+    /// Convert the decoded samples to the given color type and bit depth.
+    ///
+    /// Any image can be decoded to any combination of color type and bit depth that is valid in a
+    /// PNG image, except [`ColorType::Indexed`]. That requires a palette image and either its own
+    /// bit depth, which keeps the indices as they are, or 8 bits, which unpacks them to a byte each
+    /// as with [`Transformations::PACKING`]. Alpha from the image or its `tRNS` chunk is added,
+    /// filled with opaque samples, or removed, compositing over the background set with
+    /// [`Decoder::set_background`] if any. Color is converted to gray with the weights set with
+    /// [`Decoder::set_gray_coefficients`], and gray is duplicated to color. Samples are scaled to
+    /// the bit depth, rounding to the nearest value.
+    ///
+    /// This replaces the transformations that change the color type or bit depth, such as
+    /// [`Transformations::EXPAND`] or [`Transformations::STRIP_16`], while the others still apply.
+    /// [`Reader::output_color_type`] returns the requested format.
+    ///
+    /// An invalid combination is reported as an error by [`Decoder::read_info`].
+    pub fn set_output_format(&mut self, color_type: ColorType, bit_depth: BitDepth) {
+        self.transform_settings.output_format = Some((color_type, bit_depth));
+    }
+
     /// Set the decoder to ignore all text chunks while parsing.
     ///
     /// eg.
@@ -295,6 +412,25 @@ impl<R: Read> Decoder<R> {
             .set_ignore_iccp_chunk(ignore_iccp_chunk);
     }
 
+    /// Keep the ancillary chunks the decoder does not interpret in `Info::unknown_chunks`.
+    ///
+    /// eg.
+    /// ```
+    /// use std::fs::File;
+    /// use png::{chunk::ChunkType, ChunkPosition, Decoder};
+    /// let mut decoder = Decoder::new(File::open("tests/pngsuite/ch1n3p04.png").unwrap());
+    /// decoder.set_retain_unknown_chunks(true);
+    /// let reader = decoder.read_info().unwrap();
+    /// let histogram = &reader.info().unknown_chunks[0];
+    /// assert_eq!(histogram.chunk_type, ChunkType(*b"hIST"));
+    /// assert_eq!(histogram.position, ChunkPosition::BeforeIdat);
+    /// ```
+    pub fn set_retain_unknown_chunks(&mut self, retain_unknown_chunks: bool) {
+        self.read_decoder
+            .decoder
+            .set_retain_unknown_chunks(retain_unknown_chunks);
+    }
+
     /// Set the decoder to ignore and not verify the Adler-32 checksum
     /// and CRC code.
     pub fn ignore_checksums(&mut self, ignore_checksums: bool) {
@@ -386,13 +522,22 @@ pub struct Reader<R: Read> {
     current_start: usize,
     /// Output transformations
     transform: Transformations,
+    /// Parameters of the output transformations
+    transform_settings: TransformSettings,
     /// Function that can transform decompressed, unfiltered rows into final output.
     /// See the `transform.rs` module for more details.
-    transform_fn: Option<TransformFn>,
+    transform_fn: Option<RowTransformFn>,
+    /// Rows for intermediate results of `transform_fn` and `canvas_transform_fn`.
+    scratch_rows: ScratchRows,
     /// This buffer is only used so that `next_row` and `next_interlaced_row` can return reference
     /// to a byte slice. In a future version of this library, this buffer will be removed and
     /// `next_row` and `next_interlaced_row` will write directly into a user provided output buffer.
     scratch_buffer: Vec<u8>,
+    /// The canvas of `next_composited_frame`, created on its first call. Rows are only expanded
+    /// while it exists, see `TransformStage::Expansion`.
+    compositor: Option<Compositor>,
+    /// Function that transforms the rows of the canvas into the final output.
+    canvas_transform_fn: Option<RowTransformFn>,
 }
 
 /// The subframe specific information.
@@ -471,7 +616,7 @@ impl<R: Read> Reader<R> {
         self.subframe = SubframeInfo::new(info);
 
         // Allocate output buffer.
-        let buflen = self.output_line_size(self.subframe.width);
+        let buflen = self.row_line_size(self.subframe.width);
         self.decoder.decoder.limits.reserve_bytes(buflen)?;
 
         self.prev_start = self.current_start;
@@ -489,7 +634,7 @@ impl<R: Read> Reader<R> {
     /// Decodes the next frame into `buf`.
     ///
     /// Note that this decodes raw subframes that need to be mixed according to blend-op and
-    /// dispose-op by the caller.
+    /// dispose-op by the caller, [`Reader::next_composited_frame`] does this instead.
     ///
     /// The caller must always provide a buffer large enough to hold a complete frame (the APNG
     /// specification restricts subframes to the dimensions given in the image header). The region
@@ -500,43 +645,19 @@ impl<R: Read> Reader<R> {
     /// Output lines will be written in row-major, packed matrix with width and height of the read
     /// frame (or subframe), all samples are in big endian byte order where this matters.
     pub fn next_frame(&mut self, buf: &mut [u8]) -> Result<OutputInfo, DecodingError> {
-        let subframe_idx = match self.decoder.info().unwrap().frame_control() {
-            None => SubframeIdx::Initial,
-            Some(_) => SubframeIdx::Some(self.fctl_read - 1),
-        };
-
-        if self.next_frame == SubframeIdx::End {
-            return Err(DecodingError::Parameter(
-                ParameterErrorKind::PolledAfterEndOfImage.into(),
-            ));
-        } else if self.next_frame != subframe_idx {
-            // Advance until we've read the info / fcTL for this frame.
-            self.read_until_image_data()?;
-        }
-
-        if buf.len() < self.output_buffer_size() {
+        let output_info = self.start_frame()?;
+        if buf.len() < self.row_buffer_size() {
             return Err(DecodingError::Parameter(
                 ParameterErrorKind::ImageBufferSize {
                     expected: buf.len(),
-                    actual: self.output_buffer_size(),
+                    actual: self.row_buffer_size(),
                 }
                 .into(),
             ));
         }
 
-        let (color_type, bit_depth) = self.output_color_type();
-        let output_info = OutputInfo {
-            width: self.subframe.width,
-            height: self.subframe.height,
-            color_type,
-            bit_depth,
-            line_size: self.output_line_size(self.subframe.width),
-        };
-
-        self.data_stream.clear();
-        self.current_start = 0;
-        self.prev_start = 0;
-        let width = self.info().width;
+        let (color_type, bit_depth) = (output_info.color_type, output_info.bit_depth);
+        let width = self.subframe.width;
         if self.info().interlaced {
             while let Some(InterlacedRow {
                 data: row,
@@ -560,6 +681,43 @@ impl<R: Read> Reader<R> {
             }
         }
 
+        self.finish_frame()?;
+        Ok(output_info)
+    }
+
+    /// Advances to the image data of the next frame and returns the layout of its output.
+    fn start_frame(&mut self) -> Result<OutputInfo, DecodingError> {
+        let subframe_idx = match self.decoder.info().unwrap().frame_control() {
+            None => SubframeIdx::Initial,
+            Some(_) => SubframeIdx::Some(self.fctl_read - 1),
+        };
+
+        if self.next_frame == SubframeIdx::End {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::PolledAfterEndOfImage.into(),
+            ));
+        } else if self.next_frame != subframe_idx {
+            // Advance until we've read the info / fcTL for this frame.
+            self.read_until_image_data()?;
+        }
+
+        let (color_type, bit_depth) = self.row_color_type();
+        let output_info = OutputInfo {
+            width: self.subframe.width,
+            height: self.subframe.height,
+            color_type,
+            bit_depth,
+            line_size: self.row_line_size(self.subframe.width),
+        };
+
+        self.data_stream.clear();
+        self.current_start = 0;
+        self.prev_start = 0;
+        Ok(output_info)
+    }
+
+    /// Finishes decoding the image data of the current frame and expects the next one.
+    fn finish_frame(&mut self) -> Result<(), DecodingError> {
         // Advance over the rest of data for this (sub-)frame.
         if !self.subframe.consumed_and_flushed {
             self.decoder.finish_decoding()?;
@@ -583,17 +741,197 @@ impl<R: Read> Reader<R> {
             SubframeIdx::Some(idx) => SubframeIdx::Some(idx + 1),
         };
 
+        Ok(())
+    }
+
+    /// Decodes the next frame into a buffer of 16-bit samples.
+    ///
+    /// This is [`Reader::next_frame`] for output with 16 bits per sample, e.g. of 16-bit images
+    /// or with [`Transformations::EXPAND_16`]. Each sample is stored as a `u16` in native byte
+    /// order, independent of [`Transformations::SWAP_ENDIAN`]. `buf` must hold at least half of
+    /// [`Reader::output_buffer_size`] samples, the line size of the returned info is in bytes.
+    ///
+    /// Each row is decoded into the buffer of [`Reader::next_row`] first.
+    pub fn next_frame_u16(&mut self, buf: &mut [u16]) -> Result<OutputInfo, DecodingError> {
+        let (_, bit_depth) = self.output_color_type();
+        if bit_depth != BitDepth::Sixteen {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::OutputBitDepth {
+                    expected: BitDepth::Sixteen,
+                    actual: bit_depth,
+                }
+                .into(),
+            ));
+        }
+
+        let output_info = self.start_frame()?;
+        let size = self.output_buffer_size();
+        if buf.len() < size / 2 {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: size / 2,
+                    actual: buf.len(),
+                }
+                .into(),
+            ));
+        }
+
+        let from_bytes = if self.transform.contains(Transformations::SWAP_ENDIAN) {
+            u16::from_le_bytes
+        } else {
+            u16::from_be_bytes
+        };
+        let width = self.subframe.width;
+        let samples = output_info.color_type.samples() as u8;
+        let line_samples = output_info.line_size / 2;
+        let mut next_line = 0;
+        while let Some(InterlacedRow {
+            data: row,
+            interlace,
+            ..
+        }) = self.next_interlaced_row()?
+        {
+            match interlace {
+                InterlaceInfo::Adam7 { line, pass, .. } => {
+                    adam7::expand_pass_u16(buf, width, row, pass, line, samples, from_bytes);
+                }
+                InterlaceInfo::Null => {
+                    let line = &mut buf[next_line * line_samples..][..line_samples];
+                    next_line += 1;
+                    for (sample, bytes) in line.iter_mut().zip(row.chunks_exact(2)) {
+                        *sample = from_bytes([bytes[0], bytes[1]]);
+                    }
+                }
+            }
+        }
+
+        self.finish_frame()?;
         Ok(output_info)
     }
 
+    /// Decodes the next frame and composites it onto the full canvas in `buf`.
+    ///
+    /// Unlike [`Reader::next_frame`] this applies the dispose and blend operations of animation
+    /// frames, `buf` receives the complete image ready to be displayed and must be at least
+    /// [`Reader::output_buffer_size`] bytes. The default image is skipped when it is not part of
+    /// the animation. Images that are not animated are returned as their only frame.
+    ///
+    /// Frames are composited after the samples are expanded, the remaining transformations such as
+    /// a background, stripping alpha or the output format are applied to the canvas. With an
+    /// alpha channel, frames are alpha blended over the canvas. Otherwise a pixel covered by a
+    /// fully transparent `tRNS` entry leaves the canvas unchanged. Animations with partially
+    /// transparent palette entries, or a `tRNS` chunk that no longer matches the transformed
+    /// samples, return a parameter error unless they are expanded with
+    /// [`Transformations::EXPAND`].
+    ///
+    /// The canvas is kept between calls, so this must not be mixed with the other methods for
+    /// reading frames or rows.
+    pub fn next_composited_frame(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<CompositedFrameInfo, DecodingError> {
+        let size = self.output_buffer_size();
+        if buf.len() < size {
+            return Err(DecodingError::Parameter(
+                ParameterErrorKind::ImageBufferSize {
+                    expected: size,
+                    actual: buf.len(),
+                }
+                .into(),
+            ));
+        }
+
+        if self.compositor.is_none() {
+            let (color_type, bit_depth) = transform::expansion_color_type(
+                self.info(),
+                self.transform,
+                &self.transform_settings,
+            );
+            // The transparency only matters for blending the frames of an animation.
+            if self.info().animation_control.is_some()
+                && !blends_trns(self.info(), color_type, bit_depth)
+            {
+                return Err(DecodingError::Parameter(
+                    ParameterErrorKind::CompositedTransparency.into(),
+                ));
+            }
+            let compositor = Compositor::new(self.info(), color_type, bit_depth);
+            self.decoder
+                .decoder
+                .limits
+                .reserve_bytes(compositor.buffer_size())?;
+            self.canvas_transform_fn = Some(create_transform_fn(
+                self.info(),
+                self.transform,
+                &self.transform_settings,
+                TransformStage::Finishing,
+            )?);
+            self.compositor = Some(compositor);
+            // Rows are only expanded from now on, the rest is applied to the canvas.
+            self.transform_fn = None;
+        }
+
+        // The frame buffer is kept in the compositor, so that it is only allocated once.
+        let mut frame = self.compositor.as_mut().unwrap().take_frame();
+        if let Err(err) = self.next_animation_frame(&mut frame) {
+            self.compositor.as_mut().unwrap().put_frame(frame);
+            return Err(err);
+        }
+
+        let line_size = self.output_line_size(self.info().width);
+        // Borrowed from the decoder only, to be used along with the compositor.
+        let info = self.decoder.info().unwrap();
+        let fctl = info.animation_control.and(info.frame_control);
+        let (width, height) = info.size();
+        let compositor = self.compositor.as_mut().unwrap();
+        compositor.composite(&frame, fctl.as_ref());
+        compositor.put_frame(frame);
+
+        let canvas_transform_fn = self.canvas_transform_fn.as_deref().unwrap();
+        for (row, output) in compositor
+            .canvas_rows()
+            .zip(buf[..size].chunks_exact_mut(line_size))
+        {
+            canvas_transform_fn(row, output, info, &mut self.scratch_rows);
+        }
+
+        let (color_type, bit_depth) = self.output_color_type();
+        Ok(CompositedFrameInfo {
+            output: OutputInfo {
+                width,
+                height,
+                color_type,
+                bit_depth,
+                line_size,
+            },
+            delay_num: fctl.map_or(0, |fctl| fctl.delay_num),
+            delay_den: fctl.map_or(0, |fctl| fctl.delay_den),
+        })
+    }
+
+    /// Decodes the next frame of the animation into `frame`, skipping the default image if it is
+    /// not part of the animation.
+    fn next_animation_frame(&mut self, frame: &mut [u8]) -> Result<(), DecodingError> {
+        let info = self.info();
+        if self.next_frame == SubframeIdx::Initial
+            && info.animation_control.is_some()
+            && info.frame_control.is_none()
+        {
+            // The default image is only a fallback for decoders without APNG support.
+            self.next_frame(frame)?;
+        }
+        self.next_frame(frame)?;
+        Ok(())
+    }
+
     /// Returns the next processed row of the image
-    pub fn next_row(&mut self) -> Result<Option<Row>, DecodingError> {
+    pub fn next_row(&mut self) -> Result<Option<Row<'_>>, DecodingError> {
         self.next_interlaced_row()
             .map(|v| v.map(|v| Row { data: v.data }))
     }
 
     /// Returns the next processed row of the image
-    pub fn next_interlaced_row(&mut self) -> Result<Option<InterlacedRow>, DecodingError> {
+    pub fn next_interlaced_row(&mut self) -> Result<Option<InterlacedRow<'_>>, DecodingError> {
         let (rowlen, interlace) = match self.next_pass() {
             Some((rowlen, interlace)) => (rowlen, interlace),
             None => return Ok(None),
@@ -604,7 +942,7 @@ impl<R: Read> Reader<R> {
         } else {
             self.subframe.width
         };
-        let output_line_size = self.output_line_size(width);
+        let output_line_size = self.row_line_size(width);
 
         // TODO: change the interface of `next_interlaced_row` to take an output buffer instead of
         // making us return a reference to a buffer that we own.
@@ -652,11 +990,30 @@ impl<R: Read> Reader<R> {
         // Apply transformations and write resulting data to buffer.
         let transform_fn = {
             if self.transform_fn.is_none() {
-                self.transform_fn = Some(create_transform_fn(self.info(), self.transform)?);
+                let stage = if self.compositor.is_some() {
+                    TransformStage::Expansion
+                } else {
+                    TransformStage::All
+                };
+                self.transform_fn = Some(create_transform_fn(
+                    self.info(),
+                    self.transform,
+                    &self.transform_settings,
+                    stage,
+                )?);
+                // Intermediate rows are at most as wide as the expanded samples.
+                let (color, depth) = transform::expansion_color_type(
+                    self.info(),
+                    self.transform,
+                    &self.transform_settings,
+                );
+                let line_size = color.raw_row_length_from_width(depth, self.info().width) - 1;
+                self.scratch_rows = ScratchRows::new(line_size);
             }
             self.transform_fn.as_deref().unwrap()
         };
-        transform_fn(row, output_buffer, self.info());
+        let info = self.decoder.info().unwrap();
+        transform_fn(row, output_buffer, info, &mut self.scratch_rows);
 
         Ok(())
     }
@@ -664,37 +1021,7 @@ impl<R: Read> Reader<R> {
     /// Returns the color type and the number of bits per sample
     /// of the data returned by `Reader::next_row` and Reader::frames`.
     pub fn output_color_type(&self) -> (ColorType, BitDepth) {
-        use crate::common::ColorType::*;
-        let t = self.transform;
-        let info = self.info();
-        if t == Transformations::IDENTITY {
-            (info.color_type, info.bit_depth)
-        } else {
-            let bits = match info.bit_depth as u8 {
-                16 if t.intersects(Transformations::STRIP_16) => 8,
-                n if n < 8
-                    && (t.contains(Transformations::EXPAND)
-                        || t.contains(Transformations::ALPHA)) =>
-                {
-                    8
-                }
-                n => n,
-            };
-            let color_type =
-                if t.contains(Transformations::EXPAND) || t.contains(Transformations::ALPHA) {
-                    let has_trns = info.trns.is_some() || t.contains(Transformations::ALPHA);
-                    match info.color_type {
-                        Grayscale if has_trns => GrayscaleAlpha,
-                        Rgb if has_trns => Rgba,
-                        Indexed if has_trns => Rgba,
-                        Indexed => Rgb,
-                        ct => ct,
-                    }
-                } else {
-                    info.color_type
-                };
-            (color_type, BitDepth::from_u8(bits).unwrap())
-        }
+        transform::output_color_type(self.info(), self.transform, &self.transform_settings)
     }
 
     /// Returns the number of bytes required to hold a deinterlaced image frame
@@ -711,6 +1038,28 @@ impl<R: Read> Reader<R> {
         color.raw_row_length_from_width(depth, width) - 1
     }
 
+    /// Returns the color type and bit depth of the decoded rows, which are those of the output
+    /// unless they are composited by `next_composited_frame` first.
+    fn row_color_type(&self) -> (ColorType, BitDepth) {
+        if self.compositor.is_some() {
+            transform::expansion_color_type(self.info(), self.transform, &self.transform_settings)
+        } else {
+            self.output_color_type()
+        }
+    }
+
+    /// Returns the number of bytes of a decoded row, see `row_color_type`.
+    fn row_line_size(&self, width: u32) -> usize {
+        let (color, depth) = self.row_color_type();
+        color.raw_row_length_from_width(depth, width) - 1
+    }
+
+    /// Returns the number of bytes of a decoded frame, see `row_color_type`.
+    fn row_buffer_size(&self) -> usize {
+        let (width, height) = self.info().size();
+        self.row_line_size(width) * height as usize
+    }
+
     fn next_pass(&mut self) -> Option<(usize, InterlaceInfo)> {
         match self.subframe.interlace {
             InterlaceIter::Adam7(ref mut adam7) => {
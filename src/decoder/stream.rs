@@ -9,8 +9,10 @@ use crc32fast::Hasher as Crc32;
 use super::zlib::ZlibStream;
 use crate::chunk::{self, ChunkType, IDAT, IEND, IHDR};
 use crate::common::{
-    AnimationControl, BitDepth, BlendOp, ColorType, DisposeOp, FrameControl, Info, ParameterError,
-    PixelDimensions, ScaledFloat, SourceChromaticities, Unit,
+    AnimationControl, BackgroundColor, BitDepth, BlendOp, ChunkPosition,
+    CodingIndependentCodePoints, ColorType, ContentLightLevelInfo, DisposeOp, FrameControl, Info,
+    MasteringDisplayColorVolume, ParameterError, PixelDimensions, ScaledFloat,
+    SourceChromaticities, Time, Unit, UnknownChunk,
 };
 use crate::text_metadata::{ITXtChunk, TEXtChunk, TextDecodingError, ZTXtChunk};
 use crate::traits::ReadBytesExt;
@@ -183,6 +185,10 @@ pub(crate) enum FormatErrorInner {
     AfterPlte {
         kind: ChunkType,
     },
+    /// 4.3., some chunks must be after PLTE.
+    BeforePlte {
+        kind: ChunkType,
+    },
     /// 4.3., some chunks must be between PLTE and IDAT.
     OutsidePlteIdat {
         kind: ChunkType,
@@ -212,6 +218,10 @@ pub(crate) enum FormatErrorInner {
         bit_depth: BitDepth,
     },
     ColorWithBadTrns(ColorType),
+    /// The background color does not fit the color type, bit depth or palette of the image.
+    InvalidBackgroundColor,
+    /// The significant bits do not fit the color type or bit depth of the image.
+    InvalidSignificantBits,
     /// The image width or height is zero.
     InvalidDimensions,
     InvalidBitDepth(u8),
@@ -281,6 +291,7 @@ impl fmt::Display for FormatError {
             ChunkBeforeIhdr { kind } => write!(fmt, "{:?} chunk appeared before IHDR chunk", kind),
             AfterIdat { kind } => write!(fmt, "Chunk {:?} is invalid after IDAT chunk.", kind),
             AfterPlte { kind } => write!(fmt, "Chunk {:?} is invalid after PLTE chunk.", kind),
+            BeforePlte { kind } => write!(fmt, "Chunk {:?} is invalid before PLTE chunk.", kind),
             OutsidePlteIdat { kind } => write!(
                 fmt,
                 "Chunk {:?} must appear between PLTE and IDAT chunks.",
@@ -312,6 +323,14 @@ impl fmt::Display for FormatError {
                 "Transparency chunk found for color type {:?}.",
                 color_type
             ),
+            InvalidBackgroundColor => write!(
+                fmt,
+                "Background color does not match the color type or bit depth."
+            ),
+            InvalidSignificantBits => write!(
+                fmt,
+                "Significant bits do not match the color type or bit depth."
+            ),
             InvalidBitDepth(nr) => write!(fmt, "Invalid bit depth {}.", nr),
             InvalidColorType(nr) => write!(fmt, "Invalid color type {}.", nr),
             InvalidDisposeOp(nr) => write!(fmt, "Invalid dispose op {}.", nr),
@@ -410,6 +429,7 @@ pub struct DecodeOptions {
     ignore_text_chunk: bool,
     ignore_iccp_chunk: bool,
     skip_ancillary_crc_failures: bool,
+    retain_unknown_chunks: bool,
 }
 
 impl Default for DecodeOptions {
@@ -420,6 +440,7 @@ impl Default for DecodeOptions {
             ignore_text_chunk: false,
             ignore_iccp_chunk: false,
             skip_ancillary_crc_failures: true,
+            retain_unknown_chunks: false,
         }
     }
 }
@@ -466,6 +487,14 @@ impl DecodeOptions {
     pub fn set_skip_ancillary_crc_failures(&mut self, skip_ancillary_crc_failures: bool) {
         self.skip_ancillary_crc_failures = skip_ancillary_crc_failures;
     }
+
+    /// Keep ancillary chunks the decoder does not interpret in `Info::unknown_chunks`, together
+    /// with their position, so they can be written again when encoding.
+    ///
+    /// Defaults to `false`.
+    pub fn set_retain_unknown_chunks(&mut self, retain_unknown_chunks: bool) {
+        self.retain_unknown_chunks = retain_unknown_chunks;
+    }
 }
 
 /// PNG StreamingDecoder (low-level interface)
@@ -487,6 +516,16 @@ pub struct StreamingDecoder {
     have_idat: bool,
     /// Whether we have already seen an iCCP chunk. Used to prevent parsing of duplicate iCCP chunks.
     have_iccp: bool,
+    /// Whether we have already seen a tIME chunk, including one that was dropped as invalid.
+    have_time: bool,
+    /// Whether we have already seen a cICP chunk, including one that was dropped as invalid.
+    have_cicp: bool,
+    /// Whether we have already seen a mDCV chunk, including one that was dropped as invalid.
+    have_mdcv: bool,
+    /// Whether we have already seen a cLLI chunk, including one that was dropped as invalid.
+    have_clli: bool,
+    /// Whether we have already seen an eXIf chunk, including one that was dropped as invalid.
+    have_exif: bool,
     decode_options: DecodeOptions,
     pub(crate) limits: Limits,
 }
@@ -526,6 +565,11 @@ impl StreamingDecoder {
             current_seq_no: None,
             have_idat: false,
             have_iccp: false,
+            have_time: false,
+            have_cicp: false,
+            have_mdcv: false,
+            have_clli: false,
+            have_exif: false,
             decode_options,
             limits: Limits { bytes: usize::MAX },
         }
@@ -541,6 +585,11 @@ impl StreamingDecoder {
         self.info = None;
         self.current_seq_no = None;
         self.have_idat = false;
+        self.have_time = false;
+        self.have_cicp = false;
+        self.have_mdcv = false;
+        self.have_clli = false;
+        self.have_exif = false;
     }
 
     /// Provides access to the inner `info` field
@@ -556,6 +605,11 @@ impl StreamingDecoder {
         self.decode_options.set_ignore_iccp_chunk(ignore_iccp_chunk);
     }
 
+    pub fn set_retain_unknown_chunks(&mut self, retain_unknown_chunks: bool) {
+        self.decode_options
+            .set_retain_unknown_chunks(retain_unknown_chunks);
+    }
+
     /// Return whether the decoder is set to ignore the Adler-32 checksum.
     pub fn ignore_adler32(&self) -> bool {
         self.inflater.ignore_adler32()
@@ -898,16 +952,30 @@ impl StreamingDecoder {
             IHDR => self.parse_ihdr(),
             chunk::PLTE => self.parse_plte(),
             chunk::tRNS => self.parse_trns(),
+            chunk::sBIT => self.parse_sbit(),
+            chunk::bKGD => self.parse_bkgd(),
+            chunk::tIME => self.parse_time(),
             chunk::pHYs => self.parse_phys(),
             chunk::gAMA => self.parse_gama(),
             chunk::acTL => self.parse_actl(),
             chunk::fcTL => self.parse_fctl(),
             chunk::cHRM => self.parse_chrm(),
             chunk::sRGB => self.parse_srgb(),
+            chunk::cICP => self.parse_cicp(),
+            chunk::mDCV => self.parse_mdcv(),
+            chunk::eXIf => self.parse_exif(),
+            chunk::cLLI => self.parse_clli(),
             chunk::iCCP if !self.decode_options.ignore_iccp_chunk => self.parse_iccp(),
             chunk::tEXt if !self.decode_options.ignore_text_chunk => self.parse_text(),
             chunk::zTXt if !self.decode_options.ignore_text_chunk => self.parse_ztxt(),
             chunk::iTXt if !self.decode_options.ignore_text_chunk => self.parse_itxt(),
+            // Known chunks that are ignored on request are not unknown.
+            chunk::iCCP | chunk::tEXt | chunk::zTXt | chunk::iTXt => {
+                Ok(Decoded::PartialChunk(type_str))
+            }
+            _ if self.decode_options.retain_unknown_chunks && !chunk::is_critical(type_str) => {
+                self.parse_unknown(type_str)
+            }
             _ => Ok(Decoded::PartialChunk(type_str)),
         };
 
@@ -917,6 +985,27 @@ impl StreamingDecoder {
         parse_result
     }
 
+    fn parse_unknown(&mut self, type_str: ChunkType) -> Result<Decoded, DecodingError> {
+        let buf = &self.current_chunk.raw_bytes[..];
+        self.limits.reserve_bytes(buf.len())?;
+
+        let info = self.info.as_mut().unwrap();
+        let position = if self.have_idat {
+            ChunkPosition::AfterIdat
+        } else if info.palette.is_some() {
+            ChunkPosition::BeforeIdat
+        } else {
+            ChunkPosition::BeforePlte
+        };
+        info.unknown_chunks.push(UnknownChunk {
+            chunk_type: type_str,
+            data: buf.to_vec(),
+            position,
+        });
+
+        Ok(Decoded::PartialChunk(type_str))
+    }
+
     fn parse_fctl(&mut self) -> Result<Decoded, DecodingError> {
         let mut buf = &self.current_chunk.raw_bytes[..];
         let next_seq_no = buf.read_be()?;
@@ -1005,6 +1094,12 @@ impl StreamingDecoder {
             Err(DecodingError::Format(
                 FormatErrorInner::DuplicateChunk { kind: chunk::PLTE }.into(),
             ))
+        } else if info.bkgd.is_some() {
+            // The background color must follow the palette, also where the palette is only a
+            // suggestion.
+            Err(DecodingError::Format(
+                FormatErrorInner::BeforePlte { kind: chunk::bKGD }.into(),
+            ))
         } else {
             self.limits
                 .reserve_bytes(self.current_chunk.raw_bytes.len())?;
@@ -1076,6 +1171,75 @@ impl StreamingDecoder {
         }
     }
 
+    fn parse_sbit(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::sBIT }.into(),
+            ))
+        } else if info.palette.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterPlte { kind: chunk::sBIT }.into(),
+            ))
+        } else if info.sbit.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::sBIT }.into(),
+            ))
+        } else if !info.is_valid_sbit(&self.current_chunk.raw_bytes) {
+            Err(DecodingError::Format(
+                FormatErrorInner::InvalidSignificantBits.into(),
+            ))
+        } else {
+            info.sbit = Some(Cow::Owned(self.current_chunk.raw_bytes.clone()));
+            Ok(Decoded::Nothing)
+        }
+    }
+
+    fn parse_bkgd(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::bKGD }.into(),
+            ))
+        } else if info.bkgd.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::bKGD }.into(),
+            ))
+        } else if info.color_type == ColorType::Indexed && info.palette.is_none() {
+            // The background color refers to a palette entry, so the palette must come first.
+            Err(DecodingError::Format(
+                FormatErrorInner::BeforePlte { kind: chunk::bKGD }.into(),
+            ))
+        } else {
+            let bkgd =
+                BackgroundColor::from_be_bytes(info.color_type, &self.current_chunk.raw_bytes)
+                    .filter(|bkgd| {
+                        bkgd.is_valid_for(info.color_type, info.bit_depth, info.palette.as_deref())
+                    })
+                    .ok_or_else(|| {
+                        DecodingError::Format(FormatErrorInner::InvalidBackgroundColor.into())
+                    })?;
+            info.bkgd = Some(bkgd);
+            Ok(Decoded::Nothing)
+        }
+    }
+
+    fn parse_time(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_time {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::tIME }.into(),
+            ))
+        } else {
+            self.have_time = true;
+            // The timestamp is purely informational, a malformed one is dropped instead of
+            // failing the whole image.
+            info.last_modified =
+                Time::from_be_bytes(&self.current_chunk.raw_bytes).filter(Time::is_valid);
+            Ok(Decoded::Nothing)
+        }
+    }
+
     fn parse_phys(&mut self) -> Result<Decoded, DecodingError> {
         let info = self.info.as_mut().unwrap();
         if self.have_idat {
@@ -1205,6 +1369,96 @@ impl StreamingDecoder {
         }
     }
 
+    fn parse_cicp(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::cICP }.into(),
+            ))
+        } else if info.palette.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterPlte { kind: chunk::cICP }.into(),
+            ))
+        } else if self.have_cicp {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::cICP }.into(),
+            ))
+        } else {
+            self.have_cicp = true;
+            // Code points that PNG does not allow can't describe the image, so they are ignored
+            // like an unknown chunk.
+            info.coding_independent_code_points =
+                CodingIndependentCodePoints::from_be_bytes(&self.current_chunk.raw_bytes);
+            Ok(Decoded::Nothing)
+        }
+    }
+
+    fn parse_mdcv(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::mDCV }.into(),
+            ))
+        } else if info.palette.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterPlte { kind: chunk::mDCV }.into(),
+            ))
+        } else if self.have_mdcv {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::mDCV }.into(),
+            ))
+        } else {
+            self.have_mdcv = true;
+            // The metadata only describes the image, a malformed chunk is dropped like an
+            // unknown chunk.
+            info.mastering_display_color_volume =
+                MasteringDisplayColorVolume::from_be_bytes(&self.current_chunk.raw_bytes);
+            Ok(Decoded::Nothing)
+        }
+    }
+
+    fn parse_clli(&mut self) -> Result<Decoded, DecodingError> {
+        let info = self.info.as_mut().unwrap();
+        if self.have_idat {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterIdat { kind: chunk::cLLI }.into(),
+            ))
+        } else if info.palette.is_some() {
+            Err(DecodingError::Format(
+                FormatErrorInner::AfterPlte { kind: chunk::cLLI }.into(),
+            ))
+        } else if self.have_clli {
+            Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::cLLI }.into(),
+            ))
+        } else {
+            self.have_clli = true;
+            // The metadata only describes the image, a malformed chunk is dropped like an
+            // unknown chunk.
+            info.content_light_level =
+                ContentLightLevelInfo::from_be_bytes(&self.current_chunk.raw_bytes);
+            Ok(Decoded::Nothing)
+        }
+    }
+
+    fn parse_exif(&mut self) -> Result<Decoded, DecodingError> {
+        if self.have_exif {
+            return Err(DecodingError::Format(
+                FormatErrorInner::DuplicateChunk { kind: chunk::eXIf }.into(),
+            ));
+        }
+        self.have_exif = true;
+
+        // Exif data without a TIFF header can't be interpreted, so it is dropped like an
+        // unknown chunk.
+        let buf = &self.current_chunk.raw_bytes[..];
+        if crate::common::is_valid_exif(buf) {
+            self.limits.reserve_bytes(buf.len())?;
+            self.info.as_mut().unwrap().exif_metadata = Some(Cow::Owned(buf.to_vec()));
+        }
+        Ok(Decoded::Nothing)
+    }
+
     fn parse_iccp(&mut self) -> Result<Decoded, DecodingError> {
         if self.have_idat {
             Err(DecodingError::Format(
@@ -1235,12 +1489,13 @@ impl StreamingDecoder {
         let mut buf = &self.current_chunk.raw_bytes[..];
 
         // read profile name
-        let _: u8 = buf.read_be()?;
-        for _ in 1..80 {
+        let mut name = Vec::new();
+        for _ in 0..80 {
             let raw: u8 = buf.read_be()?;
             if raw == 0 {
                 break;
             }
+            name.push(raw);
         }
 
         match buf.read_be()? {
@@ -1257,6 +1512,9 @@ impl StreamingDecoder {
             Ok(profile) => {
                 self.limits.reserve_bytes(profile.len())?;
                 info.icc_profile = Some(Cow::Owned(profile));
+                // An invalid name is dropped so that the profile can be written again.
+                info.icc_profile_name = Some(crate::text_metadata::decode_iso_8859_1(&name))
+                    .filter(|name| crate::text_metadata::is_valid_keyword(name));
             }
             Err(fdeflate::BoundedDecompressionError::DecompressionError { inner: err }) => {
                 return Err(DecodingError::Format(
@@ -1507,10 +1765,13 @@ impl Default for ChunkState {
 
 #[cfg(test)]
 mod tests {
+    use super::BackgroundColor;
+    use super::ChunkPosition;
     use super::ScaledFloat;
     use super::SourceChromaticities;
+    use super::Time;
     use crate::test_utils::*;
-    use crate::{Decoder, DecodingError};
+    use crate::{Decoder, DecodingError, Encoder};
     use byteorder::WriteBytesExt;
     use std::fs::File;
     use std::io::Write;
@@ -1743,6 +2004,349 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn image_background_color() {
+        fn trial(path: &str, expected: Option<BackgroundColor>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let reader = decoder.read_info().unwrap();
+            assert_eq!(reader.info().bkgd, expected, "{}", path);
+        }
+        trial("tests/pngsuite/basn0g08.png", None);
+        trial(
+            "tests/pngsuite/bgbn4a08.png",
+            Some(BackgroundColor::Grayscale(0)),
+        );
+        trial(
+            "tests/pngsuite/bggn4a16.png",
+            Some(BackgroundColor::Grayscale(43908)),
+        );
+        trial(
+            "tests/pngsuite/bgwn6a08.png",
+            Some(BackgroundColor::Rgb {
+                red: 255,
+                green: 255,
+                blue: 255,
+            }),
+        );
+        trial(
+            "tests/pngsuite/bgyn6a16.png",
+            Some(BackgroundColor::Rgb {
+                red: 65535,
+                green: 65535,
+                blue: 0,
+            }),
+        );
+        trial(
+            "tests/pngsuite/tbbn3p08.png",
+            Some(BackgroundColor::Indexed(245)),
+        );
+    }
+
+    #[test]
+    fn test_bkgd_out_of_range() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // The sample does not fit into the 8-bit depth of the image.
+        write_chunk(&mut png, b"bKGD", &[1, 0, 0, 0, 0, 0]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn test_bkgd_before_plte() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // A suggested palette of an RGBA image must still come before the background color.
+        write_chunk(&mut png, b"bKGD", &[0, 0, 0, 0, 0, 0]);
+        write_chunk(&mut png, b"PLTE", &[0, 0, 0]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn image_significant_bits() {
+        fn trial(path: &str, expected: Option<&[u8]>) {
+            let decoder = crate::Decoder::new(File::open(path).unwrap());
+            let reader = decoder.read_info().unwrap();
+            assert_eq!(reader.info().sbit.as_deref(), expected, "{}", path);
+        }
+        trial("tests/pngsuite/basn0g08.png", None);
+        trial("tests/pngsuite/cs3n2c16.png", Some(&[13, 13, 13]));
+        trial("tests/pngsuite/cs5n3p08.png", Some(&[5, 5, 5]));
+    }
+
+    #[test]
+    fn test_sbit_out_of_range() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // More significant bits than the 8-bit depth of the image.
+        write_chunk(&mut png, b"sBIT", &[8, 8, 8, 9]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn test_sbit_after_plte() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        write_chunk(&mut png, b"PLTE", &[0, 0, 0]);
+        write_chunk(&mut png, b"sBIT", &[8, 8, 8, 8]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn test_cicp_after_plte() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        write_chunk(&mut png, b"PLTE", &[0, 0, 0]);
+        write_chunk(&mut png, b"cICP", &[1, 13, 0, 1]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn test_cicp_unsupported_matrix() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // YCbCr matrix coefficients are not allowed in PNG, the chunk is ignored.
+        write_chunk(&mut png, b"cICP", &[1, 13, 1, 1]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().coding_independent_code_points, None);
+    }
+
+    #[test]
+    fn test_cicp_duplicate_after_invalid() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // The first chunk is dropped as invalid, but still counts as seen.
+        write_chunk(&mut png, b"cICP", &[1, 13, 1, 1]);
+        write_chunk(&mut png, b"cICP", &[1, 13, 0, 1]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn test_hdr_metadata_wrong_length() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // Chunks with trailing bytes are dropped.
+        write_chunk(&mut png, b"mDCV", &[0; 25]);
+        write_chunk(&mut png, b"cLLI", &[0; 9]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().mastering_display_color_volume, None);
+        assert_eq!(reader.info().content_light_level, None);
+    }
+
+    #[test]
+    fn test_hdr_metadata_order() {
+        for chunk in [b"mDCV", b"cLLI"] {
+            let data = if chunk == b"mDCV" {
+                &[0; 24][..]
+            } else {
+                &[0; 8][..]
+            };
+
+            // After PLTE.
+            let mut png = Vec::new();
+            write_png_sig(&mut png);
+            write_rgba8_ihdr_with_width(&mut png, 8);
+            write_chunk(&mut png, b"PLTE", &[0, 0, 0]);
+            write_chunk(&mut png, chunk, data);
+            write_rgba8_idats(&mut png, 8, 0x7fffffff);
+            write_iend(&mut png);
+            let result = Decoder::new(png.as_slice()).read_info();
+            assert!(matches!(result, Err(DecodingError::Format(_))));
+
+            // Duplicate after a dropped chunk.
+            let mut png = Vec::new();
+            write_png_sig(&mut png);
+            write_rgba8_ihdr_with_width(&mut png, 8);
+            write_chunk(&mut png, chunk, &data[1..]);
+            write_chunk(&mut png, chunk, data);
+            write_rgba8_idats(&mut png, 8, 0x7fffffff);
+            write_iend(&mut png);
+            let result = Decoder::new(png.as_slice()).read_info();
+            assert!(matches!(result, Err(DecodingError::Format(_))));
+        }
+    }
+
+    #[test]
+    fn test_exif_after_idat() {
+        // A little endian TIFF header with orientation 8 (rotate 90° counter-clockwise).
+        let exif = [
+            b'I', b'I', 42, 0, 8, 0, 0, 0, 1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0,
+            0,
+        ];
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_chunk(&mut png, b"eXIf", &exif);
+        write_iend(&mut png);
+
+        let mut reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        reader.finish().unwrap();
+        assert_eq!(reader.info().exif_metadata.as_deref(), Some(&exif[..]));
+        assert_eq!(reader.info().exif_orientation(), Some(8));
+    }
+
+    #[test]
+    fn test_exif_without_tiff_header() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        write_chunk(&mut png, b"eXIf", b"Exif\0\0MM\0\x2a");
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().exif_metadata, None);
+        assert_eq!(reader.info().exif_orientation(), None);
+    }
+
+    #[test]
+    fn test_exif_duplicate_after_invalid() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // The first chunk is dropped as invalid, but still counts as seen.
+        write_chunk(&mut png, b"eXIf", b"Exif\0\0MM\0\x2a");
+        write_chunk(&mut png, b"eXIf", b"MM\0\x2a\0\0\0\x08\0\0");
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
+    #[test]
+    fn image_unknown_chunks() {
+        fn trial(path: &str, retain: bool, expected: &[(&[u8; 4], ChunkPosition)]) {
+            let mut decoder = crate::Decoder::new(File::open(path).unwrap());
+            decoder.set_retain_unknown_chunks(retain);
+            let reader = decoder.read_info().unwrap();
+            let unknown: Vec<_> = reader
+                .info()
+                .unknown_chunks
+                .iter()
+                .map(|unknown| (&unknown.chunk_type.0, unknown.position))
+                .collect();
+            assert_eq!(unknown, expected, "{}", path);
+        }
+        trial("tests/pngsuite/ch1n3p04.png", false, &[]);
+        trial(
+            "tests/pngsuite/ch1n3p04.png",
+            true,
+            &[(b"hIST", ChunkPosition::BeforeIdat)],
+        );
+        trial(
+            "tests/pngsuite/ps1n0g08.png",
+            true,
+            &[(b"sPLT", ChunkPosition::BeforePlte)],
+        );
+    }
+
+    #[test]
+    fn image_last_modified() {
+        let decoder = crate::Decoder::new(File::open("tests/pngsuite/cm9n0g04.png").unwrap());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(
+            reader.info().last_modified,
+            Some(Time {
+                year: 1999,
+                month: 12,
+                day: 31,
+                hour: 23,
+                minute: 59,
+                second: 59,
+            })
+        );
+    }
+
+    #[test]
+    fn test_time_after_idat() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_chunk(&mut png, b"tIME", &[0x07, 0xe8, 2, 29, 12, 0, 60]);
+        write_iend(&mut png);
+
+        let mut reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().last_modified, None);
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        reader.finish().unwrap();
+        assert_eq!(
+            reader.info().last_modified,
+            Time::new(2024, 2, 29, 12, 0, 60)
+        );
+    }
+
+    #[test]
+    fn test_time_out_of_range() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // Month 13 is out of range, the chunk is ignored.
+        write_chunk(&mut png, b"tIME", &[0x07, 0xe8, 13, 1, 0, 0, 0]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let reader = Decoder::new(png.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().last_modified, None);
+    }
+
+    #[test]
+    fn test_time_duplicate_after_invalid() {
+        let mut png = Vec::new();
+        write_png_sig(&mut png);
+        write_rgba8_ihdr_with_width(&mut png, 8);
+        // The first chunk is dropped as invalid, but still counts as seen.
+        write_chunk(&mut png, b"tIME", &[0x07, 0xe8, 13, 1, 0, 0, 0]);
+        write_chunk(&mut png, b"tIME", &[0x07, 0xe8, 2, 29, 12, 0, 60]);
+        write_rgba8_idats(&mut png, 8, 0x7fffffff);
+        write_iend(&mut png);
+
+        let result = Decoder::new(png.as_slice()).read_info();
+        assert!(matches!(result, Err(DecodingError::Format(_))));
+    }
+
     /// Test handling of a PNG file that contains *two* iCCP chunks.
     /// This is a regression test for https://github.com/image-rs/image/issues/1825.
     #[test]
@@ -1762,6 +2366,54 @@ mod tests {
         assert_eq!(4070462061, crc32fast::hash(&icc_profile));
     }
 
+    #[test]
+    fn test_iccp_invalid_name() {
+        // The name of 80 bytes is not followed by a null separator.
+        let long_name = [b'a'; 80];
+        for name in [
+            &b""[..],
+            b" P3",
+            b"P3 ",
+            b"Display  P3",
+            b"Display\nP3",
+            &long_name,
+        ] {
+            let mut iccp = name.to_vec();
+            if name.len() < 80 {
+                iccp.push(0);
+            }
+            iccp.push(0);
+            iccp.extend(fdeflate::compress_to_vec(b"profile"));
+            let mut png = Vec::new();
+            write_png_sig(&mut png);
+            write_rgba8_ihdr_with_width(&mut png, 8);
+            write_chunk(&mut png, b"iCCP", &iccp);
+            write_rgba8_idats(&mut png, 8, 0x7fffffff);
+            write_iend(&mut png);
+
+            let mut reader = Decoder::new(png.as_slice()).read_info().unwrap();
+            assert_eq!(reader.info().icc_profile.as_deref(), Some(&b"profile"[..]));
+            assert_eq!(reader.info().icc_profile_name, None);
+
+            // The profile is written again with the generic name.
+            let mut image = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut image).unwrap();
+            let mut encoded = Vec::new();
+            let encoder = Encoder::with_info(&mut encoded, reader.info().clone()).unwrap();
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&image)
+                .unwrap();
+            let reader = Decoder::new(encoded.as_slice()).read_info().unwrap();
+            assert_eq!(reader.info().icc_profile.as_deref(), Some(&b"profile"[..]));
+            assert_eq!(
+                reader.info().icc_profile_name.as_deref(),
+                Some("ICC profile")
+            );
+        }
+    }
+
     #[test]
     fn test_png_with_broken_iccp() {
         let decoder = crate::Decoder::new(File::open("tests/iccp/broken_iccp.png").unwrap());
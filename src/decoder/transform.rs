@@ -1,8 +1,15 @@
 //! Transforming a decompressed, unfiltered row into the final output.
 
+mod background;
+mod color;
+mod gray;
 mod palette;
 
-use crate::{BitDepth, ColorType, DecodingError, Info, Transformations};
+use crate::common::ParameterErrorKind;
+use crate::pixel::{alpha, swizzle};
+use crate::{
+    Background, BitDepth, ColorType, DecodingError, GrayCoefficients, Info, Transformations,
+};
 
 use super::stream::FormatErrorInner;
 
@@ -14,12 +21,570 @@ use super::stream::FormatErrorInner;
 /// faster) then consider changing this into `Box<dyn Fn(...)>`.
 pub type TransformFn = Box<dyn Fn(&[u8], &mut [u8], &Info) + Send + Sync>;
 
+/// Type of the functions created by `create_transform_fn`, which are like `TransformFn` with
+/// rows for intermediate results as the 4th argument.
+pub(crate) type RowTransformFn =
+    Box<dyn Fn(&[u8], &mut [u8], &Info, &mut ScratchRows) + Send + Sync>;
+
+/// Rows for the intermediate results of transformations that produce fewer samples per pixel,
+/// kept by the `Reader` so that they are only allocated once.
+#[derive(Debug, Default)]
+pub(crate) struct ScratchRows {
+    rows: Vec<Vec<u8>>,
+    /// The capacity of new rows, the line size of the expanded samples.
+    line_size: usize,
+}
+
+impl ScratchRows {
+    /// Creates the rows for transforming lines of at most `line_size` bytes after expansion.
+    pub(crate) fn new(line_size: usize) -> Self {
+        ScratchRows {
+            rows: Vec::new(),
+            line_size,
+        }
+    }
+
+    /// Takes a row of `len` bytes, to be returned with `give`. Its contents are unspecified.
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let mut row = self
+            .rows
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.line_size));
+        row.resize(len, 0);
+        row
+    }
+
+    /// Returns a row taken with `take`.
+    fn give(&mut self, row: Vec<u8>) {
+        self.rows.push(row);
+    }
+}
+
+/// Parameters of the transformations that need more than a flag of `Transformations`.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TransformSettings {
+    /// Gamma of the display to correct the samples for, see `Decoder::set_display_gamma`.
+    pub(crate) display_gamma: Option<f32>,
+    /// Whether to convert the samples to sRGB, see `Decoder::set_convert_to_srgb`.
+    pub(crate) convert_to_srgb: bool,
+    /// Color to composite transparent pixels over, see `Decoder::set_background`.
+    pub(crate) background: Option<Background>,
+    /// Whether to composite in linear light, see `Decoder::set_background`.
+    pub(crate) linear_background: bool,
+    /// Weights for `Transformations::RGB_TO_GRAY`, see `Decoder::set_gray_coefficients`.
+    pub(crate) gray_coefficients: GrayCoefficients,
+    /// Color type and bit depth to convert to, see `Decoder::set_output_format`.
+    pub(crate) output_format: Option<(ColorType, BitDepth)>,
+}
+
+/// The part of the transformations that a function is created for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TransformStage {
+    /// All transformations, from decompressed rows to the output.
+    All,
+    /// Only the expansion of decompressed rows into the layout of `expansion_color_type`, which
+    /// keeps the alpha channel. Animation frames are composited in this layout.
+    Expansion,
+    /// The remaining transformations, from rows in the layout of `expansion_color_type` to the
+    /// output.
+    Finishing,
+}
+
 /// Returns a transformation function that should be applied to image rows based
 /// on 1) decoded image metadata (`info`) and 2) the transformations requested
-/// by the crate client (`transform`).
-pub fn create_transform_fn(
+/// by the crate client (`transform` and `settings`), restricted to the `stage`.
+pub(crate) fn create_transform_fn(
+    info: &Info,
+    transform: Transformations,
+    settings: &TransformSettings,
+    stage: TransformStage,
+) -> Result<RowTransformFn, DecodingError> {
+    let transform = effective_transform(info, transform, settings);
+    let (expanded_color_type, bit_depth) = expanded_color_type(info, transform);
+    let widen = widens_to_16(info, transform);
+    let compositing = background(settings).and_then(|background| {
+        background::Compositing::new(
+            info,
+            transform,
+            background,
+            settings.linear_background,
+            settings.gray_coefficients,
+            expanded_color_type,
+            bit_depth,
+        )
+    });
+    let strip_alpha = compositing.is_none()
+        && transform.contains(Transformations::STRIP_ALPHA)
+        && alpha::has_alpha(expanded_color_type, bit_depth);
+    // The steps after compositing or stripping work on the samples without alpha.
+    let color_type = if compositing.is_some() || strip_alpha {
+        without_alpha(expanded_color_type)
+    } else {
+        expanded_color_type
+    };
+
+    // Unpacked samples keep their values, which are corrected at the bit depth of the image.
+    let sample_depth = if unpacks_samples(info, transform) {
+        info.bit_depth
+    } else {
+        bit_depth
+    };
+    let srgb_conversion = if settings.convert_to_srgb {
+        color::SrgbConversion::new(info, color_type, sample_depth)
+    } else {
+        None
+    };
+    // Gamma correction continues from the sRGB samples if they were converted.
+    let source_transfer = match srgb_conversion {
+        Some(_) => Some(color::SourceTransfer::Srgb),
+        None => color::SourceTransfer::from_info(info),
+    };
+    let gamma_table = settings
+        .display_gamma
+        .and_then(|gamma| color::gamma_table(source_transfer?, gamma, color_type, sample_depth));
+    let shifts = significant_bit_shifts(info, transform).and_then(|(mut shifts, bit_depth)| {
+        shifts.truncate(color_type.samples());
+        if shifts.iter().all(|&shift| shift == 0) {
+            None
+        } else {
+            Some((shifts, bit_depth))
+        }
+    });
+
+    // Steps are only fused into the expansion if it is followed by the other steps directly.
+    let changes_expanded_samples = stage != TransformStage::All
+        || widen
+        || compositing.is_some()
+        || strip_alpha
+        || srgb_conversion.is_some()
+        || gamma_table.is_some()
+        || shifts.is_some();
+
+    // Palette entries are converted to gray once, unless the samples are changed afterwards.
+    let gray_color_type = gray_color_type(color_type, transform);
+    let gray_weights = settings.gray_coefficients.weights();
+    let gray_in_expansion = gray_color_type.is_some()
+        && info.color_type == ColorType::Indexed
+        && !changes_expanded_samples;
+
+    // An alpha channel from `tRNS` or the palette is premultiplied while it is expanded, unless
+    // the samples are changed afterwards. Otherwise the row is premultiplied after those steps.
+    let premultiply = transform.contains(Transformations::PREMULTIPLY_ALPHA)
+        && alpha::has_alpha(color_type, bit_depth);
+    let premultiply_in_expansion = premultiply
+        && matches!(
+            info.color_type,
+            ColorType::Grayscale | ColorType::Rgb | ColorType::Indexed
+        )
+        && !changes_expanded_samples;
+
+    let mut transform_fn: RowTransformFn = if stage == TransformStage::Finishing {
+        Box::new(|input, output, _, _| output.copy_from_slice(&input[..output.len()]))
+    } else {
+        let expansion = create_expansion_fn(
+            info,
+            transform,
+            premultiply_in_expansion,
+            Some(gray_weights).filter(|_| gray_in_expansion),
+        )?;
+        Box::new(move |input, output, info, _| expansion(input, output, info))
+    };
+
+    // The expansion produces 8-bit samples which are widened in place.
+    if widen && stage != TransformStage::Finishing {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            let len = output.len() / 2;
+            previous(input, &mut output[..len], info, scratch);
+            widen_to_16(output);
+        });
+    }
+    if stage == TransformStage::Expansion {
+        return Ok(transform_fn);
+    }
+    // The number of bits per pixel of the input rows.
+    let input_bits_pp = match stage {
+        TransformStage::Finishing => expanded_color_type.samples() * bit_depth as usize,
+        _ => info.bits_per_pixel(),
+    };
+
+    let colors = color_type.samples();
+    if let Some(compositing) = compositing {
+        transform_fn = narrowing(transform_fn, colors + 1, colors, move |row, output| {
+            compositing.apply(row, output)
+        });
+    } else if strip_alpha {
+        let bytes = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+        transform_fn = narrowing(transform_fn, colors + 1, colors, move |row, output| {
+            for (pixel, output) in row
+                .chunks_exact((colors + 1) * bytes)
+                .zip(output.chunks_exact_mut(colors * bytes))
+            {
+                output.copy_from_slice(&pixel[..colors * bytes]);
+            }
+        });
+    }
+
+    if let Some(conversion) = srgb_conversion {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            previous(input, output, info, scratch);
+            conversion.apply(output, color_type, bit_depth);
+        });
+    }
+
+    if let Some(table) = gamma_table {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            previous(input, output, info, scratch);
+            color::apply_table(output, &table, color_type, bit_depth);
+        });
+    }
+
+    if let Some((shifts, bit_depth)) = shifts {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            previous(input, output, info, scratch);
+            shift_row(output, &shifts, bit_depth);
+        });
+    }
+
+    if let (Some(gray_color_type), false) = (gray_color_type, gray_in_expansion) {
+        let (rgb_samples, gray_samples) = (color_type.samples(), gray_color_type.samples());
+        transform_fn = narrowing(
+            transform_fn,
+            rgb_samples,
+            gray_samples,
+            move |row, output| gray::convert_row(row, output, color_type, bit_depth, gray_weights),
+        );
+    }
+    let color_type = gray_color_type.unwrap_or(color_type);
+
+    if premultiply && !premultiply_in_expansion {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            previous(input, output, info, scratch);
+            alpha::premultiply_row(output, color_type, bit_depth);
+        });
+    }
+
+    if let Some(depth) = packed_gray_depth(info, settings) {
+        let depth = depth as u8;
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            // One byte per pixel, for as many pixels as both the input and output have room for.
+            let pixels =
+                (output.len() * 8 / usize::from(depth)).min(input.len() * 8 / input_bits_pp);
+            let mut gray = scratch.take(pixels);
+            previous(input, &mut gray, info, scratch);
+            pack_gray(&gray, output, depth);
+            scratch.give(gray);
+        });
+    }
+
+    // Duplicating the gray samples comes last, so that the steps above work on a single channel.
+    if let Some(channels) = gray_to_rgb_channels(color_type, transform) {
+        let bytes = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            let gray_len = output.len() / (channels + 2) * channels;
+            previous(input, &mut output[..gray_len], info, scratch);
+            expand_gray_to_rgb(output, channels, bytes);
+        });
+    }
+
+    let (output_color_type, _) = output_color_type(info, transform, settings);
+    if let Some(swizzle) = swizzle::Swizzle::new(output_color_type, bit_depth, transform) {
+        let previous = transform_fn;
+        transform_fn = Box::new(move |input, output, info, scratch| {
+            previous(input, output, info, scratch);
+            swizzle.apply(output);
+        });
+    }
+
+    Ok(transform_fn)
+}
+
+/// Returns the color type and bit depth of the rows produced by `TransformStage::Expansion`.
+pub(crate) fn expansion_color_type(
     info: &Info,
     transform: Transformations,
+    settings: &TransformSettings,
+) -> (ColorType, BitDepth) {
+    expanded_color_type(info, effective_transform(info, transform, settings))
+}
+
+/// Returns the color type and bit depth of the rows produced by the transformations.
+pub(crate) fn output_color_type(
+    info: &Info,
+    transform: Transformations,
+    settings: &TransformSettings,
+) -> (ColorType, BitDepth) {
+    if let Some(bit_depth) = packed_gray_depth(info, settings) {
+        return (ColorType::Grayscale, bit_depth);
+    }
+    let transform = effective_transform(info, transform, settings);
+    let (color_type, bit_depth) = expanded_color_type(info, transform);
+    let composited = background(settings).and_then(|background| {
+        background::background_color(
+            info,
+            transform,
+            background,
+            settings.gray_coefficients,
+            color_type,
+            bit_depth,
+        )
+    });
+    let color_type = if composited.is_some() || transform.contains(Transformations::STRIP_ALPHA) {
+        without_alpha(color_type)
+    } else {
+        color_type
+    };
+    let color_type = gray_color_type(color_type, transform).unwrap_or(color_type);
+    match gray_to_rgb_channels(color_type, transform) {
+        Some(1) => (ColorType::Rgb, bit_depth),
+        Some(_) => (ColorType::Rgba, bit_depth),
+        None => (color_type, bit_depth),
+    }
+}
+
+/// Checks that the output format requested with `Decoder::set_output_format` can be produced.
+pub(crate) fn check_output_format(
+    info: &Info,
+    settings: &TransformSettings,
+) -> Result<(), DecodingError> {
+    match settings.output_format {
+        Some((color_type, bit_depth))
+            if color_type.is_combination_invalid(bit_depth)
+                || (color_type == ColorType::Indexed
+                    && (info.color_type != ColorType::Indexed
+                        || (bit_depth != info.bit_depth && bit_depth != BitDepth::Eight))) =>
+        {
+            Err(DecodingError::Parameter(
+                ParameterErrorKind::OutputFormat {
+                    color_type,
+                    bit_depth,
+                }
+                .into(),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The flags that change the color type or bit depth, replaced by those for the output format.
+const LAYOUT_FLAGS: Transformations = Transformations::EXPAND
+    .union(Transformations::PACKING)
+    .union(Transformations::ALPHA)
+    .union(Transformations::STRIP_ALPHA)
+    .union(Transformations::STRIP_16)
+    .union(Transformations::SCALE_16)
+    .union(Transformations::EXPAND_16)
+    .union(Transformations::GRAY_TO_RGB)
+    .union(Transformations::RGB_TO_GRAY);
+
+/// Returns the transformations to apply, with the flags implied by others and those producing
+/// the output format requested with `Decoder::set_output_format`.
+fn effective_transform(
+    info: &Info,
+    mut transform: Transformations,
+    settings: &TransformSettings,
+) -> Transformations {
+    match settings.output_format {
+        // Palette indices are kept as they are, as are packed gray samples of the requested depth.
+        Some((color_type, bit_depth))
+            if color_type == ColorType::Indexed
+                || ((color_type, bit_depth) == (info.color_type, info.bit_depth)
+                    && (bit_depth as u8) < 8) =>
+        {
+            transform.remove(LAYOUT_FLAGS);
+            if bit_depth != info.bit_depth {
+                transform.insert(Transformations::PACKING);
+            }
+        }
+        Some((color_type, bit_depth)) => {
+            transform.remove(LAYOUT_FLAGS);
+            transform.insert(Transformations::EXPAND);
+            transform.insert(match bit_depth {
+                BitDepth::Sixteen => Transformations::EXPAND_16,
+                _ => Transformations::SCALE_16,
+            });
+            transform.insert(match color_type {
+                ColorType::Grayscale | ColorType::GrayscaleAlpha => Transformations::RGB_TO_GRAY,
+                _ => Transformations::GRAY_TO_RGB,
+            });
+            transform.insert(match color_type {
+                ColorType::GrayscaleAlpha | ColorType::Rgba => Transformations::ALPHA,
+                _ => Transformations::STRIP_ALPHA,
+            });
+            // Shifting the samples before they are packed into fewer bits would discard them.
+            if (bit_depth as u8) < 8 {
+                transform.remove(Transformations::SHIFT);
+            }
+        }
+        None => {}
+    }
+    with_implied_flags(transform)
+}
+
+/// Returns the bit depth of gray output with less than 8 bits per sample that is packed from
+/// 8-bit samples, for an output format requested with `Decoder::set_output_format`.
+fn packed_gray_depth(info: &Info, settings: &TransformSettings) -> Option<BitDepth> {
+    match settings.output_format {
+        Some((ColorType::Grayscale, bit_depth))
+            if (bit_depth as u8) < 8
+                && (info.color_type, info.bit_depth) != (ColorType::Grayscale, bit_depth) =>
+        {
+            Some(bit_depth)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the background to composite over, unless the output format keeps alpha.
+fn background(settings: &TransformSettings) -> Option<Background> {
+    match settings.output_format {
+        Some((ColorType::GrayscaleAlpha | ColorType::Rgba, _)) => None,
+        _ => settings.background,
+    }
+}
+
+/// Wraps `previous` for a step that produces fewer samples per pixel. `previous` writes rows with
+/// `wide` samples per pixel into a temporary row, which `convert` turns into output rows with
+/// `narrow` samples per pixel.
+fn narrowing(
+    previous: RowTransformFn,
+    wide: usize,
+    narrow: usize,
+    convert: impl Fn(&[u8], &mut [u8]) + Send + Sync + 'static,
+) -> RowTransformFn {
+    Box::new(move |input, output, info, scratch| {
+        let mut row = scratch.take(output.len() / narrow * wide);
+        previous(input, &mut row, info, scratch);
+        convert(&row, output);
+        scratch.give(row);
+    })
+}
+
+/// Returns the color type without its alpha channel.
+fn without_alpha(color_type: ColorType) -> ColorType {
+    match color_type {
+        ColorType::GrayscaleAlpha => ColorType::Grayscale,
+        ColorType::Rgba => ColorType::Rgb,
+        color_type => color_type,
+    }
+}
+
+/// Adds the flags implied by others: `EXPAND_16` and `RGB_TO_GRAY` imply `EXPAND`, `EXPAND_16`
+/// overrides `STRIP_16` and `SCALE_16`, which overrides `STRIP_16`.
+fn with_implied_flags(mut transform: Transformations) -> Transformations {
+    if transform.contains(Transformations::RGB_TO_GRAY) {
+        transform.insert(Transformations::EXPAND);
+    }
+    if transform.contains(Transformations::EXPAND_16) {
+        transform.insert(Transformations::EXPAND);
+        transform.remove(Transformations::STRIP_16 | Transformations::SCALE_16);
+    }
+    if transform.contains(Transformations::SCALE_16) {
+        transform.remove(Transformations::STRIP_16);
+    }
+    transform
+}
+
+/// Returns whether the expanded samples are widened from 8 to 16 bits for
+/// `Transformations::EXPAND_16`.
+fn widens_to_16(info: &Info, transform: Transformations) -> bool {
+    transform.contains(Transformations::EXPAND_16) && info.bit_depth != BitDepth::Sixteen
+}
+
+/// Returns whether 1, 2 and 4-bit palette indices or gray samples are unpacked to a byte each,
+/// without scaling them, for `Transformations::PACKING`.
+fn unpacks_samples(info: &Info, transform: Transformations) -> bool {
+    let expand = transform.intersects(Transformations::EXPAND | Transformations::ALPHA);
+    transform.contains(Transformations::PACKING)
+        && (info.bit_depth as u8) < 8
+        && match info.color_type {
+            ColorType::Indexed => !expand,
+            ColorType::Grayscale => !expand && !transform.contains(Transformations::GRAY_TO_RGB),
+            _ => false,
+        }
+}
+
+/// Returns the color type and bit depth after the expansion of the samples, before gray samples
+/// are duplicated for `Transformations::GRAY_TO_RGB`.
+fn expanded_color_type(info: &Info, transform: Transformations) -> (ColorType, BitDepth) {
+    use crate::common::ColorType::*;
+    let t = transform;
+    if t == Transformations::IDENTITY {
+        (info.color_type, info.bit_depth)
+    } else {
+        let bits = match info.bit_depth as u8 {
+            _ if widens_to_16(info, t) => 16,
+            16 if t.intersects(Transformations::STRIP_16 | Transformations::SCALE_16) => 8,
+            n if n < 8
+                && (t.contains(Transformations::EXPAND)
+                    || t.contains(Transformations::ALPHA)
+                    || (t.contains(Transformations::GRAY_TO_RGB)
+                        && info.color_type == Grayscale)) =>
+            {
+                8
+            }
+            n if n < 8 && unpacks_samples(info, t) => 8,
+            n => n,
+        };
+        let color_type =
+            if t.contains(Transformations::EXPAND) || t.contains(Transformations::ALPHA) {
+                let has_trns = info.trns.is_some() || t.contains(Transformations::ALPHA);
+                match info.color_type {
+                    Grayscale if has_trns => GrayscaleAlpha,
+                    Rgb if has_trns => Rgba,
+                    Indexed if has_trns => Rgba,
+                    Indexed => Rgb,
+                    ct => ct,
+                }
+            } else {
+                info.color_type
+            };
+        (color_type, BitDepth::from_u8(bits).unwrap())
+    }
+}
+
+/// Returns the gray color type to convert RGB or RGBA samples to for
+/// `Transformations::RGB_TO_GRAY`, or `None` if they are not converted.
+fn gray_color_type(color_type: ColorType, transform: Transformations) -> Option<ColorType> {
+    if !transform.contains(Transformations::RGB_TO_GRAY) {
+        return None;
+    }
+    match color_type {
+        ColorType::Rgb => Some(ColorType::Grayscale),
+        ColorType::Rgba => Some(ColorType::GrayscaleAlpha),
+        _ => None,
+    }
+}
+
+/// Returns the number of gray channels, including alpha, to expand to RGB or RGBA for
+/// `Transformations::GRAY_TO_RGB`, or `None` if the rows are not to be expanded.
+fn gray_to_rgb_channels(color_type: ColorType, transform: Transformations) -> Option<usize> {
+    if !transform.contains(Transformations::GRAY_TO_RGB) {
+        return None;
+    }
+    match color_type {
+        ColorType::Grayscale => Some(1),
+        ColorType::GrayscaleAlpha => Some(2),
+        _ => None,
+    }
+}
+
+/// Returns the function for the transformations that change the layout of the samples, i.e.
+/// palette and `tRNS` expansion, unpacking and stripping to 8 bits.
+///
+/// If `premultiply` is set, the alpha channel created from `tRNS` or the palette is premultiplied.
+/// With `gray_weights` palette entries are converted to gray.
+fn create_expansion_fn(
+    info: &Info,
+    transform: Transformations,
+    premultiply: bool,
+    gray_weights: Option<[f32; 3]>,
 ) -> Result<TransformFn, DecodingError> {
     let color_type = info.color_type;
     let bit_depth = info.bit_depth as u8;
@@ -27,55 +592,218 @@ pub fn create_transform_fn(
     let expand =
         transform.contains(Transformations::EXPAND) || transform.contains(Transformations::ALPHA);
     let strip16 = bit_depth == 16 && transform.contains(Transformations::STRIP_16);
+    let scale16 = bit_depth == 16 && transform.contains(Transformations::SCALE_16);
+    let unpack_gray = expand || transform.contains(Transformations::GRAY_TO_RGB);
     match color_type {
         ColorType::Indexed if expand => {
             if info.palette.is_none() {
-                return Err(DecodingError::Format(
+                Err(DecodingError::Format(
                     FormatErrorInner::PaletteRequired.into(),
-                ));
+                ))
             } else if let BitDepth::Sixteen = info.bit_depth {
                 // This should have been caught earlier but let's check again. Can't hurt.
-                return Err(DecodingError::Format(
+                Err(DecodingError::Format(
                     FormatErrorInner::InvalidColorBitDepth {
                         color_type: ColorType::Indexed,
                         bit_depth: BitDepth::Sixteen,
                     }
                     .into(),
-                ));
+                ))
             } else {
-                Ok(if trns {
-                    palette::create_expansion_into_rgba8(info)
+                Ok(if let Some(weights) = gray_weights {
+                    palette::create_expansion_into_gray8(info, weights, trns, premultiply)
+                } else if trns {
+                    palette::create_expansion_into_rgba8(info, premultiply)
                 } else {
                     palette::create_expansion_into_rgb8(info)
                 })
             }
         }
-        ColorType::Grayscale | ColorType::GrayscaleAlpha if bit_depth < 8 && expand => {
-            Ok(Box::new(if trns {
-                expand_gray_u8_with_trns
-            } else {
-                expand_gray_u8
-            }))
+        ColorType::Grayscale | ColorType::GrayscaleAlpha if bit_depth < 8 && unpack_gray => {
+            Ok(match (expand && trns, premultiply) {
+                (true, true) => Box::new(expand_gray_u8_with_trns::<true>),
+                (true, false) => Box::new(expand_gray_u8_with_trns::<false>),
+                (false, _) => Box::new(expand_gray_u8),
+            })
         }
         ColorType::Grayscale | ColorType::Rgb if expand && trns => {
-            Ok(Box::new(if bit_depth == 8 {
-                expand_trns_line
-            } else if strip16 {
-                expand_trns_and_strip_line16
+            if premultiply {
+                Ok(create_trns_expansion_fn::<true>(
+                    bit_depth, strip16, scale16,
+                ))
             } else {
-                assert_eq!(bit_depth, 16);
-                expand_trns_line16
-            }))
+                Ok(create_trns_expansion_fn::<false>(
+                    bit_depth, strip16, scale16,
+                ))
+            }
         }
         ColorType::Grayscale | ColorType::GrayscaleAlpha | ColorType::Rgb | ColorType::Rgba
             if strip16 =>
         {
             Ok(Box::new(transform_row_strip16))
         }
+        ColorType::Grayscale | ColorType::GrayscaleAlpha | ColorType::Rgb | ColorType::Rgba
+            if scale16 =>
+        {
+            Ok(Box::new(transform_row_scale16))
+        }
+        ColorType::Indexed | ColorType::Grayscale if unpacks_samples(info, transform) => {
+            Ok(Box::new(unpack_samples))
+        }
         _ => Ok(Box::new(copy_row)),
     }
 }
 
+/// Returns the function expanding gray or RGB samples with an alpha channel from `tRNS`, where
+/// the color of transparent pixels is zeroed if `PREMULTIPLY` is set.
+fn create_trns_expansion_fn<const PREMULTIPLY: bool>(
+    bit_depth: u8,
+    strip16: bool,
+    scale16: bool,
+) -> TransformFn {
+    Box::new(if bit_depth == 8 {
+        expand_trns_line::<PREMULTIPLY>
+    } else if strip16 {
+        expand_trns_and_strip_line16::<PREMULTIPLY>
+    } else if scale16 {
+        expand_trns_and_scale_line16::<PREMULTIPLY>
+    } else {
+        assert_eq!(bit_depth, 16);
+        expand_trns_line16::<PREMULTIPLY>
+    })
+}
+
+/// Computes how far each output channel has to be shifted right for `Transformations::SHIFT`,
+/// together with the bit depth of the output samples.
+///
+/// Returns `None` if no sample needs to be shifted.
+fn significant_bit_shifts(info: &Info, transform: Transformations) -> Option<(Vec<u8>, u8)> {
+    if !transform.contains(Transformations::SHIFT) {
+        return None;
+    }
+    let sbit = info.sbit.as_deref()?;
+    let bit_depth = info.bit_depth as u8;
+    let trns = info.trns.is_some() || transform.contains(Transformations::ALPHA);
+    let expand =
+        transform.contains(Transformations::EXPAND) || transform.contains(Transformations::ALPHA);
+
+    // Expanding adds an alpha channel from `tRNS` to gray and color images of any bit depth.
+    let trns_alpha =
+        expand && trns && matches!(info.color_type, ColorType::Grayscale | ColorType::Rgb);
+
+    let (output_depth, adds_alpha) = match info.color_type {
+        // Palette indices are not samples, only the expanded palette entries can be shifted.
+        ColorType::Indexed if !expand => return None,
+        ColorType::Indexed => (8, trns),
+        _ if bit_depth == 16
+            && transform.intersects(Transformations::STRIP_16 | Transformations::SCALE_16) =>
+        {
+            (8, trns_alpha)
+        }
+        _ if bit_depth < 8 && expand => (8, trns),
+        _ if bit_depth < 8 && transform.contains(Transformations::GRAY_TO_RGB) => (8, false),
+        _ => (bit_depth, trns_alpha),
+    };
+
+    let output_depth = if widens_to_16(info, transform) {
+        16
+    } else {
+        output_depth
+    };
+
+    // Expanding and stripping keeps the most significant bits, so the shift is relative to the
+    // output depth. An alpha channel created from `tRNS` has no significant bits to restore.
+    let mut shifts: Vec<u8> = sbit
+        .iter()
+        .map(|&bits| output_depth - bits.min(output_depth))
+        .collect();
+    if adds_alpha {
+        shifts.push(0);
+    }
+
+    if shifts.iter().all(|&shift| shift == 0) {
+        None
+    } else if unpacks_samples(info, transform) {
+        // The unpacked samples keep their values, each in a byte of its own.
+        Some((shifts, 8))
+    } else {
+        Some((shifts, output_depth))
+    }
+}
+
+/// Shifts each sample of an output row right by the amount for its channel.
+fn shift_row(row: &mut [u8], shifts: &[u8], bit_depth: u8) {
+    match bit_depth {
+        16 => {
+            for pixel in row.chunks_exact_mut(2 * shifts.len()) {
+                for (sample, &shift) in pixel.chunks_exact_mut(2).zip(shifts) {
+                    let value = u16::from_be_bytes([sample[0], sample[1]]) >> shift;
+                    sample.copy_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        8 => {
+            for pixel in row.chunks_exact_mut(shifts.len()) {
+                for (sample, &shift) in pixel.iter_mut().zip(shifts) {
+                    *sample >>= shift;
+                }
+            }
+        }
+        _ => {
+            // Packed grayscale samples, there is a single channel.
+            let shift = shifts[0];
+            let mask = ((1u16 << bit_depth) - 1) as u8;
+            for byte in row.iter_mut() {
+                let mut shifted = 0;
+                for pos in (0..8).step_by(bit_depth.into()) {
+                    shifted |= (((*byte >> pos) & mask) >> shift) << pos;
+                }
+                *byte = shifted;
+            }
+        }
+    }
+}
+
+/// Expands a row of gray or gray-alpha samples, stored at the start of the row, to RGB or RGBA
+/// in place. Works from the end of the row so that no sample is overwritten before it is read.
+fn expand_gray_to_rgb(row: &mut [u8], channels: usize, bytes: usize) {
+    let pixels = row.len() / ((channels + 2) * bytes);
+    for pixel in (0..pixels).rev() {
+        let src = pixel * channels * bytes;
+        let dst = pixel * (channels + 2) * bytes;
+        if channels == 2 {
+            row.copy_within(src + bytes..src + 2 * bytes, dst + 3 * bytes);
+        }
+        for i in (0..3).rev() {
+            row.copy_within(src..src + bytes, dst + i * bytes);
+        }
+    }
+}
+
+/// Packs 8-bit gray samples into samples of `bit_depth` bits, rounding to the nearest value.
+fn pack_gray(gray: &[u8], output: &mut [u8], bit_depth: u8) {
+    let max = (1u16 << bit_depth) - 1;
+    let per_byte = usize::from(8 / bit_depth);
+    for (byte, samples) in output.iter_mut().zip(gray.chunks(per_byte)) {
+        *byte = 0;
+        for (i, &sample) in samples.iter().enumerate() {
+            let value = (u16::from(sample) * max + 127) / 255;
+            *byte |= (value as u8) << (8 - bit_depth as usize * (i + 1));
+        }
+    }
+}
+
+/// Widens the 8-bit samples stored in the first half of the row to 16 bits in place, replicating
+/// the bits so that the maximum value maps to the maximum value.
+fn widen_to_16(row: &mut [u8]) {
+    let len = row.len() / 2;
+    for i in (0..len).rev() {
+        let value = row[i];
+        row[2 * i] = value;
+        row[2 * i + 1] = value;
+    }
+}
+
 fn copy_row(row: &[u8], output_buffer: &mut [u8], _: &Info) {
     output_buffer.copy_from_slice(row);
 }
@@ -86,6 +814,18 @@ fn transform_row_strip16(row: &[u8], output_buffer: &mut [u8], _: &Info) {
     }
 }
 
+/// Scales a 16-bit sample to 8 bits, rounding to the nearest value.
+fn scale_16_to_8(sample: &[u8]) -> u8 {
+    let value = u32::from(u16::from_be_bytes([sample[0], sample[1]]));
+    ((value * 255 + 32895) >> 16) as u8
+}
+
+fn transform_row_scale16(row: &[u8], output_buffer: &mut [u8], _: &Info) {
+    for (output, sample) in output_buffer.iter_mut().zip(row.chunks_exact(2)) {
+        *output = scale_16_to_8(sample);
+    }
+}
+
 #[inline(always)]
 fn unpack_bits<F>(input: &[u8], output: &mut [u8], channels: usize, bit_depth: u8, func: F)
 where
@@ -134,27 +874,40 @@ where
     }
 }
 
-fn expand_trns_line(input: &[u8], output: &mut [u8], info: &Info) {
+// With `PREMULTIPLY` set, the color of pixels made transparent by `tRNS` is zeroed. The alpha from
+// `tRNS` is either zero or the maximum, so this is all that premultiplying changes.
+
+fn expand_trns_line<const PREMULTIPLY: bool>(input: &[u8], output: &mut [u8], info: &Info) {
     let channels = info.color_type.samples();
     let trns = info.trns.as_deref();
     for (input, output) in input
         .chunks_exact(channels)
         .zip(output.chunks_exact_mut(channels + 1))
     {
-        output[..channels].copy_from_slice(input);
-        output[channels] = if Some(input) == trns { 0 } else { 0xFF };
+        let transparent = Some(input) == trns;
+        if PREMULTIPLY && transparent {
+            output[..channels].fill(0);
+        } else {
+            output[..channels].copy_from_slice(input);
+        }
+        output[channels] = if transparent { 0 } else { 0xFF };
     }
 }
 
-fn expand_trns_line16(input: &[u8], output: &mut [u8], info: &Info) {
+fn expand_trns_line16<const PREMULTIPLY: bool>(input: &[u8], output: &mut [u8], info: &Info) {
     let channels = info.color_type.samples();
     let trns = info.trns.as_deref();
     for (input, output) in input
         .chunks_exact(channels * 2)
         .zip(output.chunks_exact_mut(channels * 2 + 2))
     {
-        output[..channels * 2].copy_from_slice(input);
-        if Some(input) == trns {
+        let transparent = Some(input) == trns;
+        if PREMULTIPLY && transparent {
+            output[..channels * 2].fill(0);
+        } else {
+            output[..channels * 2].copy_from_slice(input);
+        }
+        if transparent {
             output[channels * 2] = 0;
             output[channels * 2 + 1] = 0
         } else {
@@ -164,20 +917,53 @@ fn expand_trns_line16(input: &[u8], output: &mut [u8], info: &Info) {
     }
 }
 
-fn expand_trns_and_strip_line16(input: &[u8], output: &mut [u8], info: &Info) {
+fn expand_trns_and_strip_line16<const PREMULTIPLY: bool>(
+    input: &[u8],
+    output: &mut [u8],
+    info: &Info,
+) {
+    expand_trns_and_reduce_line16::<PREMULTIPLY>(input, output, info, |sample| sample[0]);
+}
+
+fn expand_trns_and_scale_line16<const PREMULTIPLY: bool>(
+    input: &[u8],
+    output: &mut [u8],
+    info: &Info,
+) {
+    expand_trns_and_reduce_line16::<PREMULTIPLY>(input, output, info, scale_16_to_8);
+}
+
+#[inline(always)]
+fn expand_trns_and_reduce_line16<const PREMULTIPLY: bool>(
+    input: &[u8],
+    output: &mut [u8],
+    info: &Info,
+    reduce: impl Fn(&[u8]) -> u8,
+) {
     let channels = info.color_type.samples();
     let trns = info.trns.as_deref();
     for (input, output) in input
         .chunks_exact(channels * 2)
         .zip(output.chunks_exact_mut(channels + 1))
     {
-        for i in 0..channels {
-            output[i] = input[i * 2];
+        let transparent = Some(input) == trns;
+        if PREMULTIPLY && transparent {
+            output[..channels].fill(0);
+        } else {
+            for (output, sample) in output.iter_mut().zip(input.chunks_exact(2)) {
+                *output = reduce(sample);
+            }
         }
-        output[channels] = if Some(input) == trns { 0 } else { 0xFF };
+        output[channels] = if transparent { 0 } else { 0xFF };
     }
 }
 
+fn unpack_samples(row: &[u8], buffer: &mut [u8], info: &Info) {
+    unpack_bits(row, buffer, 1, info.bit_depth as u8, |val, chunk| {
+        chunk[0] = val
+    });
+}
+
 fn expand_gray_u8(row: &[u8], buffer: &mut [u8], info: &Info) {
     let scaling_factor = (255) / ((1u16 << info.bit_depth as u8) - 1) as u8;
     unpack_bits(row, buffer, 1, info.bit_depth as u8, |val, chunk| {
@@ -185,7 +971,7 @@ fn expand_gray_u8(row: &[u8], buffer: &mut [u8], info: &Info) {
     });
 }
 
-fn expand_gray_u8_with_trns(row: &[u8], buffer: &mut [u8], info: &Info) {
+fn expand_gray_u8_with_trns<const PREMULTIPLY: bool>(row: &[u8], buffer: &mut [u8], info: &Info) {
     let scaling_factor = (255) / ((1u16 << info.bit_depth as u8) - 1) as u8;
     let trns = info.trns.as_deref();
     unpack_bits(row, buffer, 2, info.bit_depth as u8, |pixel, chunk| {
@@ -198,6 +984,1300 @@ fn expand_gray_u8_with_trns(row: &[u8], buffer: &mut [u8], info: &Info) {
         } else {
             0xFF
         };
-        chunk[0] = pixel * scaling_factor
+        chunk[0] = if PREMULTIPLY && chunk[1] == 0 {
+            0
+        } else {
+            pixel * scaling_factor
+        }
     });
 }
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::scale_16_to_8;
+    use crate::{
+        Background, BackgroundColor, BitDepth, ColorSpacePreset, ColorType, Decoder, DecodingError,
+        Encoder, GrayCoefficients, Info, ScaledFloat, SourceChromaticities, SrgbRenderingIntent,
+        Transformations,
+    };
+
+    fn decode(path: &str, transform: Transformations) -> Vec<u8> {
+        decode_with(path, |decoder| decoder.set_transformations(transform)).0
+    }
+
+    fn decode_with_gamma(
+        path: &str,
+        transform: Transformations,
+        display_gamma: Option<f32>,
+    ) -> Vec<u8> {
+        decode_with(path, |decoder| {
+            decoder.set_transformations(transform);
+            decoder.set_display_gamma(display_gamma);
+        })
+        .0
+    }
+
+    /// Decodes the first frame with the decoder set up by `configure`, returning it with the
+    /// output color type and bit depth.
+    fn decode_with(
+        path: &str,
+        configure: impl FnOnce(&mut Decoder<File>),
+    ) -> (Vec<u8>, (ColorType, BitDepth)) {
+        let mut decoder = Decoder::new(File::open(path).unwrap());
+        configure(&mut decoder);
+        let mut reader = decoder.read_info().unwrap();
+        let output_color_type = reader.output_color_type();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        assert_eq!((info.color_type, info.bit_depth), output_color_type);
+        (buf, output_color_type)
+    }
+
+    /// Reads the metadata of the file, ignoring broken checksums of some test images.
+    fn read_info(path: &str) -> Info<'static> {
+        let mut decoder = Decoder::new(File::open(path).unwrap());
+        decoder.ignore_checksums(true);
+        decoder.read_info().unwrap().info().clone()
+    }
+
+    fn assert_close(actual: u16, expected: f32) {
+        assert!(
+            (f32::from(actual) - expected).abs() <= 1.0,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    /// Duplicates the gray sample of each pixel, keeping alpha.
+    fn gray_to_rgb(gray: &[u8], channels: usize, bytes: usize) -> Vec<u8> {
+        gray.chunks_exact(channels * bytes)
+            .flat_map(|pixel| {
+                let (gray, alpha) = pixel.split_at(bytes);
+                [gray, gray, gray, alpha].concat()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gray_to_rgb_all_depths() {
+        for (path, bytes) in [
+            ("tests/pngsuite/basn0g01.png", 1),
+            ("tests/pngsuite/basn0g02.png", 1),
+            ("tests/pngsuite/basn0g04.png", 1),
+            ("tests/pngsuite/basn0g08.png", 1),
+            ("tests/pngsuite/basn0g16.png", 2),
+        ] {
+            let gray = decode(path, Transformations::EXPAND);
+            let rgb = decode(path, Transformations::GRAY_TO_RGB);
+            assert_eq!(rgb, gray_to_rgb(&gray, 1, bytes), "{}", path);
+        }
+
+        let gray = decode("tests/pngsuite/basn4a16.png", Transformations::STRIP_16);
+        let rgb = decode(
+            "tests/pngsuite/basn4a16.png",
+            Transformations::STRIP_16 | Transformations::GRAY_TO_RGB,
+        );
+        assert_eq!(rgb, gray_to_rgb(&gray, 2, 1));
+    }
+
+    #[test]
+    fn gray_to_rgb_with_trns() {
+        for (path, bytes) in [
+            ("tests/pngsuite/tbbn0g04.png", 1),
+            ("tests/pngsuite/tbwn0g16.png", 2),
+        ] {
+            let gray = decode(path, Transformations::EXPAND);
+            let rgb = decode(path, Transformations::EXPAND | Transformations::GRAY_TO_RGB);
+            assert_eq!(rgb, gray_to_rgb(&gray, 2, bytes), "{}", path);
+        }
+
+        // Without `EXPAND` the transparency stays in the `tRNS` chunk.
+        let path = "tests/pngsuite/tbbn0g04.png";
+        let (_, output_color_type) = decode_with(path, |decoder| {
+            decoder.set_transformations(Transformations::GRAY_TO_RGB)
+        });
+        assert_eq!(output_color_type, (ColorType::Rgb, BitDepth::Eight));
+    }
+
+    #[test]
+    fn expand_16_replicates_bits() {
+        for path in [
+            "tests/pngsuite/basn0g01.png",
+            "tests/pngsuite/basn0g04.png",
+            "tests/pngsuite/basn3p02.png",
+            "tests/pngsuite/basn4a08.png",
+            "tests/pngsuite/tbbn0g04.png",
+            "tests/pngsuite/tbrn2c08.png",
+            "tests/pngsuite/tbbn3p08.png",
+        ] {
+            let expanded = decode(path, Transformations::EXPAND);
+            let widened = decode(path, Transformations::EXPAND_16);
+            let replicated: Vec<u8> = expanded.iter().flat_map(|&v| [v, v]).collect();
+            assert_eq!(widened, replicated, "{}", path);
+        }
+
+        // 16-bit images are kept as they are, even if they would be stripped.
+        let path = "tests/pngsuite/basn2c16.png";
+        assert_eq!(
+            decode(path, Transformations::EXPAND_16 | Transformations::STRIP_16),
+            decode(path, Transformations::IDENTITY)
+        );
+
+        let (rgb, output_color_type) = decode_with("tests/pngsuite/basn3p02.png", |decoder| {
+            decoder.set_transformations(Transformations::EXPAND_16)
+        });
+        assert_eq!(output_color_type, (ColorType::Rgb, BitDepth::Sixteen));
+        assert_eq!(rgb.len(), 32 * 32 * 6);
+    }
+
+    #[test]
+    fn expand_16_with_gray_to_rgb_and_shift() {
+        let path = "tests/pngsuite/basn0g02.png";
+        let gray = decode(path, Transformations::EXPAND_16);
+        let rgb = decode(
+            path,
+            Transformations::EXPAND_16 | Transformations::GRAY_TO_RGB,
+        );
+        assert_eq!(rgb, gray_to_rgb(&gray, 1, 2));
+
+        // 5 significant bits of 8, restored from the widened samples.
+        let path = "tests/pngsuite/cs5n2c08.png";
+        let shifted = decode(path, Transformations::SHIFT);
+        let widened = decode(path, Transformations::EXPAND_16 | Transformations::SHIFT);
+        for (shifted, widened) in shifted.iter().zip(widened.chunks_exact(2)) {
+            assert_eq!(
+                u16::from(*shifted),
+                u16::from_be_bytes([widened[0], widened[1]])
+            );
+        }
+    }
+
+    #[test]
+    fn scale_16_rounds_to_nearest() {
+        for value in 0..=u16::MAX {
+            let expected = (f64::from(value) * 255.0 / 65535.0).round() as u8;
+            assert_eq!(super::scale_16_to_8(&value.to_be_bytes()), expected);
+        }
+
+        for (path, transform) in [
+            ("tests/pngsuite/basn0g16.png", Transformations::IDENTITY),
+            ("tests/pngsuite/basn2c16.png", Transformations::IDENTITY),
+            ("tests/pngsuite/basn4a16.png", Transformations::IDENTITY),
+            ("tests/pngsuite/basn6a16.png", Transformations::IDENTITY),
+            ("tests/pngsuite/tbwn0g16.png", Transformations::EXPAND),
+            ("tests/pngsuite/tbbn2c16.png", Transformations::EXPAND),
+        ] {
+            let wide = decode(path, transform);
+            let scaled: Vec<u8> = wide.chunks_exact(2).map(super::scale_16_to_8).collect();
+            assert_eq!(
+                decode(path, transform | Transformations::SCALE_16),
+                scaled,
+                "{}",
+                path
+            );
+            assert_eq!(
+                decode(
+                    path,
+                    transform | Transformations::SCALE_16 | Transformations::STRIP_16
+                ),
+                scaled,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn swizzle_channels() {
+        let swizzled = |path: &str, transform: Transformations, map: fn(&[u8]) -> Vec<u8>| {
+            let unswizzled = decode(
+                path,
+                transform
+                    - Transformations::BGR
+                    - Transformations::SWAP_ALPHA
+                    - Transformations::INVERT_ALPHA,
+            );
+            let (swizzled, (color_type, bit_depth)) =
+                decode_with(path, |decoder| decoder.set_transformations(transform));
+            let pixel_size = color_type.samples() * (bit_depth as usize / 8);
+            let expected: Vec<u8> = unswizzled.chunks_exact(pixel_size).flat_map(map).collect();
+            assert_eq!(swizzled, expected, "{}", path);
+        };
+
+        swizzled(
+            "tests/pngsuite/basn6a08.png",
+            Transformations::BGR | Transformations::SWAP_ALPHA,
+            |p| vec![p[3], p[2], p[1], p[0]],
+        );
+        swizzled(
+            "tests/pngsuite/basn6a16.png",
+            Transformations::BGR | Transformations::INVERT_ALPHA,
+            |p| [&p[4..6], &p[2..4], &p[..2], &[!p[6], !p[7]]].concat(),
+        );
+        swizzled(
+            "tests/pngsuite/basn4a08.png",
+            Transformations::SWAP_ALPHA | Transformations::INVERT_ALPHA,
+            |p| vec![!p[1], p[0]],
+        );
+        swizzled(
+            "tests/pngsuite/basn2c08.png",
+            Transformations::BGR | Transformations::SWAP_ALPHA,
+            |p| vec![p[2], p[1], p[0]],
+        );
+        swizzled(
+            "tests/pngsuite/tbbn3p08.png",
+            Transformations::EXPAND | Transformations::BGR | Transformations::SWAP_ALPHA,
+            |p| vec![p[3], p[2], p[1], p[0]],
+        );
+        swizzled(
+            "tests/pngsuite/basn0g08.png",
+            Transformations::GRAY_TO_RGB | Transformations::BGR | Transformations::INVERT_ALPHA,
+            |p| p.to_vec(),
+        );
+    }
+
+    #[test]
+    fn premultiply_alpha() {
+        let premultiplied = |path: &str, transform: Transformations| {
+            let (straight, (color_type, bit_depth)) =
+                decode_with(path, |decoder| decoder.set_transformations(transform));
+            let channels = color_type.samples();
+            let max = f64::from((1u32 << bit_depth as u8) - 1);
+            let expected: Vec<u8> = if bit_depth == BitDepth::Sixteen {
+                straight
+                    .chunks_exact(channels * 2)
+                    .flat_map(|p| {
+                        let alpha = f64::from(u16::from_be_bytes([p[p.len() - 2], p[p.len() - 1]]));
+                        let mut pixel = p.to_vec();
+                        for s in pixel[..(channels - 1) * 2].chunks_exact_mut(2) {
+                            let value = f64::from(u16::from_be_bytes([s[0], s[1]]));
+                            let value = (value * alpha / max).round() as u16;
+                            s.copy_from_slice(&value.to_be_bytes());
+                        }
+                        pixel
+                    })
+                    .collect()
+            } else {
+                straight
+                    .chunks_exact(channels)
+                    .flat_map(|p| {
+                        let alpha = f64::from(p[channels - 1]);
+                        let mut pixel = p.to_vec();
+                        for s in &mut pixel[..channels - 1] {
+                            *s = (f64::from(*s) * alpha / max).round() as u8;
+                        }
+                        pixel
+                    })
+                    .collect()
+            };
+            let actual = decode(path, transform | Transformations::PREMULTIPLY_ALPHA);
+            assert_eq!(actual, expected, "{} {:?}", path, transform);
+        };
+
+        premultiplied("tests/pngsuite/basn6a08.png", Transformations::IDENTITY);
+        premultiplied("tests/pngsuite/basn6a16.png", Transformations::IDENTITY);
+        premultiplied("tests/pngsuite/basn6a16.png", Transformations::SCALE_16);
+        premultiplied("tests/pngsuite/basn4a08.png", Transformations::GRAY_TO_RGB);
+        premultiplied("tests/pngsuite/basn4a16.png", Transformations::STRIP_16);
+        premultiplied("tests/pngsuite/basn6a08.png", Transformations::EXPAND_16);
+        // The alpha channel is created from the palette or `tRNS`.
+        premultiplied("tests/pngsuite/tbbn3p08.png", Transformations::EXPAND);
+        premultiplied("tests/pngsuite/tbbn3p08.png", Transformations::EXPAND_16);
+        premultiplied("tests/pngsuite/tbrn2c08.png", Transformations::EXPAND);
+        premultiplied("tests/pngsuite/tbbn0g04.png", Transformations::EXPAND);
+        premultiplied("tests/pngsuite/tbwn0g16.png", Transformations::EXPAND);
+        premultiplied(
+            "tests/pngsuite/tbwn0g16.png",
+            Transformations::normalize_to_color8(),
+        );
+        premultiplied(
+            "tests/pngsuite/tbbn2c16.png",
+            Transformations::EXPAND | Transformations::SCALE_16,
+        );
+
+        // Images without alpha are unchanged.
+        let path = "tests/pngsuite/basn2c08.png";
+        assert_eq!(
+            decode(path, Transformations::PREMULTIPLY_ALPHA),
+            decode(path, Transformations::IDENTITY)
+        );
+    }
+
+    fn decode_over_background(
+        png: &[u8],
+        transform: Transformations,
+        background: Background,
+        linear: bool,
+    ) -> (Vec<u8>, ColorType) {
+        let mut decoder = Decoder::new(png);
+        decoder.set_transformations(transform);
+        decoder.set_background(Some(background), linear);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).unwrap();
+        (buf, info.color_type)
+    }
+
+    /// Blends straight 8-bit or 16-bit pixels with alpha over a color, dropping alpha.
+    fn blend_over(pixels: &[u8], colors: usize, bytes: usize, background: [u16; 3]) -> Vec<u8> {
+        let max = (1u32 << (8 * bytes)) - 1;
+        let sample = |s: &[u8]| s.iter().fold(0, |v, &b| v << 8 | u32::from(b));
+        pixels
+            .chunks_exact((colors + 1) * bytes)
+            .flat_map(|p| {
+                let alpha = sample(&p[colors * bytes..]);
+                (0..colors).flat_map(move |i| {
+                    let value = sample(&p[i * bytes..][..bytes]);
+                    let bg = u32::from(background[i]);
+                    let blended = (value * alpha + bg * (max - alpha) + max / 2) / max;
+                    blended.to_be_bytes()[4 - bytes..].to_vec()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn composite_over_bkgd() {
+        let read = |path: &str| std::fs::read(path).unwrap();
+
+        // RGBA over a white bKGD.
+        let png = read("tests/pngsuite/bgwn6a08.png");
+        let straight = decode("tests/pngsuite/bgwn6a08.png", Transformations::IDENTITY);
+        let (composited, color_type) =
+            decode_over_background(&png, Transformations::IDENTITY, Background::File, false);
+        assert_eq!(color_type, ColorType::Rgb);
+        assert_eq!(composited, blend_over(&straight, 3, 1, [255; 3]));
+
+        // 16-bit gray with alpha over a gray bKGD, and scaled to 8 bits.
+        let path = "tests/pngsuite/bggn4a16.png";
+        let bkgd = match read_info(path).bkgd {
+            Some(BackgroundColor::Grayscale(gray)) => gray,
+            bkgd => panic!("unexpected bKGD {:?}", bkgd),
+        };
+        let (composited, color_type) = decode_over_background(
+            &read(path),
+            Transformations::IDENTITY,
+            Background::File,
+            false,
+        );
+        assert_eq!(color_type, ColorType::Grayscale);
+        let straight = decode(path, Transformations::IDENTITY);
+        assert_eq!(composited, blend_over(&straight, 1, 2, [bkgd; 3]));
+        let (composited, _) = decode_over_background(
+            &read(path),
+            Transformations::SCALE_16,
+            Background::File,
+            false,
+        );
+        let straight = decode(path, Transformations::SCALE_16);
+        let scaled = u16::from(scale_16_to_8(&bkgd.to_be_bytes()));
+        assert_eq!(composited, blend_over(&straight, 1, 1, [scaled; 3]));
+
+        // Palette with tRNS over the palette entry of bKGD.
+        let path = "tests/pngsuite/tbbn3p08.png";
+        let (composited, color_type) = decode_over_background(
+            &read(path),
+            Transformations::EXPAND,
+            Background::File,
+            false,
+        );
+        assert_eq!(color_type, ColorType::Rgb);
+        let straight = decode(path, Transformations::EXPAND);
+        let info = read_info(path);
+        let entry = match info.bkgd {
+            Some(BackgroundColor::Indexed(index)) => {
+                &info.palette.as_deref().unwrap()[3 * usize::from(index)..][..3]
+            }
+            bkgd => panic!("unexpected bKGD {:?}", bkgd),
+        };
+        let background = [entry[0], entry[1], entry[2]].map(u16::from);
+        assert_eq!(composited, blend_over(&straight, 3, 1, background));
+
+        // Without bKGD the alpha channel is kept.
+        let path = "tests/pngsuite/basn6a08.png";
+        let (kept, color_type) = decode_over_background(
+            &read(path),
+            Transformations::IDENTITY,
+            Background::File,
+            false,
+        );
+        assert_eq!(color_type, ColorType::Rgba);
+        assert_eq!(kept, decode(path, Transformations::IDENTITY));
+    }
+
+    #[test]
+    fn composite_over_color() {
+        let red = Background::Color {
+            red: 0xFFFF,
+            green: 0,
+            blue: 0,
+        };
+
+        let path = "tests/pngsuite/basn6a16.png";
+        let png = std::fs::read(path).unwrap();
+        let (composited, color_type) =
+            decode_over_background(&png, Transformations::IDENTITY, red, false);
+        assert_eq!(color_type, ColorType::Rgb);
+        let straight = decode(path, Transformations::IDENTITY);
+        assert_eq!(composited, blend_over(&straight, 3, 2, [0xFFFF, 0, 0]));
+
+        // Gray images use the luma of the color.
+        let path = "tests/pngsuite/basn4a08.png";
+        let png = std::fs::read(path).unwrap();
+        let (composited, color_type) =
+            decode_over_background(&png, Transformations::GRAY_TO_RGB, red, false);
+        assert_eq!(color_type, ColorType::Rgb);
+        let straight = decode(path, Transformations::IDENTITY);
+        let gray = blend_over(&straight, 1, 1, [54; 3]);
+        assert_eq!(composited, gray_to_rgb(&gray, 1, 1));
+
+        // The luma is taken with the coefficients of `RGB_TO_GRAY`.
+        let (composited, _) = decode_with(path, |decoder| {
+            decoder.set_background(Some(red), false);
+            decoder.set_gray_coefficients(GrayCoefficients::Custom {
+                red: 1.0,
+                green: 0.0,
+                blue: 0.0,
+            });
+        });
+        assert_eq!(composited, blend_over(&straight, 1, 1, [255; 3]));
+    }
+
+    #[test]
+    fn composite_in_linear_light() {
+        // Half transparent white, the image has no gamma information and is taken as sRGB.
+        let png = encode_tagged(
+            ColorType::Rgba,
+            BitDepth::Eight,
+            &[255, 255, 255, 128],
+            |_| {},
+        );
+        let black = Background::Color {
+            red: 0,
+            green: 0,
+            blue: 0,
+        };
+        let (encoded, _) = decode_over_background(&png, Transformations::IDENTITY, black, false);
+        assert_eq!(encoded, [128; 3]);
+        let (linear, _) = decode_over_background(&png, Transformations::IDENTITY, black, true);
+        let expected = (srgb_encode(128.0 / 255.0) * 255.0).round() as u8;
+        assert_eq!(linear, [expected; 3]);
+
+        // 16-bit shadows are not quantized to 16-bit linear light.
+        let shadows = [[0, 200, 255, 254], [3, 232, 255, 254]].concat();
+        let png = encode_tagged(
+            ColorType::GrayscaleAlpha,
+            BitDepth::Sixteen,
+            &shadows,
+            |e| e.set_source_gamma(ScaledFloat::new(1.0 / 2.2)),
+        );
+        let (linear, _) = decode_over_background(&png, Transformations::IDENTITY, black, true);
+        let linear: Vec<u16> = linear
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(linear, [200, 1000]);
+
+        // Samples with a gamma of 1.0 are linear already.
+        let path = "tests/pngsuite/bgwn6a08.png";
+        let png = std::fs::read(path).unwrap();
+        let (encoded, _) =
+            decode_over_background(&png, Transformations::IDENTITY, Background::File, false);
+        let (linear, _) =
+            decode_over_background(&png, Transformations::IDENTITY, Background::File, true);
+        for (&linear, &encoded) in linear.iter().zip(&encoded) {
+            assert_close(linear.into(), encoded.into());
+        }
+    }
+
+    #[test]
+    fn rgb_to_gray() {
+        let converted = |path: &str, transform: Transformations, coefficients| {
+            let (gray, (color_type, bit_depth)) = decode_with(path, |decoder| {
+                decoder.set_transformations(transform | Transformations::RGB_TO_GRAY);
+                decoder.set_gray_coefficients(coefficients);
+            });
+
+            let rgb = decode(path, transform | Transformations::EXPAND);
+            let weights = GrayCoefficients::weights(coefficients);
+            let (bytes, max) = match bit_depth {
+                BitDepth::Sixteen => (2, u16::MAX),
+                _ => (1, 0xFF),
+            };
+            let alpha = color_type.samples() - 1;
+            let expected: Vec<u8> = rgb
+                .chunks_exact((3 + alpha) * bytes)
+                .flat_map(|p| {
+                    let sample = |i: usize| {
+                        p[i * bytes..][..bytes]
+                            .iter()
+                            .fold(0, |v, &b| v << 8 | u16::from(b))
+                    };
+                    let gray =
+                        super::gray::rgb_to_gray([sample(0), sample(1), sample(2)], weights, max);
+                    [&gray.to_be_bytes()[2 - bytes..], &p[3 * bytes..]].concat()
+                })
+                .collect();
+            assert_eq!(gray, expected, "{} {:?}", path, transform);
+            color_type
+        };
+
+        let rec601 = GrayCoefficients::Rec601;
+        let rec709 = GrayCoefficients::Rec709;
+        let red = GrayCoefficients::Custom {
+            red: 1.0,
+            green: 0.0,
+            blue: 0.0,
+        };
+        let identity = Transformations::IDENTITY;
+
+        let gray = ColorType::Grayscale;
+        let gray_alpha = ColorType::GrayscaleAlpha;
+        assert_eq!(
+            converted("tests/pngsuite/basn2c08.png", identity, rec709),
+            gray
+        );
+        assert_eq!(
+            converted("tests/pngsuite/basn2c08.png", identity, rec601),
+            gray
+        );
+        assert_eq!(
+            converted("tests/pngsuite/basn2c16.png", identity, red),
+            gray
+        );
+        assert_eq!(
+            converted("tests/pngsuite/basn6a08.png", identity, rec601),
+            gray_alpha
+        );
+        assert_eq!(
+            converted("tests/pngsuite/basn6a16.png", identity, rec709),
+            gray_alpha
+        );
+        assert_eq!(
+            converted(
+                "tests/pngsuite/basn6a16.png",
+                Transformations::SCALE_16,
+                rec709
+            ),
+            gray_alpha
+        );
+        // Palettes are converted once, or after the samples are widened.
+        assert_eq!(
+            converted("tests/pngsuite/basn3p04.png", identity, rec709),
+            gray
+        );
+        assert_eq!(
+            converted("tests/pngsuite/tbbn3p08.png", identity, rec601),
+            gray_alpha
+        );
+        assert_eq!(
+            converted(
+                "tests/pngsuite/tbbn3p08.png",
+                Transformations::EXPAND_16,
+                rec709
+            ),
+            gray_alpha
+        );
+        // Gray images are left as they are.
+        let path = "tests/pngsuite/basn4a08.png";
+        assert_eq!(
+            decode(path, Transformations::RGB_TO_GRAY),
+            decode(path, identity)
+        );
+
+        // The custom weights select the red samples.
+        let path = "tests/pngsuite/basn2c08.png";
+        let (gray, _) = decode_with(path, |decoder| {
+            decoder.set_transformations(Transformations::RGB_TO_GRAY);
+            decoder.set_gray_coefficients(red);
+        });
+        let reds: Vec<u8> = decode(path, identity)
+            .chunks_exact(3)
+            .map(|p| p[0])
+            .collect();
+        assert_eq!(gray, reds);
+    }
+
+    fn decode_to_format(
+        path: &str,
+        transform: Transformations,
+        color_type: ColorType,
+        bit_depth: BitDepth,
+    ) -> Vec<u8> {
+        let (buf, output_color_type) = decode_with(path, |decoder| {
+            decoder.set_transformations(transform);
+            decoder.set_output_format(color_type, bit_depth);
+        });
+        assert_eq!(output_color_type, (color_type, bit_depth));
+        buf
+    }
+
+    #[test]
+    fn output_format_all_combinations() {
+        let sources = [
+            "basn0g01", "basn0g02", "basn0g04", "basn0g08", "basn0g16", "basn2c08", "basn2c16",
+            "basn3p01", "basn3p02", "basn3p04", "basn3p08", "basn4a08", "basn4a16", "basn6a08",
+            "basn6a16", "basi0g02", "basi3p04", "basi6a16", "tbbn0g04", "tbrn2c08", "tbbn3p08",
+        ];
+        let targets = [
+            (ColorType::Grayscale, BitDepth::One),
+            (ColorType::Grayscale, BitDepth::Two),
+            (ColorType::Grayscale, BitDepth::Four),
+            (ColorType::Grayscale, BitDepth::Eight),
+            (ColorType::Grayscale, BitDepth::Sixteen),
+            (ColorType::Rgb, BitDepth::Eight),
+            (ColorType::Rgb, BitDepth::Sixteen),
+            (ColorType::GrayscaleAlpha, BitDepth::Eight),
+            (ColorType::GrayscaleAlpha, BitDepth::Sixteen),
+            (ColorType::Rgba, BitDepth::Eight),
+            (ColorType::Rgba, BitDepth::Sixteen),
+        ];
+        for source in sources {
+            let path = format!("tests/pngsuite/{}.png", source);
+            for (color_type, bit_depth) in targets {
+                for transform in [Transformations::IDENTITY, Transformations::EXPAND] {
+                    decode_to_format(&path, transform, color_type, bit_depth);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn output_format() {
+        let identity = Transformations::IDENTITY;
+
+        // Removing alpha keeps the color samples.
+        let path = "tests/pngsuite/basn6a08.png";
+        let rgb: Vec<u8> = decode(path, identity)
+            .chunks_exact(4)
+            .flat_map(|p| p[..3].to_vec())
+            .collect();
+        assert_eq!(
+            decode_to_format(path, identity, ColorType::Rgb, BitDepth::Eight),
+            rgb
+        );
+
+        // Adding alpha, widening and duplicating gray.
+        let path = "tests/pngsuite/basn0g08.png";
+        let expected = decode(
+            path,
+            Transformations::EXPAND_16 | Transformations::ALPHA | Transformations::GRAY_TO_RGB,
+        );
+        assert_eq!(
+            decode_to_format(path, identity, ColorType::Rgba, BitDepth::Sixteen),
+            expected
+        );
+        // Earlier layout flags are replaced.
+        assert_eq!(
+            decode_to_format(
+                path,
+                Transformations::STRIP_16,
+                ColorType::Rgba,
+                BitDepth::Sixteen
+            ),
+            expected
+        );
+
+        // Scaling and converting color to gray.
+        let path = "tests/pngsuite/basn2c16.png";
+        assert_eq!(
+            decode_to_format(path, identity, ColorType::Grayscale, BitDepth::Eight),
+            decode(
+                path,
+                Transformations::SCALE_16 | Transformations::RGB_TO_GRAY
+            )
+        );
+
+        // Packing into fewer bits rounds to the nearest value.
+        let path = "tests/pngsuite/basn0g08.png";
+        let gray = decode(path, identity);
+        let packed = decode_to_format(path, identity, ColorType::Grayscale, BitDepth::Two);
+        assert_eq!(packed.len(), gray.len() / 4);
+        for (byte, samples) in packed.iter().zip(gray.chunks_exact(4)) {
+            for (i, &sample) in samples.iter().enumerate() {
+                let expected = ((f32::from(sample) * 3.0 / 255.0).round()) as u8;
+                assert_eq!(byte >> (6 - 2 * i) & 0b11, expected);
+            }
+        }
+
+        // Packed gray and palette indices are kept as they are.
+        let path = "tests/pngsuite/basn0g02.png";
+        assert_eq!(
+            decode_to_format(path, identity, ColorType::Grayscale, BitDepth::Two),
+            decode(path, identity)
+        );
+        let path = "tests/pngsuite/basn3p04.png";
+        assert_eq!(
+            decode_to_format(
+                path,
+                Transformations::EXPAND,
+                ColorType::Indexed,
+                BitDepth::Four
+            ),
+            decode(path, identity)
+        );
+
+        // Alpha from tRNS is composited over a background, unless it is kept.
+        let png = std::fs::read("tests/pngsuite/tbrn2c08.png").unwrap();
+        let decode_over = |color_type| {
+            let mut decoder = Decoder::new(png.as_slice());
+            decoder.set_background(Some(Background::File), false);
+            decoder.set_output_format(color_type, BitDepth::Eight);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            buf
+        };
+        let (composited, _) =
+            decode_over_background(&png, Transformations::EXPAND, Background::File, false);
+        assert_eq!(decode_over(ColorType::Rgb), composited);
+        assert_eq!(
+            decode_over(ColorType::Rgba),
+            decode("tests/pngsuite/tbrn2c08.png", Transformations::EXPAND)
+        );
+    }
+
+    #[test]
+    fn output_format_invalid() {
+        let read_info = |path: &str, color_type, bit_depth| {
+            let mut decoder = Decoder::new(File::open(path).unwrap());
+            decoder.set_output_format(color_type, bit_depth);
+            decoder.read_info().map(|_| ())
+        };
+        let invalid = |path, color_type, bit_depth| match read_info(path, color_type, bit_depth) {
+            Err(DecodingError::Parameter(_)) => {}
+            result => panic!("{:?} {:?}: {:?}", color_type, bit_depth, result),
+        };
+
+        invalid(
+            "tests/pngsuite/basn2c08.png",
+            ColorType::Rgb,
+            BitDepth::Four,
+        );
+        invalid(
+            "tests/pngsuite/basn6a08.png",
+            ColorType::Rgba,
+            BitDepth::One,
+        );
+        invalid(
+            "tests/pngsuite/basn2c08.png",
+            ColorType::Indexed,
+            BitDepth::Eight,
+        );
+        invalid(
+            "tests/pngsuite/basn3p04.png",
+            ColorType::Indexed,
+            BitDepth::Two,
+        );
+        invalid(
+            "tests/pngsuite/basn3p08.png",
+            ColorType::Indexed,
+            BitDepth::Sixteen,
+        );
+        assert!(read_info(
+            "tests/pngsuite/basn3p08.png",
+            ColorType::Indexed,
+            BitDepth::Eight
+        )
+        .is_ok());
+        assert!(read_info(
+            "tests/pngsuite/basn3p04.png",
+            ColorType::Indexed,
+            BitDepth::Eight
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn unpack_indices() {
+        for depth in [1, 2, 4] {
+            let path = format!("tests/pngsuite/basn3p0{}.png", depth);
+            let (indices, output_color_type) = decode_with(&path, |decoder| {
+                decoder.set_transformations(Transformations::PACKING)
+            });
+            assert_eq!(output_color_type, (ColorType::Indexed, BitDepth::Eight));
+            let palette = read_info(&path).palette.unwrap();
+
+            let rgb: Vec<u8> = indices
+                .iter()
+                .flat_map(|&index| palette[3 * usize::from(index)..][..3].to_vec())
+                .collect();
+            assert_eq!(rgb, decode(&path, Transformations::EXPAND), "{}", path);
+
+            // Interlaced images are unpacked pass by pass.
+            let interlaced = format!("tests/pngsuite/basi3p0{}.png", depth);
+            assert_eq!(decode(&interlaced, Transformations::PACKING), indices);
+            assert_eq!(
+                decode_to_format(
+                    &path,
+                    Transformations::EXPAND,
+                    ColorType::Indexed,
+                    BitDepth::Eight
+                ),
+                indices
+            );
+            // Expanding the palette takes precedence.
+            assert_eq!(
+                decode(&path, Transformations::PACKING | Transformations::EXPAND),
+                decode(&path, Transformations::EXPAND)
+            );
+        }
+    }
+
+    #[test]
+    fn unpack_gray() {
+        for depth in [1u8, 2, 4] {
+            let path = format!("tests/pngsuite/basn0g0{}.png", depth);
+            let unpacked = decode(&path, Transformations::PACKING);
+            let scale = 0xFF / ((1 << depth) - 1);
+            let scaled: Vec<u8> = unpacked.iter().map(|&gray| gray * scale).collect();
+            assert_eq!(scaled, decode(&path, Transformations::EXPAND), "{}", path);
+        }
+
+        // Gamma correction and shifting work on the unscaled samples.
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, 8, 1);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_depth(BitDepth::Two);
+        encoder.set_source_gamma(ScaledFloat::new(1.0 / 2.2));
+        encoder.set_sbit(vec![1]);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[0b00_01_10_11, 0b11_10_01_00])
+            .unwrap();
+        writer.finish().unwrap();
+
+        let decode_png = |transform| {
+            let mut decoder = Decoder::new(png.as_slice());
+            decoder.set_transformations(transform);
+            decoder.set_display_gamma(Some(1.0));
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            buf
+        };
+        let unpack = |packed: Vec<u8>| -> Vec<u8> {
+            packed
+                .iter()
+                .flat_map(|&byte| (0..4).rev().map(move |i| byte >> (2 * i) & 0b11))
+                .collect()
+        };
+        assert_eq!(
+            decode_png(Transformations::PACKING),
+            unpack(decode_png(Transformations::IDENTITY))
+        );
+        assert_eq!(
+            decode_png(Transformations::PACKING | Transformations::SHIFT),
+            unpack(decode_png(Transformations::SHIFT))
+        );
+    }
+
+    #[test]
+    fn swap_endian() {
+        let path = "tests/pngsuite/basn6a16.png";
+        let big_endian = decode(path, Transformations::IDENTITY);
+        let little_endian = decode(path, Transformations::SWAP_ENDIAN);
+        let swapped: Vec<u8> = big_endian
+            .chunks_exact(2)
+            .flat_map(|s| [s[1], s[0]])
+            .collect();
+        assert_eq!(little_endian, swapped);
+
+        // Only 16-bit samples are swapped.
+        let path = "tests/pngsuite/basn6a08.png";
+        assert_eq!(
+            decode(path, Transformations::SWAP_ENDIAN),
+            decode(path, Transformations::IDENTITY)
+        );
+    }
+
+    #[test]
+    fn next_frame_u16() {
+        let decode_u16 = |path: &str, transform: Transformations| {
+            let mut decoder = Decoder::new(File::open(path).unwrap());
+            decoder.set_transformations(transform);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size() / 2];
+            reader.next_frame_u16(&mut buf).map(|_| buf)
+        };
+
+        let path = "tests/pngsuite/basn2c16.png";
+        let expected: Vec<u16> = decode(path, Transformations::IDENTITY)
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(
+            decode_u16(path, Transformations::IDENTITY).unwrap(),
+            expected
+        );
+        assert_eq!(
+            decode_u16(path, Transformations::SWAP_ENDIAN).unwrap(),
+            expected
+        );
+        // Interlaced passes are expanded into the same samples.
+        assert_eq!(
+            decode_u16("tests/pngsuite/basi2c16.png", Transformations::IDENTITY).unwrap(),
+            expected
+        );
+
+        let path = "tests/pngsuite/basn3p04.png";
+        let expected: Vec<u16> = decode(path, Transformations::EXPAND)
+            .iter()
+            .map(|&v| u16::from(v) * 257)
+            .collect();
+        assert_eq!(
+            decode_u16(path, Transformations::EXPAND_16).unwrap(),
+            expected
+        );
+        assert!(decode_u16(path, Transformations::EXPAND).is_err());
+    }
+
+    #[test]
+    fn shift_16bit_samples() {
+        // 13 significant bits of 16.
+        let path = "tests/pngsuite/cs3n2c16.png";
+        let unshifted = decode(path, Transformations::IDENTITY);
+        let shifted = decode(path, Transformations::SHIFT);
+        for (unshifted, shifted) in unshifted.chunks_exact(2).zip(shifted.chunks_exact(2)) {
+            let unshifted = u16::from_be_bytes([unshifted[0], unshifted[1]]);
+            let shifted = u16::from_be_bytes([shifted[0], shifted[1]]);
+            assert_eq!(shifted, unshifted >> 3);
+        }
+
+        // Only the 8 most significant bits remain after stripping, none of them is shifted.
+        assert_eq!(
+            decode(path, Transformations::STRIP_16),
+            decode(path, Transformations::STRIP_16 | Transformations::SHIFT)
+        );
+    }
+
+    #[test]
+    fn shift_16bit_with_trns() {
+        // 4 significant bits of 16, the second pixel matches the transparent color.
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, 2, 1);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Sixteen);
+        encoder.set_trns(vec![0x10, 0x00, 0x20, 0x00, 0x30, 0x00]);
+        encoder.set_sbit(vec![4, 4, 4]);
+        let mut writer = encoder.write_header().unwrap();
+        // The samples of the first pixel are stripped and scaled to the same 8 bits.
+        writer
+            .write_image_data(&[
+                0xF0, 0xF0, 0x80, 0x80, 0x10, 0x10, 0x10, 0x00, 0x20, 0x00, 0x30, 0x00,
+            ])
+            .unwrap();
+        writer.finish().unwrap();
+
+        let decode_png = |transform| {
+            let mut decoder = Decoder::new(png.as_slice());
+            decoder.set_transformations(transform);
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut buf).unwrap();
+            buf
+        };
+        for narrow in [Transformations::STRIP_16, Transformations::SCALE_16] {
+            let unshifted = decode_png(Transformations::EXPAND | narrow);
+            let shifted = decode_png(Transformations::EXPAND | narrow | Transformations::SHIFT);
+            assert_eq!(shifted.len(), 8);
+            for (unshifted, shifted) in unshifted.chunks_exact(4).zip(shifted.chunks_exact(4)) {
+                // The alpha channel created from `tRNS` is not shifted.
+                assert_eq!(shifted[3], unshifted[3]);
+                for (unshifted, shifted) in unshifted[..3].iter().zip(&shifted[..3]) {
+                    assert_eq!(*shifted, unshifted >> 4);
+                }
+            }
+            assert_eq!(&shifted[..4], [0x0F, 0x08, 0x01, 0xFF]);
+            assert_eq!(shifted[7], 0);
+        }
+    }
+
+    #[test]
+    fn shift_expanded_palette() {
+        // 3 significant bits of the 8-bit palette entries.
+        let path = "tests/pngsuite/cs3n3p08.png";
+        let unshifted = decode(path, Transformations::EXPAND);
+        let shifted = decode(path, Transformations::EXPAND | Transformations::SHIFT);
+        assert!(unshifted.iter().any(|&sample| sample > 7));
+        for (unshifted, shifted) in unshifted.iter().zip(&shifted) {
+            assert_eq!(*shifted, unshifted >> 5);
+        }
+
+        // The palette indices themselves are left alone.
+        assert_eq!(
+            decode(path, Transformations::IDENTITY),
+            decode(path, Transformations::SHIFT)
+        );
+    }
+
+    #[test]
+    fn shift_packed_gray() {
+        let mut row = [0b1111_0110, 0b1000_0001];
+        super::shift_row(&mut row, &[1], 4);
+        assert_eq!(row, [0b0111_0011, 0b0100_0000]);
+
+        let mut row = [0b11_10_01_00];
+        super::shift_row(&mut row, &[1], 2);
+        assert_eq!(row, [0b01_01_00_00]);
+    }
+
+    #[test]
+    fn gamma_correct_8bit() {
+        // File gamma 0.35.
+        let path = "tests/pngsuite/g03n2c08.png";
+        let original = decode(path, Transformations::IDENTITY);
+        let corrected = decode_with_gamma(path, Transformations::IDENTITY, Some(2.2));
+        assert_ne!(original, corrected);
+        for (&original, &corrected) in original.iter().zip(&corrected) {
+            let linear = (f32::from(original) / 255.0).powf(1.0 / 0.35);
+            assert_close(corrected.into(), linear.powf(1.0 / 2.2) * 255.0);
+        }
+
+        // A matching display gamma needs no correction.
+        let path = "tests/pngsuite/g10n2c08.png";
+        assert_eq!(
+            decode(path, Transformations::IDENTITY),
+            decode_with_gamma(path, Transformations::IDENTITY, Some(1.0))
+        );
+    }
+
+    #[test]
+    fn gamma_correct_16bit() {
+        // File gamma 2.5.
+        let path = "tests/pngsuite/g25n0g16.png";
+        let original = decode(path, Transformations::IDENTITY);
+        let corrected = decode_with_gamma(path, Transformations::IDENTITY, Some(2.2));
+        for (original, corrected) in original.chunks_exact(2).zip(corrected.chunks_exact(2)) {
+            let original = u16::from_be_bytes([original[0], original[1]]);
+            let corrected = u16::from_be_bytes([corrected[0], corrected[1]]);
+            let linear = (f32::from(original) / 65535.0).powf(1.0 / 2.5);
+            assert_close(corrected, linear.powf(1.0 / 2.2) * 65535.0);
+        }
+    }
+
+    #[test]
+    fn gamma_correct_palette() {
+        let path = "tests/pngsuite/g03n3p04.png";
+        // Indices are not corrected, only the expanded colors.
+        assert_eq!(
+            decode(path, Transformations::IDENTITY),
+            decode_with_gamma(path, Transformations::IDENTITY, Some(2.2))
+        );
+        assert_ne!(
+            decode(path, Transformations::EXPAND),
+            decode_with_gamma(path, Transformations::EXPAND, Some(2.2))
+        );
+    }
+
+    #[test]
+    fn gamma_correct_srgb_over_gama() {
+        let ramp: Vec<u8> = (0..=255u8).flat_map(|v| [v, 255 - v]).collect();
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, 256, 1);
+        encoder.set_color(ColorType::GrayscaleAlpha);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.set_source_gamma(ScaledFloat::new(1.0));
+        encoder.set_srgb(SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&ramp).unwrap();
+        writer.finish().unwrap();
+
+        let mut decoder = Decoder::new(&*png);
+        decoder.set_display_gamma(Some(1.0));
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+
+        for (pixel, original) in buf.chunks_exact(2).zip(ramp.chunks_exact(2)) {
+            let value = f32::from(original[0]) / 255.0;
+            let linear = if value <= 0.04045 {
+                value / 12.92
+            } else {
+                ((value + 0.055) / 1.055).powf(2.4)
+            };
+            assert_close(pixel[0].into(), linear * 255.0);
+            assert_eq!(pixel[1], original[1]);
+        }
+    }
+
+    fn encode_tagged(
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        data: &[u8],
+        configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>),
+    ) -> Vec<u8> {
+        let pixel_size = color_type.samples() * (bit_depth as usize / 8);
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, (data.len() / pixel_size) as u32, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        configure(&mut encoder);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        png
+    }
+
+    fn decode_to_srgb(png: &[u8]) -> Vec<u8> {
+        let mut decoder = Decoder::new(png);
+        decoder.set_convert_to_srgb(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        buf
+    }
+
+    fn srgb_encode(linear: f32) -> f32 {
+        let linear = linear.clamp(0.0, 1.0);
+        if linear <= 0.0031308 {
+            linear * 12.92
+        } else {
+            1.055 * linear.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    #[test]
+    fn convert_to_srgb_keeps_srgb() {
+        let data: Vec<u8> = (0..=255).collect();
+        let png = encode_tagged(ColorType::Rgb, BitDepth::Eight, &data[..255], |encoder| {
+            encoder.set_srgb(SrgbRenderingIntent::Perceptual)
+        });
+        assert_eq!(decode_to_srgb(&png), &data[..255]);
+
+        // Untagged images are assumed to be sRGB already.
+        let png = encode_tagged(ColorType::Rgb, BitDepth::Eight, &data[..255], |_| {});
+        assert_eq!(decode_to_srgb(&png), &data[..255]);
+    }
+
+    #[test]
+    fn convert_display_p3_to_srgb() {
+        let data = [128, 128, 64, 255, 0, 0, 20, 200, 100, 7, 7, 7];
+        let png = encode_tagged(ColorType::Rgba, BitDepth::Eight, &data, |encoder| {
+            encoder.set_source_gamma(ScaledFloat::new(1.0));
+            encoder.set_source_chromaticities(SourceChromaticities::new(
+                (0.3127, 0.3290),
+                (0.680, 0.320),
+                (0.265, 0.690),
+                (0.150, 0.060),
+            ));
+        });
+
+        // Linear Display P3 to linear sRGB.
+        let matrix = [
+            [1.2249, -0.2247, 0.0],
+            [-0.0420, 1.0419, 0.0],
+            [-0.0197, -0.0786, 1.0979],
+        ];
+        let converted = decode_to_srgb(&png);
+        for (pixel, original) in converted.chunks_exact(4).zip(data.chunks_exact(4)) {
+            let linear: Vec<f32> = original[..3]
+                .iter()
+                .map(|&v| f32::from(v) / 255.0)
+                .collect();
+            for (sample, row) in pixel[..3].iter().zip(&matrix) {
+                let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                let expected = srgb_encode(value) * 255.0;
+                assert!((f32::from(*sample) - expected).abs() <= 2.0, "{:?}", pixel);
+            }
+            assert_eq!(pixel[3], original[3]);
+        }
+    }
+
+    #[test]
+    fn convert_to_srgb_adapts_white_point() {
+        // ProPhoto RGB primaries with a D50 white point, neutral colors must remain neutral.
+        let data: Vec<u8> = (0..=255u16)
+            .flat_map(|v| {
+                let [high, low] = (v * 257).to_be_bytes();
+                [high, low, high, low, high, low]
+            })
+            .collect();
+        let png = encode_tagged(ColorType::Rgb, BitDepth::Sixteen, &data, |encoder| {
+            encoder.set_source_gamma(ScaledFloat::new(1.0 / 1.8));
+            encoder.set_source_chromaticities(SourceChromaticities::new(
+                (0.3457, 0.3585),
+                (0.7347, 0.2653),
+                (0.1596, 0.8404),
+                (0.0366, 0.0001),
+            ));
+        });
+
+        let converted = decode_to_srgb(&png);
+        for (pixel, original) in converted.chunks_exact(6).zip(data.chunks_exact(6)) {
+            let samples: Vec<f32> = pixel
+                .chunks_exact(2)
+                .map(|v| f32::from(u16::from_be_bytes([v[0], v[1]])))
+                .collect();
+            let original = f32::from(u16::from_be_bytes([original[0], original[1]])) / 65535.0;
+            let expected = srgb_encode(original.powf(1.8)) * 65535.0;
+            for sample in samples {
+                assert!(
+                    (sample - expected).abs() <= 8.0,
+                    "{} != {}",
+                    sample,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn convert_gray_to_srgb() {
+        let data: Vec<u8> = (0..=255).flat_map(|v| [v, 255 - v]).collect();
+        let png = encode_tagged(
+            ColorType::GrayscaleAlpha,
+            BitDepth::Eight,
+            &data,
+            |encoder| encoder.set_source_gamma(ScaledFloat::new(1.0)),
+        );
+
+        let converted = decode_to_srgb(&png);
+        for (pixel, original) in converted.chunks_exact(2).zip(data.chunks_exact(2)) {
+            assert_close(
+                pixel[0].into(),
+                srgb_encode(f32::from(original[0]) / 255.0) * 255.0,
+            );
+            assert_eq!(pixel[1], original[1]);
+        }
+    }
+
+    #[test]
+    fn convert_icc_tagged_to_srgb() {
+        let profile = read_info("tests/bugfixes/issue#1825.png")
+            .icc_profile
+            .unwrap();
+
+        // The profile describes sRGB and takes precedence over the gamma.
+        let data: Vec<u8> = (0..=255).collect();
+        let png = encode_tagged(ColorType::Rgb, BitDepth::Eight, &data[..255], |encoder| {
+            encoder
+                .set_icc_profile("sRGB", profile.into_owned())
+                .unwrap();
+            encoder.set_source_gamma(ScaledFloat::new(1.0));
+        });
+        assert_eq!(decode_to_srgb(&png), &data[..255]);
+    }
+
+    #[test]
+    fn convert_rec2020_to_srgb() {
+        let data = [128, 128, 128, 10, 10, 10];
+        let png = encode_tagged(ColorType::Rgb, BitDepth::Eight, &data, |encoder| {
+            encoder.set_color_space(ColorSpacePreset::Rec2020)
+        });
+
+        let converted = decode_to_srgb(&png);
+        for (sample, original) in converted.iter().zip(&data) {
+            let value = f32::from(*original) / 255.0;
+            let linear = if value < 0.081 {
+                value / 4.5
+            } else {
+                ((value + 0.099) / 1.099).powf(1.0 / 0.45)
+            };
+            assert_close((*sample).into(), srgb_encode(linear) * 255.0);
+        }
+    }
+}
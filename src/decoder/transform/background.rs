@@ -0,0 +1,218 @@
+//! Compositing pixels over a background color, see `Decoder::set_background`.
+
+use super::color::SourceTransfer;
+use super::gray::rgb_to_gray;
+use super::scale_16_to_8;
+use crate::pixel::alpha;
+use crate::{
+    Background, BackgroundColor, BitDepth, ColorType, GrayCoefficients, Info, Transformations,
+};
+
+/// Returns the background color for rows of the given layout, at their bit depth, or `None` if
+/// the rows are not composited.
+///
+/// Only the first sample is used for grayscale rows, a `Background::Color` is converted to gray
+/// with the `gray_coefficients`.
+pub(crate) fn background_color(
+    info: &Info,
+    transform: Transformations,
+    background: Background,
+    gray_coefficients: GrayCoefficients,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+) -> Option<[u16; 3]> {
+    if !alpha::has_alpha(color_type, bit_depth) {
+        return None;
+    }
+    let depth = bit_depth as u8;
+    let strip16 = transform.contains(Transformations::STRIP_16);
+    // Scales a sample like the expansion of the image samples does.
+    let rescale = |value: u16, from: u8| -> u16 {
+        if from < depth {
+            let factor = ((1u32 << depth) - 1) / ((1u32 << from) - 1);
+            (u32::from(value) * factor) as u16
+        } else if from > depth && strip16 {
+            value >> 8
+        } else if from > depth {
+            u16::from(scale_16_to_8(&value.to_be_bytes()))
+        } else {
+            value
+        }
+    };
+
+    match background {
+        Background::File => {
+            let bkgd = info.bkgd.filter(|bkgd| {
+                bkgd.is_valid_for(info.color_type, info.bit_depth, info.palette.as_deref())
+            })?;
+            let from = info.bit_depth as u8;
+            Some(match bkgd {
+                BackgroundColor::Grayscale(gray) => [rescale(gray, from); 3],
+                BackgroundColor::Rgb { red, green, blue } => {
+                    [red, green, blue].map(|sample| rescale(sample, from))
+                }
+                BackgroundColor::Indexed(index) => {
+                    let palette = info.palette.as_deref()?;
+                    let entry = &palette[3 * usize::from(index)..][..3];
+                    [entry[0], entry[1], entry[2]].map(|sample| rescale(sample.into(), 8))
+                }
+            })
+        }
+        Background::Color { red, green, blue } => {
+            let color = if color_type == ColorType::GrayscaleAlpha {
+                let weights = gray_coefficients.weights();
+                [rgb_to_gray([red, green, blue], weights, u16::MAX); 3]
+            } else {
+                [red, green, blue]
+            };
+            Some(match bit_depth {
+                BitDepth::Sixteen => color,
+                _ => color.map(|sample| u16::from(scale_16_to_8(&sample.to_be_bytes()))),
+            })
+        }
+    }
+}
+
+/// Blends the pixels of rows with alpha over a background color, dropping the alpha channel.
+pub(crate) struct Compositing {
+    /// The background at the bit depth of the rows.
+    color: [u16; 3],
+    /// Number of color samples per pixel, without alpha.
+    colors: usize,
+    bit_depth: BitDepth,
+    linear: Option<LinearLight>,
+}
+
+/// Tables for blending in linear light.
+struct LinearLight {
+    /// Linear light of each sample value.
+    to_linear: Vec<f32>,
+    /// The encoded sample for linear light quantized to 16 bits, for rows with 8 bits per sample.
+    /// Quantizing would lose the shadows of 16-bit samples, which are encoded directly instead.
+    from_linear: Option<Vec<u16>>,
+    transfer: SourceTransfer,
+    /// The largest sample value.
+    max: f32,
+}
+
+impl LinearLight {
+    /// Encodes linear light between 0 and 1 into a sample.
+    fn encode(&self, linear: f32) -> u16 {
+        match &self.from_linear {
+            Some(from_linear) => from_linear[(linear * 65535.0).round() as usize],
+            None => (self.transfer.encode_linear(linear).clamp(0.0, 1.0) * self.max).round() as u16,
+        }
+    }
+}
+
+impl Compositing {
+    /// Creates the compositing of rows of the given layout, or `None` if they are not composited.
+    ///
+    /// With `linear` set the samples are blended in linear light, using the transfer function
+    /// of the image or sRGB if it has none.
+    pub(crate) fn new(
+        info: &Info,
+        transform: Transformations,
+        background: Background,
+        linear: bool,
+        gray_coefficients: GrayCoefficients,
+        color_type: ColorType,
+        bit_depth: BitDepth,
+    ) -> Option<Self> {
+        let color = background_color(
+            info,
+            transform,
+            background,
+            gray_coefficients,
+            color_type,
+            bit_depth,
+        )?;
+        let linear = if linear {
+            let transfer = SourceTransfer::from_info(info).unwrap_or(SourceTransfer::Srgb);
+            let max = ((1u32 << bit_depth as u8) - 1) as f32;
+            let from_linear = (bit_depth != BitDepth::Sixteen).then(|| {
+                (0..=u16::MAX)
+                    .map(|linear| {
+                        let sample = transfer.encode_linear(f32::from(linear) / 65535.0);
+                        (sample.clamp(0.0, 1.0) * max).round() as u16
+                    })
+                    .collect()
+            });
+            Some(LinearLight {
+                to_linear: (0..=max as u32)
+                    .map(|value| transfer.to_linear(value as f32 / max))
+                    .collect(),
+                from_linear,
+                transfer,
+                max,
+            })
+        } else {
+            None
+        };
+        Some(Compositing {
+            color,
+            colors: color_type.samples() - 1,
+            bit_depth,
+            linear,
+        })
+    }
+
+    /// Composites the pixels of `input` into `output`, which has no room for the alpha channel.
+    pub(crate) fn apply(&self, input: &[u8], output: &mut [u8]) {
+        let colors = self.colors;
+        match self.bit_depth {
+            BitDepth::Sixteen => {
+                for (input, output) in input
+                    .chunks_exact(2 * (colors + 1))
+                    .zip(output.chunks_exact_mut(2 * colors))
+                {
+                    let alpha = u16::from_be_bytes([input[2 * colors], input[2 * colors + 1]]);
+                    for (i, (input, output)) in input
+                        .chunks_exact(2)
+                        .zip(output.chunks_exact_mut(2))
+                        .enumerate()
+                    {
+                        let value = u16::from_be_bytes([input[0], input[1]]);
+                        let blended = self.blend(value, alpha, i, 0xFFFF);
+                        output.copy_from_slice(&blended.to_be_bytes());
+                    }
+                }
+            }
+            _ => {
+                for (input, output) in input
+                    .chunks_exact(colors + 1)
+                    .zip(output.chunks_exact_mut(colors))
+                {
+                    let alpha = u16::from(input[colors]);
+                    for (i, (input, output)) in input.iter().zip(output).enumerate() {
+                        *output = self.blend(u16::from(*input), alpha, i, 0xFF) as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blends a sample of the `channel` over the background, rounding to the nearest value.
+    fn blend(&self, value: u16, alpha: u16, channel: usize, max: u32) -> u16 {
+        let background = self.color[channel];
+        if u32::from(alpha) == max {
+            return value;
+        } else if alpha == 0 {
+            return background;
+        }
+
+        match &self.linear {
+            Some(linear) => {
+                let opacity = f32::from(alpha) / max as f32;
+                let blended = linear.to_linear[usize::from(value)] * opacity
+                    + linear.to_linear[usize::from(background)] * (1.0 - opacity);
+                linear.encode(blended)
+            }
+            None => {
+                let (value, alpha, background) =
+                    (u32::from(value), u32::from(alpha), u32::from(background));
+                ((value * alpha + background * (max - alpha) + max / 2) / max) as u16
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Color corrections applied to the output samples.
+
+use crate::color_math::{chromaticities_to_f64, cicp_primaries, to_srgb_matrix, SRGB_PRIMARIES};
+use crate::icc::{IccProfile, SrgbTransform};
+use crate::{BitDepth, ColorSpace, ColorType, Info};
+
+/// The transfer function the samples of an image are encoded with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum SourceTransfer {
+    /// The piecewise sRGB curve.
+    Srgb,
+    /// The piecewise curve of BT.709, also used by BT.601 and BT.2020.
+    Bt709,
+    /// A power law, the value of the `gAMA` chunk: `sample = linear ^ gamma`.
+    Gamma(f32),
+}
+
+impl SourceTransfer {
+    /// Determines the transfer function of the image from its [`Info::color_space`].
+    ///
+    /// The `gAMA` chunk serves as fallback for an ICC profile. Returns `None` if the image does
+    /// not specify it or it is not one of the supported curves.
+    pub(crate) fn from_info(info: &Info) -> Option<Self> {
+        let gamma = match info.color_space() {
+            ColorSpace::CodingIndependentCodePoints(cicp) => {
+                return match cicp.transfer_function {
+                    13 => Some(SourceTransfer::Srgb),
+                    1 | 6 | 14 | 15 => Some(SourceTransfer::Bt709),
+                    8 => Some(SourceTransfer::Gamma(1.0)),
+                    _ => None,
+                };
+            }
+            ColorSpace::Srgb(_) => return Some(SourceTransfer::Srgb),
+            ColorSpace::IccProfile(_) => info.source_gamma?,
+            ColorSpace::GammaChromaticities { gamma, .. } => gamma?,
+            ColorSpace::Unspecified => return None,
+        }
+        .into_value();
+
+        if gamma > 0.0 && gamma.is_finite() {
+            Some(SourceTransfer::Gamma(gamma))
+        } else {
+            None
+        }
+    }
+
+    /// Converts a sample between 0 and 1 into linear light.
+    pub(crate) fn to_linear(self, value: f32) -> f32 {
+        match self {
+            SourceTransfer::Srgb if value <= 0.04045 => value / 12.92,
+            SourceTransfer::Srgb => ((value + 0.055) / 1.055).powf(2.4),
+            SourceTransfer::Bt709 if value < 0.081 => value / 4.5,
+            SourceTransfer::Bt709 => ((value + 0.099) / 1.099).powf(1.0 / 0.45),
+            SourceTransfer::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+
+    /// Converts linear light between 0 and 1 into a sample, the inverse of `to_linear`.
+    pub(crate) fn encode_linear(self, linear: f32) -> f32 {
+        match self {
+            SourceTransfer::Srgb if linear <= 0.0031308 => linear * 12.92,
+            SourceTransfer::Srgb => 1.055 * linear.powf(1.0 / 2.4) - 0.055,
+            SourceTransfer::Bt709 if linear < 0.018 => linear * 4.5,
+            SourceTransfer::Bt709 => 1.099 * linear.powf(0.45) - 0.099,
+            SourceTransfer::Gamma(gamma) => linear.powf(gamma),
+        }
+    }
+}
+
+/// Creates a table mapping each sample value of the output to its value for a display with the
+/// given gamma, the samples being encoded with the `source` transfer function.
+///
+/// Returns `None` if the samples can't be or need not be corrected.
+pub(crate) fn gamma_table(
+    source: SourceTransfer,
+    display_gamma: f32,
+    color_type: ColorType,
+    bit_depth: BitDepth,
+) -> Option<Vec<u16>> {
+    if color_type == ColorType::Indexed || !(display_gamma > 0.0 && display_gamma.is_finite()) {
+        return None;
+    }
+
+    if let SourceTransfer::Gamma(gamma) = source {
+        if (gamma * display_gamma - 1.0).abs() < 0.01 {
+            return None;
+        }
+    }
+
+    let max = ((1u32 << bit_depth as u8) - 1) as f32;
+    let table = (0..=max as u32)
+        .map(|value| {
+            let linear = source.to_linear(value as f32 / max);
+            (linear.powf(1.0 / display_gamma) * max).round() as u16
+        })
+        .collect();
+    Some(table)
+}
+
+/// Replaces the color samples of a row with their entry in the `table`, keeping alpha.
+pub(crate) fn apply_table(
+    row: &mut [u8],
+    table: &[u16],
+    color_type: ColorType,
+    bit_depth: BitDepth,
+) {
+    let channels = color_type.samples();
+    let colors = match color_type {
+        ColorType::GrayscaleAlpha | ColorType::Rgba => channels - 1,
+        _ => channels,
+    };
+
+    match bit_depth {
+        BitDepth::Sixteen => {
+            for pixel in row.chunks_exact_mut(2 * channels) {
+                for sample in pixel[..2 * colors].chunks_exact_mut(2) {
+                    let value = table[usize::from(u16::from_be_bytes([sample[0], sample[1]]))];
+                    sample.copy_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+        BitDepth::Eight => {
+            for pixel in row.chunks_exact_mut(channels) {
+                for sample in &mut pixel[..colors] {
+                    *sample = table[usize::from(*sample)] as u8;
+                }
+            }
+        }
+        _ => {
+            // Packed grayscale samples, the padding bits of the last byte are mapped as well.
+            let depth = bit_depth as u8;
+            let mask = ((1u16 << depth) - 1) as u8;
+            for byte in row.iter_mut() {
+                let mut mapped = 0;
+                for pos in (0..8).step_by(depth.into()) {
+                    mapped |= (table[usize::from((*byte >> pos) & mask)] as u8) << pos;
+                }
+                *byte = mapped;
+            }
+        }
+    }
+}
+
+/// Converts the samples of an image to the sRGB color space.
+pub(crate) struct SrgbConversion {
+    /// Linear light of each input sample value, per color channel or shared by all of them.
+    to_linear: Vec<Vec<f32>>,
+    /// Maps linear source RGB to linear sRGB, `None` for grayscale images.
+    matrix: Option<[[f32; 3]; 3]>,
+    /// sRGB encoded output sample for linear light quantized to 16 bits.
+    to_srgb: Vec<u16>,
+}
+
+impl SrgbConversion {
+    /// Prepares the conversion of the output samples, based on the color space of the image.
+    ///
+    /// The `cICP` chunk takes precedence over an ICC profile, which takes precedence over `sRGB`,
+    /// which takes precedence over `gAMA` and `cHRM`. Returns `None` for images that are already
+    /// sRGB, including those with a profile describing sRGB, or don't specify their color space,
+    /// and for output that is not 8 or 16 bits per sample.
+    pub(crate) fn new(info: &Info, color_type: ColorType, bit_depth: BitDepth) -> Option<Self> {
+        if color_type == ColorType::Indexed || (bit_depth as u8) < 8 {
+            return None;
+        }
+
+        let max = ((1u32 << bit_depth as u8) - 1) as f32;
+        let profile = icc_profile(info);
+        if profile.as_ref().map_or(false, IccProfile::is_srgb) {
+            return None;
+        }
+
+        let is_rgb = matches!(color_type, ColorType::Rgb | ColorType::Rgba);
+        let (to_linear, matrix) = match profile.and_then(|profile| icc_transform(&profile, is_rgb))
+        {
+            Some(transform) => {
+                let to_linear = transform
+                    .curves
+                    .iter()
+                    .map(|curve| {
+                        (0..=max as u32)
+                            .map(|value| curve.eval(f64::from(value) / f64::from(max)) as f32)
+                            .collect()
+                    })
+                    .collect();
+                (to_linear, transform.matrix)
+            }
+            None => {
+                let (primaries, transfer) = match info.color_space() {
+                    ColorSpace::CodingIndependentCodePoints(cicp) => (
+                        Some(cicp_primaries(cicp.color_primaries)?),
+                        SourceTransfer::from_info(info)?,
+                    ),
+                    ColorSpace::Srgb(_) | ColorSpace::Unspecified => return None,
+                    // Uninterpreted ICC profiles fall back to `gAMA` and `cHRM`.
+                    ColorSpace::IccProfile(_) | ColorSpace::GammaChromaticities { .. } => {
+                        let primaries = info.source_chromaticities.map(chromaticities_to_f64);
+                        let transfer = SourceTransfer::from_info(info);
+                        if primaries.is_none() && transfer.is_none() {
+                            return None;
+                        }
+                        (primaries, transfer.unwrap_or(SourceTransfer::Srgb))
+                    }
+                };
+
+                if transfer == SourceTransfer::Srgb
+                    && primaries.map_or(true, |p| p == SRGB_PRIMARIES)
+                {
+                    return None;
+                }
+
+                let matrix = match primaries {
+                    Some(primaries) if is_rgb => Some(to_srgb_matrix(primaries)?),
+                    _ => None,
+                };
+                let to_linear = (0..=max as u32)
+                    .map(|value| transfer.to_linear(value as f32 / max))
+                    .collect();
+                (vec![to_linear], matrix)
+            }
+        };
+
+        let to_srgb = (0..=u16::MAX)
+            .map(|linear| {
+                let encoded = SourceTransfer::Srgb.encode_linear(f32::from(linear) / 65535.0);
+                (encoded * max).round() as u16
+            })
+            .collect();
+
+        Some(SrgbConversion {
+            to_linear,
+            matrix: matrix.map(|m| m.map(|row| row.map(|v| v as f32))),
+            to_srgb,
+        })
+    }
+
+    /// Converts the color samples of a row, keeping alpha.
+    pub(crate) fn apply(&self, row: &mut [u8], color_type: ColorType, bit_depth: BitDepth) {
+        let channels = color_type.samples();
+        let colors = match color_type {
+            ColorType::GrayscaleAlpha | ColorType::Rgba => channels - 1,
+            _ => channels,
+        };
+        let bytes = if bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+
+        let read = |sample: &[u8], channel: usize| -> f32 {
+            let value = match sample {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                _ => u16::from(sample[0]),
+            };
+            let to_linear = &self.to_linear[channel.min(self.to_linear.len() - 1)];
+            to_linear[usize::from(value)]
+        };
+        let write = |sample: &mut [u8], linear: f32| {
+            let quantized = (linear.clamp(0.0, 1.0) * 65535.0).round() as usize;
+            let value = self.to_srgb[quantized];
+            if bytes == 2 {
+                sample.copy_from_slice(&value.to_be_bytes());
+            } else {
+                sample[0] = value as u8;
+            }
+        };
+
+        for pixel in row.chunks_exact_mut(channels * bytes) {
+            match self.matrix {
+                Some(matrix) => {
+                    let rgb = [
+                        read(&pixel[..bytes], 0),
+                        read(&pixel[bytes..2 * bytes], 1),
+                        read(&pixel[2 * bytes..3 * bytes], 2),
+                    ];
+                    for (i, sample) in pixel[..3 * bytes].chunks_exact_mut(bytes).enumerate() {
+                        let row = matrix[i];
+                        write(sample, row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+                    }
+                }
+                None => {
+                    for (i, sample) in pixel[..colors * bytes].chunks_exact_mut(bytes).enumerate() {
+                        let linear = read(sample, i);
+                        write(sample, linear);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parses the ICC profile of the image, unless its color space is not an ICC profile.
+fn icc_profile(info: &Info) -> Option<IccProfile> {
+    match info.color_space() {
+        ColorSpace::IccProfile(profile) => IccProfile::parse(profile),
+        _ => None,
+    }
+}
+
+/// Creates the transform of the profile to sRGB, unless the profile does not describe the color
+/// type of the output.
+fn icc_transform(profile: &IccProfile, is_rgb: bool) -> Option<SrgbTransform> {
+    let transform = profile.srgb_transform()?;
+    let channels = if is_rgb { 3 } else { 1 };
+    Some(transform).filter(|transform| transform.channels() == channels)
+}
@@ -0,0 +1,45 @@
+//! Converting RGB samples to gray, for `RGB_TO_GRAY`.
+
+use crate::{BitDepth, ColorType};
+
+/// Weights the red, green and blue samples, rounding to the nearest value and clamping to `max`.
+pub(crate) fn rgb_to_gray(rgb: [u16; 3], weights: [f32; 3], max: u16) -> u16 {
+    let gray = weights[0] * f32::from(rgb[0])
+        + weights[1] * f32::from(rgb[1])
+        + weights[2] * f32::from(rgb[2]);
+    gray.round().clamp(0.0, f32::from(max)) as u16
+}
+
+/// Converts a row of RGB or RGBA pixels in `input` to gray or gray with alpha in `output`.
+pub(crate) fn convert_row(
+    input: &[u8],
+    output: &mut [u8],
+    color_type: ColorType,
+    bit_depth: BitDepth,
+    weights: [f32; 3],
+) {
+    let alpha = usize::from(color_type == ColorType::Rgba);
+    match bit_depth {
+        BitDepth::Sixteen => {
+            let sample = |s: &[u8]| u16::from_be_bytes([s[0], s[1]]);
+            for (input, output) in input
+                .chunks_exact(2 * (3 + alpha))
+                .zip(output.chunks_exact_mut(2 * (1 + alpha)))
+            {
+                let rgb = [sample(input), sample(&input[2..]), sample(&input[4..])];
+                output[..2].copy_from_slice(&rgb_to_gray(rgb, weights, u16::MAX).to_be_bytes());
+                output[2..].copy_from_slice(&input[6..]);
+            }
+        }
+        _ => {
+            for (input, output) in input
+                .chunks_exact(3 + alpha)
+                .zip(output.chunks_exact_mut(1 + alpha))
+            {
+                let rgb = [input[0], input[1], input[2]].map(u16::from);
+                output[0] = rgb_to_gray(rgb, weights, 0xFF) as u8;
+                output[1..].copy_from_slice(&input[3..]);
+            }
+        }
+    }
+}
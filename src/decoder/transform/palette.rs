@@ -17,7 +17,8 @@
 //! that memoization is a net benefit for images bigger than around 13x13 pixels.
 
 use super::{unpack_bits, TransformFn};
-use crate::{BitDepth, Info};
+use crate::pixel::alpha;
+use crate::{BitDepth, ColorType, Info};
 
 pub fn create_expansion_into_rgb8(info: &Info) -> TransformFn {
     let rgba_palette = create_rgba_palette(info);
@@ -29,13 +30,43 @@ pub fn create_expansion_into_rgb8(info: &Info) -> TransformFn {
     }
 }
 
-pub fn create_expansion_into_rgba8(info: &Info) -> TransformFn {
-    let rgba_palette = create_rgba_palette(info);
+/// If `premultiply` is set, the color of each palette entry is premultiplied with its alpha.
+pub fn create_expansion_into_rgba8(info: &Info, premultiply: bool) -> TransformFn {
+    let mut rgba_palette = create_rgba_palette(info);
+    if premultiply {
+        for entry in rgba_palette.iter_mut() {
+            alpha::premultiply_row(entry, ColorType::Rgba, BitDepth::Eight);
+        }
+    }
     Box::new(move |input, output, info| {
         expand_paletted_into_rgba8(input, output, info, &rgba_palette)
     })
 }
 
+/// Expands the indices into gray samples, converting the palette to gray once. With `alpha` set
+/// the output keeps the alpha of each entry, premultiplied if `premultiply` is set.
+pub fn create_expansion_into_gray8(
+    info: &Info,
+    weights: [f32; 3],
+    alpha: bool,
+    premultiply: bool,
+) -> TransformFn {
+    let gray_palette = create_rgba_palette(info).map(|[red, green, blue, alpha]| {
+        let gray = super::gray::rgb_to_gray([red, green, blue].map(u16::from), weights, 0xFF);
+        let mut entry = [gray as u8, alpha];
+        if premultiply {
+            alpha::premultiply_row(&mut entry, ColorType::GrayscaleAlpha, BitDepth::Eight);
+        }
+        entry
+    });
+    let channels = if alpha { 2 } else { 1 };
+    Box::new(move |input, output, info| {
+        unpack_bits(input, output, channels, info.bit_depth as u8, |i, chunk| {
+            chunk.copy_from_slice(&gray_palette[i as usize][..channels]);
+        })
+    })
+}
+
 fn create_rgba_palette(info: &Info) -> [[u8; 4]; 256] {
     let palette = info.palette.as_deref().expect("Caller should verify");
     let trns = info.trns.as_deref().unwrap_or(&[]);
@@ -70,7 +101,7 @@ fn create_rgba_palette(info: &Info) -> [[u8; 4]; 256] {
             palette_iter = &palette_iter[3..];
             rgba_iter = &mut rgba_iter[1..];
         }
-        if palette_iter.len() > 0 {
+        if !palette_iter.is_empty() {
             rgba_iter[0][0..3].copy_from_slice(&palette_iter[0..3]);
         }
     }
@@ -99,7 +130,7 @@ fn expand_8bit_into_rgb8(mut input: &[u8], mut output: &mut [u8], rgba_palette:
         input = &input[1..];
         output = &mut output[3..];
     }
-    if output.len() > 0 {
+    if !output.is_empty() {
         let rgba = &rgba_palette[input[0] as usize];
         output[0..3].copy_from_slice(&rgba[0..3]);
     }
@@ -202,9 +233,19 @@ mod test {
         let samples_count = src.len() * samples_count_per_byte;
 
         let mut dst = vec![0; samples_count * output_bytes_per_input_sample];
-        let transform_fn =
-            super::super::create_transform_fn(&info, Transformations::EXPAND).unwrap();
-        transform_fn(src, dst.as_mut_slice(), &info);
+        let transform_fn = super::super::create_transform_fn(
+            &info,
+            Transformations::EXPAND,
+            &Default::default(),
+            super::super::TransformStage::All,
+        )
+        .unwrap();
+        transform_fn(
+            src,
+            dst.as_mut_slice(),
+            &info,
+            &mut super::super::ScratchRows::default(),
+        );
 
         {
             // Compare the memoization-based calculations with the old, non-memoized code.
@@ -338,10 +379,10 @@ mod test {
         fn create_expected_rgba_palette(plte: &[u8], trns: &[u8]) -> [[u8; 4]; 256] {
             let mut rgba = [[1, 2, 3, 4]; 256];
             for (i, rgba) in rgba.iter_mut().enumerate() {
-                rgba[0] = plte.get(i * 3 + 0).map(|&r| r).unwrap_or(0);
-                rgba[1] = plte.get(i * 3 + 1).map(|&g| g).unwrap_or(0);
-                rgba[2] = plte.get(i * 3 + 2).map(|&b| b).unwrap_or(0);
-                rgba[3] = trns.get(i * 1 + 0).map(|&a| a).unwrap_or(0xFF);
+                rgba[0] = plte.get(i * 3).copied().unwrap_or(0);
+                rgba[1] = plte.get(i * 3 + 1).copied().unwrap_or(0);
+                rgba[2] = plte.get(i * 3 + 2).copied().unwrap_or(0);
+                rgba[3] = trns.get(i).copied().unwrap_or(0xFF);
             }
             rgba
         }
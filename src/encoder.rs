@@ -6,14 +6,22 @@ use std::{borrow, error, fmt, io, mem, ops, result};
 use crc32fast::Hasher as Crc32;
 use flate2::write::ZlibEncoder;
 
+use crate::adam7::{self, Adam7Iterator};
 use crate::chunk::{self, ChunkType};
+use crate::color_math::chromaticities_to_f64;
 use crate::common::{
-    AnimationControl, BitDepth, BlendOp, BytesPerPixel, ColorType, Compression, DisposeOp,
-    FrameControl, Info, ParameterError, ParameterErrorKind, PixelDimensions, ScaledFloat,
+    AnimationControl, BitDepth, BlendOp, BytesPerPixel, ChunkPosition, CodingIndependentCodePoints,
+    ColorSpacePreset, ColorType, Compression, DisposeOp, FrameControl, Info, ParameterError,
+    ParameterErrorKind, PixelDimensions, ScaledFloat, SourceChromaticities, Time, Transformations,
+    UnknownChunk,
 };
 use crate::filter::{filter, AdaptiveFilterType, FilterType};
+use crate::icc::{self, ToneCurve};
+use crate::pixel::{alpha, swizzle::Swizzle};
+use crate::srgb;
 use crate::text_metadata::{
-    EncodableTextChunk, ITXtChunk, TEXtChunk, TextEncodingError, ZTXtChunk,
+    encode_iso_8859_1, is_valid_keyword, EncodableTextChunk, ITXtChunk, TEXtChunk,
+    TextEncodingError, ZTXtChunk,
 };
 use crate::traits::WriteBytesExt;
 
@@ -38,6 +46,14 @@ enum FormatErrorKind {
     ZeroHeight,
     InvalidColorCombination(BitDepth, ColorType),
     NoPalette,
+    InvalidBackgroundColor,
+    InvalidTime,
+    DuplicateTime,
+    InvalidSignificantBits,
+    InvalidCodingIndependentCodePoints,
+    InvalidExifMetadata,
+    InvalidIccProfileName,
+    IccProfileWithSrgb,
     // TODO: wait, what?
     WrittenTooMuch(usize),
     NotAnimated,
@@ -84,6 +100,28 @@ impl fmt::Display for FormatError {
                 depth, color
             ),
             NoPalette => write!(fmt, "can't write indexed image without palette"),
+            InvalidBackgroundColor => write!(
+                fmt,
+                "background color does not match the color type, bit depth or palette"
+            ),
+            InvalidTime => write!(fmt, "time of last modification is out of range"),
+            DuplicateTime => write!(fmt, "time of last modification has already been written"),
+            InvalidSignificantBits => write!(
+                fmt,
+                "significant bits do not match the color type or bit depth"
+            ),
+            InvalidCodingIndependentCodePoints => write!(
+                fmt,
+                "coding-independent code points must use the identity matrix coefficients"
+            ),
+            InvalidExifMetadata => write!(fmt, "exif metadata does not start with a TIFF header"),
+            InvalidIccProfileName => write!(
+                fmt,
+                "ICC profile name must be 1-79 printable Latin-1 characters without extra spaces"
+            ),
+            IccProfileWithSrgb => {
+                write!(fmt, "an ICC profile can't be written with the sRGB chunk")
+            }
             WrittenTooMuch(index) => write!(fmt, "wrong data size, got {} bytes too many", index),
             NotAnimated => write!(fmt, "not an animation"),
             OutOfBounds => write!(
@@ -158,6 +196,7 @@ struct Options {
     adaptive_filter: AdaptiveFilterType,
     sep_def_img: bool,
     validate_sequence: bool,
+    transformations: Transformations,
 }
 
 impl<'a, W: Write> Encoder<'a, W> {
@@ -251,6 +290,31 @@ impl<'a, W: Write> Encoder<'a, W> {
         self.info.trns = Some(trns.into());
     }
 
+    /// Set the number of significant bits of each channel, stored in the sBIT chunk.
+    ///
+    /// There must be one entry per channel of the color type (3 for indexed images), each between
+    /// 1 and the bit depth (8 for indexed images). Otherwise [`Encoder::write_header`] returns an
+    /// error.
+    pub fn set_sbit<T: Into<Cow<'a, [u8]>>>(&mut self, sbit: T) {
+        self.info.sbit = Some(sbit.into());
+    }
+
+    /// Set the default background color, stored in the bKGD chunk.
+    ///
+    /// The variant of the color must match the color type of the image and its samples must fit
+    /// into the bit depth, otherwise [`Encoder::write_header`] returns an error.
+    pub fn set_background_color(&mut self, background_color: super::BackgroundColor) {
+        self.info.bkgd = Some(background_color);
+    }
+
+    /// Set the time of the last image modification, stored in the tIME chunk.
+    ///
+    /// The chunk is written with the header. Use [`Writer::write_last_modified`] instead to place
+    /// it after the image data. An out-of-range time makes [`Encoder::write_header`] fail.
+    pub fn set_last_modified(&mut self, time: Time) {
+        self.info.last_modified = Some(time);
+    }
+
     /// Set the display gamma of the source system on which the image was generated or last edited.
     pub fn set_source_gamma(&mut self, source_gamma: ScaledFloat) {
         self.info.source_gamma = Some(source_gamma);
@@ -269,8 +333,165 @@ impl<'a, W: Write> Encoder<'a, W> {
     ///
     /// Matching source gamma and chromaticities chunks are added automatically.
     /// Any manually specified source gamma or chromaticities will be ignored.
+    /// This replaces an ICC profile set with [`Encoder::set_icc_profile`].
     pub fn set_srgb(&mut self, rendering_intent: super::SrgbRenderingIntent) {
         self.info.srgb = Some(rendering_intent);
+        self.info.icc_profile = None;
+        self.info.icc_profile_name = None;
+    }
+
+    /// Embed an ICC profile describing the color space of the image, stored compressed in the
+    /// iCCP chunk.
+    ///
+    /// The name must be between 1 and 79 printable Latin-1 characters, without leading, trailing
+    /// or consecutive spaces. The profile replaces the sRGB color space set with
+    /// [`Encoder::set_srgb`], a PNG image should not contain both.
+    pub fn set_icc_profile<T: Into<Cow<'a, [u8]>>>(
+        &mut self,
+        name: &str,
+        profile: T,
+    ) -> Result<()> {
+        validate_icc_profile_name(name)?;
+        self.info.icc_profile = Some(profile.into());
+        self.info.icc_profile_name = Some(name.to_string());
+        self.info.srgb = None;
+        Ok(())
+    }
+
+    /// Set the coding-independent code points, stored in the cICP chunk.
+    ///
+    /// These signal the color space and transfer function of the image, e.g. PQ or HLG for HDR
+    /// content. PNG images are always RGB, so the matrix coefficients must be 0 or
+    /// [`Encoder::write_header`] returns an error.
+    pub fn set_coding_independent_code_points(&mut self, cicp: super::CodingIndependentCodePoints) {
+        self.info.coding_independent_code_points = Some(cicp);
+    }
+
+    /// Declare the color space of the image with a consistent set of chunks.
+    ///
+    /// Besides the chunk identifying the color space, fallbacks are written for decoders that
+    /// don't support it:
+    ///
+    /// * sRGB is stored in the sRGB chunk, with matching gAMA and cHRM chunks.
+    /// * Display P3, Rec. 2020 and linear sRGB are stored in the cICP chunk, with an ICC profile
+    ///   and the closest gAMA and cHRM chunks.
+    /// * Custom color spaces are stored as an ICC profile and gAMA and cHRM chunks.
+    ///
+    /// This replaces the color space set with [`Encoder::set_srgb`], [`Encoder::set_icc_profile`],
+    /// [`Encoder::set_coding_independent_code_points`], [`Encoder::set_source_gamma`] and
+    /// [`Encoder::set_source_chromaticities`].
+    pub fn set_color_space(&mut self, color_space: ColorSpacePreset) {
+        let srgb_curve = ToneCurve::Parametric {
+            function_type: 3,
+            params: [
+                2.4,
+                1.0 / 1.055,
+                0.055 / 1.055,
+                1.0 / 12.92,
+                0.04045,
+                0.0,
+                0.0,
+            ],
+        };
+        let (name, gamma, chromaticities, curve, cicp) = match color_space {
+            ColorSpacePreset::Srgb(rendering_intent) => {
+                self.set_srgb(rendering_intent);
+                self.info.source_gamma = Some(srgb::substitute_gamma());
+                self.info.source_chromaticities = Some(srgb::substitute_chromaticities());
+                self.info.coding_independent_code_points = None;
+                return;
+            }
+            ColorSpacePreset::DisplayP3 => (
+                "Display P3",
+                srgb::substitute_gamma(),
+                SourceChromaticities::new(
+                    (0.3127, 0.3290),
+                    (0.680, 0.320),
+                    (0.265, 0.690),
+                    (0.150, 0.060),
+                ),
+                srgb_curve,
+                Some((12, 13)),
+            ),
+            ColorSpacePreset::Rec2020 => (
+                "Rec. 2020",
+                // The power law closest to the BT.709 curve.
+                ScaledFloat::from_scaled(50_000),
+                SourceChromaticities::new(
+                    (0.3127, 0.3290),
+                    (0.708, 0.292),
+                    (0.170, 0.797),
+                    (0.131, 0.046),
+                ),
+                ToneCurve::Parametric {
+                    function_type: 3,
+                    params: [
+                        1.0 / 0.45,
+                        1.0 / 1.099,
+                        0.099 / 1.099,
+                        1.0 / 4.5,
+                        0.081,
+                        0.0,
+                        0.0,
+                    ],
+                },
+                Some((9, 1)),
+            ),
+            ColorSpacePreset::LinearSrgb => (
+                "Linear sRGB",
+                ScaledFloat::from_scaled(100_000),
+                srgb::substitute_chromaticities(),
+                ToneCurve::Gamma(1.0),
+                Some((1, 8)),
+            ),
+            ColorSpacePreset::Custom {
+                gamma,
+                chromaticities,
+            } => (
+                "Custom",
+                gamma,
+                chromaticities,
+                ToneCurve::Gamma(1.0 / f64::from(gamma.into_value())),
+                None,
+            ),
+        };
+
+        let profile = Some(gamma)
+            .filter(|gamma| gamma.into_scaled() > 0)
+            .and_then(|_| {
+                icc::matrix_trc_profile(name, chromaticities_to_f64(chromaticities), &curve)
+            });
+        self.info.srgb = None;
+        self.info.source_gamma = Some(gamma);
+        self.info.source_chromaticities = Some(chromaticities);
+        self.info.icc_profile_name = profile.as_ref().map(|_| name.to_string());
+        self.info.icc_profile = profile.map(Cow::Owned);
+        self.info.coding_independent_code_points = cicp.map(
+            |(color_primaries, transfer_function)| CodingIndependentCodePoints {
+                color_primaries,
+                transfer_function,
+                matrix_coefficients: 0,
+                is_video_full_range_image: true,
+            },
+        );
+    }
+
+    /// Set the Exif metadata, stored in the eXIf chunk.
+    ///
+    /// The data is written as is and must be a TIFF structure, starting with the `II` or `MM`
+    /// byte order mark. Otherwise [`Encoder::write_header`] returns an error.
+    pub fn set_exif_metadata<T: Into<Cow<'a, [u8]>>>(&mut self, exif: T) {
+        self.info.exif_metadata = Some(exif.into());
+    }
+
+    /// Set the color volume of the mastering display, stored in the mDCV chunk.
+    pub fn set_mastering_display_color_volume(&mut self, mdcv: super::MasteringDisplayColorVolume) {
+        self.info.mastering_display_color_volume = Some(mdcv);
+    }
+
+    /// Set the light level information of HDR content, stored in the cLLI chunk.
+    pub fn set_content_light_level_info(&mut self, clli: super::ContentLightLevelInfo) {
+        self.info.content_light_level = Some(clli);
     }
 
     /// Start encoding by writing the header data.
@@ -294,6 +515,26 @@ impl<'a, W: Write> Encoder<'a, W> {
         self.info.bit_depth = depth;
     }
 
+    /// Set the layout of the image data that is later supplied, if it differs from PNG.
+    ///
+    /// [`Transformations::BGR`], [`Transformations::SWAP_ALPHA`] and
+    /// [`Transformations::INVERT_ALPHA`] describe image data with 8 or 16 bits per sample that is
+    /// converted back to the order of PNG before encoding, e.g. BGRA or ARGB for an RGBA image.
+    /// [`Transformations::SWAP_ENDIAN`] describes little endian 16-bit samples.
+    /// [`Transformations::PREMULTIPLY_ALPHA`] describes color samples premultiplied with alpha,
+    /// which are divided by alpha before encoding. Other transformations are ignored.
+    pub fn set_transformations(&mut self, transformations: Transformations) {
+        self.options.transformations = transformations;
+    }
+
+    /// Set whether the image data is written interlaced with the Adam7 method.
+    ///
+    /// The image data is still supplied row by row, the encoder reorders it into the seven
+    /// passes. This allows decoders to show a coarse preview of a partially loaded image.
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.info.interlaced = interlaced;
+    }
+
     /// Set compression parameters.
     ///
     /// Accepts a `Compression` or any type that can transform into a `Compression`. Notably `deflate::Compression` and
@@ -485,6 +726,8 @@ pub struct Writer<W: Write> {
     /// A flag to note when the IEND chunk was already added.
     /// This is only set on code paths that drop `Self` to control the destructor.
     iend_written: bool,
+    /// Unknown chunks to be written after the image data, right before IEND.
+    trailing_chunks: Vec<UnknownChunk>,
 }
 
 /// Contains the subset of attributes of [Info] needed for [Writer] to function
@@ -497,6 +740,9 @@ struct PartialInfo {
     animation_control: Option<AnimationControl>,
     compression: Compression,
     has_palette: bool,
+    /// Whether a tIME chunk has been written.
+    has_time: bool,
+    interlaced: bool,
 }
 
 impl PartialInfo {
@@ -510,6 +756,8 @@ impl PartialInfo {
             animation_control: info.animation_control,
             compression: info.compression,
             has_palette: info.palette.is_some(),
+            has_time: info.last_modified.is_some(),
+            interlaced: info.interlaced,
         }
     }
 
@@ -539,6 +787,7 @@ impl PartialInfo {
             frame_control: self.frame_control,
             animation_control: self.animation_control,
             compression: self.compression,
+            interlaced: self.interlaced,
             ..Default::default()
         }
     }
@@ -546,6 +795,36 @@ impl PartialInfo {
 
 const DEFAULT_BUFFER_LENGTH: usize = 4 * 1024;
 
+/// Returns whether the image data is premultiplied and has to be divided by alpha.
+fn unpremultiplies(color_type: ColorType, bit_depth: BitDepth, transform: Transformations) -> bool {
+    transform.contains(Transformations::PREMULTIPLY_ALPHA)
+        && alpha::has_alpha(color_type, bit_depth)
+}
+
+/// Reorders the rows of a frame into the seven Adam7 passes.
+///
+/// Returns the length of the rows of each non-empty pass together with the rows themselves.
+fn adam7_passes(info: &PartialInfo, data: &[u8], width: u32, height: u32) -> Vec<(usize, Vec<u8>)> {
+    let bits_pp = info.to_info().bits_per_pixel() as u8;
+    let mut passes: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut current_pass = 0;
+
+    for (pass, line_no, pass_width) in Adam7Iterator::new(width, height) {
+        let line_len = info.raw_row_length_from_width(pass_width) - 1;
+        if pass != current_pass {
+            current_pass = pass;
+            passes.push((line_len, Vec::new()));
+        }
+
+        let rows = &mut passes.last_mut().unwrap().1;
+        let start = rows.len();
+        rows.resize(start + line_len, 0);
+        adam7::extract_pass(data, width, &mut rows[start..], pass, line_no, bits_pp);
+    }
+
+    passes
+}
+
 pub(crate) fn write_chunk<W: Write>(mut w: W, name: chunk::ChunkType, data: &[u8]) -> Result<()> {
     w.write_be(data.len() as u32)?;
     w.write_all(&name.0)?;
@@ -557,6 +836,41 @@ pub(crate) fn write_chunk<W: Write>(mut w: W, name: chunk::ChunkType, data: &[u8
     Ok(())
 }
 
+/// Writes an iCCP chunk with the zlib compressed `profile`.
+pub(crate) fn write_iccp_chunk<W: Write>(
+    w: W,
+    name: &str,
+    profile: &[u8],
+    compression: Compression,
+) -> Result<()> {
+    validate_icc_profile_name(name)?;
+    let mut data = encode_iso_8859_1(name)?;
+    // Null separator and compression method, the only valid value is 0.
+    data.extend_from_slice(&[0, 0]);
+
+    let mut encoder = ZlibEncoder::new(data, compression.to_options());
+    encoder.write_all(profile)?;
+    write_chunk(w, chunk::iCCP, &encoder.finish()?)
+}
+
+fn validate_icc_profile_name(name: &str) -> Result<()> {
+    if is_valid_keyword(name) {
+        Ok(())
+    } else {
+        Err(EncodingError::Format(
+            FormatErrorKind::InvalidIccProfileName.into(),
+        ))
+    }
+}
+
+fn validate_time(time: Time) -> Result<()> {
+    if time.is_valid() {
+        Ok(())
+    } else {
+        Err(EncodingError::Format(FormatErrorKind::InvalidTime.into()))
+    }
+}
+
 impl<W: Write> Writer<W> {
     fn new(w: W, info: PartialInfo, options: Options) -> Writer<W> {
         Writer {
@@ -566,6 +880,7 @@ impl<W: Write> Writer<W> {
             images_written: 0,
             animation_written: 0,
             iend_written: false,
+            trailing_chunks: Vec::new(),
         }
     }
 
@@ -589,9 +904,69 @@ impl<W: Write> Writer<W> {
             ));
         }
 
+        if let Some(sbit) = &info.sbit {
+            if !info.is_valid_sbit(sbit) {
+                return Err(EncodingError::Format(
+                    FormatErrorKind::InvalidSignificantBits.into(),
+                ));
+            }
+        }
+
+        if let Some(cicp) = info.coding_independent_code_points {
+            if cicp.matrix_coefficients != 0 {
+                return Err(EncodingError::Format(
+                    FormatErrorKind::InvalidCodingIndependentCodePoints.into(),
+                ));
+            }
+        }
+
+        if info.icc_profile.is_some() {
+            // Decoded images may contain both, but the profile must not be written next to an
+            // sRGB chunk.
+            if info.srgb.is_some() {
+                return Err(EncodingError::Format(
+                    FormatErrorKind::IccProfileWithSrgb.into(),
+                ));
+            }
+            if let Some(name) = &info.icc_profile_name {
+                validate_icc_profile_name(name)?;
+            }
+        }
+
+        if let Some(exif) = &info.exif_metadata {
+            if !crate::common::is_valid_exif(exif) {
+                return Err(EncodingError::Format(
+                    FormatErrorKind::InvalidExifMetadata.into(),
+                ));
+            }
+        }
+
+        if let Some(bkgd) = info.bkgd {
+            if !bkgd.is_valid_for(info.color_type, info.bit_depth, info.palette.as_deref()) {
+                return Err(EncodingError::Format(
+                    FormatErrorKind::InvalidBackgroundColor.into(),
+                ));
+            }
+        }
+
+        if let Some(time) = info.last_modified {
+            validate_time(time)?;
+        }
+
         self.w.write_all(&[137, 80, 78, 71, 13, 10, 26, 10])?; // PNG signature
         info.encode(&mut self.w)?;
 
+        // Same selection as `Info::encode_unknown_chunks`, but written when finishing.
+        self.trailing_chunks = info
+            .unknown_chunks
+            .iter()
+            .filter(|unknown| {
+                unknown.position == ChunkPosition::AfterIdat
+                    && chunk::safe_to_copy(unknown.chunk_type)
+            })
+            .cloned()
+            .collect();
+
         Ok(self)
     }
 
@@ -614,6 +989,21 @@ impl<W: Write> Writer<W> {
         text_chunk.encode(&mut self.w)
     }
 
+    /// Write a tIME chunk with the time of the last image modification.
+    ///
+    /// This can be called before or after the image data. The chunk must appear at most once in
+    /// an image, so this returns an error if it was already written, including with the header
+    /// by [`Encoder::set_last_modified`].
+    pub fn write_last_modified(&mut self, time: Time) -> Result<()> {
+        if self.info.has_time {
+            return Err(EncodingError::Format(FormatErrorKind::DuplicateTime.into()));
+        }
+        validate_time(time)?;
+        time.encode(&mut self.w)?;
+        self.info.has_time = true;
+        Ok(())
+    }
+
     /// Check if we should allow writing another image.
     fn validate_new_image(&self) -> Result<()> {
         if !self.options.validate_sequence {
@@ -686,8 +1076,37 @@ impl<W: Write> Writer<W> {
             ));
         }
 
-        let prev = vec![0; in_len];
-        let mut prev = prev.as_slice();
+        let (color_type, bit_depth) = (self.info.color_type, self.info.bit_depth);
+        let swizzle = Swizzle::new(color_type, bit_depth, self.options.transformations);
+        let unpremultiply = unpremultiplies(color_type, bit_depth, self.options.transformations);
+        let data = if swizzle.is_some() || unpremultiply {
+            let mut data = data.to_vec();
+            if let Some(swizzle) = swizzle {
+                swizzle.revert(&mut data);
+            }
+            if unpremultiply {
+                alpha::unpremultiply_row(&mut data, color_type, bit_depth);
+            }
+            Cow::Owned(data)
+        } else {
+            Cow::Borrowed(data)
+        };
+        let data = &*data;
+
+        // Each Adam7 pass is filtered as an image of its own, starting over from an empty row.
+        let passes = if self.info.interlaced {
+            adam7_passes(&self.info, data, width as u32, height as u32)
+                .into_iter()
+                .map(|(line_len, rows)| (line_len, Cow::Owned(rows)))
+                .collect()
+        } else {
+            vec![(in_len, Cow::Borrowed(data))]
+        };
+        let filtered_size: usize = passes
+            .iter()
+            .map(|(line_len, rows)| rows.len() / line_len * (line_len + 1))
+            .sum();
+        let zeros = vec![0; in_len];
 
         let bpp = self.info.bpp_in_prediction();
         let filter_method = self.options.filter;
@@ -698,24 +1117,28 @@ impl<W: Write> Writer<W> {
                 let mut compressor = fdeflate::Compressor::new(std::io::Cursor::new(Vec::new()))?;
 
                 let mut current = vec![0; in_len + 1];
-                for line in data.chunks(in_len) {
-                    let filter_type = filter(
-                        filter_method,
-                        adaptive_method,
-                        bpp,
-                        prev,
-                        line,
-                        &mut current[1..],
-                    );
-
-                    current[0] = filter_type as u8;
-                    compressor.write_data(&current)?;
-                    prev = line;
+                for (line_len, rows) in &passes {
+                    let current = &mut current[..line_len + 1];
+                    let mut prev = &zeros[..*line_len];
+                    for line in rows.chunks(*line_len) {
+                        let filter_type = filter(
+                            filter_method,
+                            adaptive_method,
+                            bpp,
+                            prev,
+                            line,
+                            &mut current[1..],
+                        );
+
+                        current[0] = filter_type as u8;
+                        compressor.write_data(current)?;
+                        prev = line;
+                    }
                 }
 
                 let compressed = compressor.finish()?.into_inner();
                 if compressed.len()
-                    > fdeflate::StoredOnlyCompressor::<()>::compressed_size((in_len + 1) * height)
+                    > fdeflate::StoredOnlyCompressor::<()>::compressed_size(filtered_size)
                 {
                     // Write uncompressed data since the result from fast compression would take
                     // more space than that.
@@ -726,9 +1149,11 @@ impl<W: Write> Writer<W> {
                     // compression ratio.
                     let mut compressor =
                         fdeflate::StoredOnlyCompressor::new(std::io::Cursor::new(Vec::new()))?;
-                    for line in data.chunks(in_len) {
-                        compressor.write_data(&[0])?;
-                        compressor.write_data(line)?;
+                    for (line_len, rows) in &passes {
+                        for line in rows.chunks(*line_len) {
+                            compressor.write_data(&[0])?;
+                            compressor.write_data(line)?;
+                        }
                     }
                     compressor.finish()?.into_inner()
                 } else {
@@ -739,19 +1164,17 @@ impl<W: Write> Writer<W> {
                 let mut current = vec![0; in_len];
 
                 let mut zlib = ZlibEncoder::new(Vec::new(), self.info.compression.to_options());
-                for line in data.chunks(in_len) {
-                    let filter_type = filter(
-                        filter_method,
-                        adaptive_method,
-                        bpp,
-                        prev,
-                        line,
-                        &mut current,
-                    );
-
-                    zlib.write_all(&[filter_type as u8])?;
-                    zlib.write_all(&current)?;
-                    prev = line;
+                for (line_len, rows) in &passes {
+                    let current = &mut current[..*line_len];
+                    let mut prev = &zeros[..*line_len];
+                    for line in rows.chunks(*line_len) {
+                        let filter_type =
+                            filter(filter_method, adaptive_method, bpp, prev, line, current);
+
+                        zlib.write_all(&[filter_type as u8])?;
+                        zlib.write_all(current)?;
+                        prev = line;
+                    }
                 }
                 zlib.finish()?
             }
@@ -803,6 +1226,9 @@ impl<W: Write> Writer<W> {
 
     fn write_iend(&mut self) -> Result<()> {
         self.iend_written = true;
+        for unknown in mem::take(&mut self.trailing_chunks) {
+            unknown.encode(&mut self.w)?;
+        }
         self.write_chunk(chunk::IEND, &[])
     }
 
@@ -1008,7 +1434,7 @@ impl<W: Write> Writer<W> {
     ///
     /// This borrows the writer which allows for manually appending additional
     /// chunks after the image data has been written.
-    pub fn stream_writer(&mut self) -> Result<StreamWriter<W>> {
+    pub fn stream_writer(&mut self) -> Result<StreamWriter<'_, W>> {
         self.stream_writer_with_size(DEFAULT_BUFFER_LENGTH)
     }
 
@@ -1017,7 +1443,7 @@ impl<W: Write> Writer<W> {
     /// See [`stream_writer`].
     ///
     /// [`stream_writer`]: #fn.stream_writer
-    pub fn stream_writer_with_size(&mut self, size: usize) -> Result<StreamWriter<W>> {
+    pub fn stream_writer_with_size(&mut self, size: usize) -> Result<StreamWriter<'_, W>> {
         StreamWriter::new(ChunkOutput::Borrowed(self), size)
     }
 
@@ -1141,21 +1567,23 @@ impl<'a, W: Write> ChunkWriter<'a, W> {
     /// the next one
     fn next_frame_info(&self) -> (usize, usize) {
         let wrt = self.writer.deref();
+        let (width, height) = self.frame_dimensions();
+
+        let in_len = wrt.info.raw_row_length_from_width(width) - 1;
+        let data_size = in_len * height as usize;
+
+        (in_len, data_size)
+    }
+
+    /// Returns the width and height of the next frame
+    fn frame_dimensions(&self) -> (u32, u32) {
+        let wrt = self.writer.deref();
 
-        let width: usize;
-        let height: usize;
         if let Some(fctl) = wrt.info.frame_control {
-            width = fctl.width as usize;
-            height = fctl.height as usize;
+            (fctl.width, fctl.height)
         } else {
-            width = wrt.info.width as usize;
-            height = wrt.info.height as usize;
+            (wrt.info.width, wrt.info.height)
         }
-
-        let in_len = wrt.info.raw_row_length_from_width(width as u32) - 1;
-        let data_size = in_len * height;
-
-        (in_len, data_size)
     }
 
     /// NOTE: this bypasses the internal buffer so the flush method should be called before this
@@ -1226,8 +1654,7 @@ impl<'a, W: Write> Write for ChunkWriter<'a, W> {
 
             // Prepare the next animated frame, if any.
             let no_fctl = wrt.should_skip_frame_control_on_default_image();
-            if wrt.info.frame_control.is_some() && !no_fctl {
-                let fctl = wrt.info.frame_control.as_mut().unwrap();
+            if let Some(fctl) = wrt.info.frame_control.as_mut().filter(|_| !no_fctl) {
                 self.buffer[0..4].copy_from_slice(&fctl.sequence_number.to_be_bytes());
                 fctl.sequence_number += 1;
                 self.index = 4;
@@ -1314,6 +1741,8 @@ pub struct StreamWriter<'a, W: Write> {
     line_len: usize,
     /// size of the frame (width * height * sample_size)
     to_write: usize,
+    /// Rows of the current frame, collected until it is complete when the image is interlaced
+    interlaced_buf: Option<Vec<u8>>,
 
     width: u32,
     height: u32,
@@ -1323,6 +1752,10 @@ pub struct StreamWriter<'a, W: Write> {
     adaptive_filter: AdaptiveFilterType,
     fctl: Option<FrameControl>,
     compression: Compression,
+    /// Converts the rows to the order of PNG, see `Encoder::set_transformations`.
+    swizzle: Option<Swizzle>,
+    /// The layout of rows to divide by alpha, see `Encoder::set_transformations`.
+    unpremultiply: Option<(ColorType, BitDepth)>,
 }
 
 impl<'a, W: Write> StreamWriter<'a, W> {
@@ -1332,6 +1765,7 @@ impl<'a, W: Write> StreamWriter<'a, W> {
             height,
             frame_control: fctl,
             compression,
+            interlaced,
             ..
         } = writer.info;
 
@@ -1339,6 +1773,16 @@ impl<'a, W: Write> StreamWriter<'a, W> {
         let in_len = writer.info.raw_row_length() - 1;
         let filter = writer.options.filter;
         let adaptive_filter = writer.options.adaptive_filter;
+        let swizzle = Swizzle::new(
+            writer.info.color_type,
+            writer.info.bit_depth,
+            writer.options.transformations,
+        );
+        let unpremultiply = Some((writer.info.color_type, writer.info.bit_depth)).filter(
+            |&(color_type, bit_depth)| {
+                unpremultiplies(color_type, bit_depth, writer.options.transformations)
+            },
+        );
         let prev_buf = vec![0; in_len];
         let curr_buf = vec![0; in_len];
 
@@ -1359,8 +1803,11 @@ impl<'a, W: Write> StreamWriter<'a, W> {
             adaptive_filter,
             line_len,
             to_write,
+            interlaced_buf: if interlaced { Some(Vec::new()) } else { None },
             fctl,
             compression,
+            swizzle,
+            unpremultiply,
         })
     }
 
@@ -1603,6 +2050,46 @@ impl<'a, W: Write> StreamWriter<'a, W> {
 
         Ok(())
     }
+
+    /// Filters and compresses the collected rows of an interlaced frame, one Adam7 pass after
+    /// the other.
+    fn write_interlaced_frame(&mut self) -> Result<()> {
+        // This can't fail as the other variant is used only to allow the zlib encoder to finish
+        let wrt = match &mut self.writer {
+            Wrapper::Zlib(wrt) => wrt,
+            _ => unreachable!(),
+        };
+        let rows = match &mut self.interlaced_buf {
+            Some(rows) => rows,
+            None => unreachable!(),
+        };
+
+        let chunk_writer = wrt.get_ref();
+        let (width, height) = chunk_writer.frame_dimensions();
+        let passes = adam7_passes(&chunk_writer.writer.info, rows, width, height);
+        rows.clear();
+
+        for (line_len, pass) in passes {
+            let mut prev = vec![0; line_len];
+            let mut filtered = vec![0; line_len];
+            for line in pass.chunks(line_len) {
+                let filter_type = filter(
+                    self.filter,
+                    self.adaptive_filter,
+                    self.bpp,
+                    &prev,
+                    line,
+                    &mut filtered,
+                );
+
+                wrt.write_all(&[filter_type as u8])?;
+                wrt.write_all(&filtered)?;
+                prev.copy_from_slice(line);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'a, W: Write> Write for StreamWriter<'a, W> {
@@ -1639,6 +2126,27 @@ impl<'a, W: Write> Write for StreamWriter<'a, W> {
         self.to_write -= written;
 
         if self.index == self.line_len {
+            if let Some(swizzle) = &self.swizzle {
+                swizzle.revert(&mut self.curr_buf[..self.line_len]);
+            }
+            if let Some((color_type, bit_depth)) = self.unpremultiply {
+                alpha::unpremultiply_row(
+                    &mut self.curr_buf[..self.line_len],
+                    color_type,
+                    bit_depth,
+                );
+            }
+
+            if let Some(rows) = &mut self.interlaced_buf {
+                // The passes of an interlaced frame can only be written once all rows are known.
+                rows.extend_from_slice(&self.curr_buf[..self.line_len]);
+                self.index = 0;
+                if self.to_write == 0 {
+                    self.write_interlaced_frame()?;
+                }
+                return Ok(written);
+            }
+
             // TODO: reuse this buffer between rows.
             let mut filtered = vec![0; self.curr_buf.len()];
             let filter_type = filter(
@@ -1713,7 +2221,11 @@ impl Compression {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Decoder;
+    use crate::icc::IccProfile;
+    use crate::{
+        BackgroundColor, ColorSpace, ContentLightLevelInfo, Decoder, Luminance,
+        MasteringDisplayColorVolume, SrgbRenderingIntent,
+    };
 
     use rand::{thread_rng, Rng};
     use std::cmp;
@@ -1818,6 +2330,105 @@ mod tests {
         }
     }
 
+    #[test]
+    fn roundtrip_interlaced() {
+        for path in glob::glob("tests/pngsuite/*.png")
+            .unwrap()
+            .map(|r| r.unwrap())
+        {
+            if path.file_name().unwrap().to_str().unwrap().starts_with('x') {
+                // x* files are expected to fail to decode
+                continue;
+            }
+            // Decode image
+            let decoder = Decoder::new(File::open(&path).unwrap());
+            let mut reader = decoder.read_info().unwrap();
+            let mut buf = vec![0; reader.output_buffer_size()];
+            let info = reader.next_frame(&mut buf).unwrap();
+
+            for stream in [false, true] {
+                // Encode decoded image with interlacing
+                let mut out = Vec::new();
+                {
+                    let mut encoder = Encoder::new(&mut out, info.width, info.height);
+                    encoder.set_color(info.color_type);
+                    encoder.set_depth(info.bit_depth);
+                    encoder.set_interlaced(true);
+                    if let Some(palette) = &reader.info().palette {
+                        encoder.set_palette(palette.clone());
+                    }
+                    let mut encoder = encoder.write_header().unwrap();
+                    if stream {
+                        let mut stream_writer = encoder.stream_writer().unwrap();
+                        let mut outer_wrapper = RandomChunkWriter {
+                            rng: thread_rng(),
+                            w: &mut stream_writer,
+                        };
+                        outer_wrapper.write_all(&buf).unwrap();
+                        stream_writer.finish().unwrap();
+                    } else {
+                        encoder.write_image_data(&buf).unwrap();
+                    }
+                }
+                // Decode encoded decoded image
+                let decoder = Decoder::new(&*out);
+                let mut reader = decoder.read_info().unwrap();
+                assert!(reader.info().interlaced);
+                let mut buf2 = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut buf2).unwrap();
+                assert_eq!(buf, buf2, "{} (stream: {})", path.display(), stream);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_interlaced_small_sizes() {
+        for &bit_depth in &[
+            BitDepth::One,
+            BitDepth::Two,
+            BitDepth::Four,
+            BitDepth::Eight,
+        ] {
+            for width in 1..10u32 {
+                for height in 1..10u32 {
+                    let info = Info {
+                        width,
+                        height,
+                        bit_depth,
+                        ..Info::default()
+                    };
+                    let mut data = vec![0; info.raw_row_length() - 1];
+                    let mut image = Vec::new();
+                    for row in 0..height {
+                        data.iter_mut()
+                            .enumerate()
+                            .for_each(|(i, b)| *b = (i as u32 * 31 + row * 97) as u8);
+                        // Padding bits in the last byte of a row aren't preserved.
+                        let pad = data.len() * 8 - (width * bit_depth as u32) as usize;
+                        *data.last_mut().unwrap() &= 0xffu8 << pad;
+                        image.extend_from_slice(&data);
+                    }
+
+                    let mut out = Vec::new();
+                    {
+                        let mut encoder = Encoder::new(&mut out, width, height);
+                        encoder.set_color(ColorType::Grayscale);
+                        encoder.set_depth(bit_depth);
+                        encoder.set_interlaced(true);
+                        let mut writer = encoder.write_header().unwrap();
+                        writer.write_image_data(&image).unwrap();
+                    }
+
+                    let decoder = Decoder::new(&*out);
+                    let mut reader = decoder.read_info().unwrap();
+                    let mut decoded = vec![0; reader.output_buffer_size()];
+                    reader.next_frame(&mut decoded).unwrap();
+                    assert_eq!(decoded, image, "{}x{} at {:?}", width, height, bit_depth);
+                }
+            }
+        }
+    }
+
     #[test]
     fn image_palette() -> Result<()> {
         for &bit_depth in &[1u8, 2, 4, 8] {
@@ -2111,6 +2722,564 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn background_color_roundtrip() -> Result<()> {
+        let roundtrip = |color: ColorType, depth: BitDepth, bkgd: BackgroundColor| -> Result<()> {
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            if color == ColorType::Indexed {
+                encoder.set_palette(vec![0; 6]);
+            }
+            encoder.set_background_color(bkgd);
+            let data = vec![0; color.samples() * ((depth as usize + 7) / 8)];
+            encoder.write_header()?.write_image_data(&data)?;
+
+            let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+            assert_eq!(reader.info().bkgd, Some(bkgd));
+            Ok(())
+        };
+
+        roundtrip(
+            ColorType::Grayscale,
+            BitDepth::Four,
+            BackgroundColor::Grayscale(15),
+        )?;
+        roundtrip(
+            ColorType::GrayscaleAlpha,
+            BitDepth::Sixteen,
+            BackgroundColor::Grayscale(0xabcd),
+        )?;
+        roundtrip(
+            ColorType::Rgba,
+            BitDepth::Eight,
+            BackgroundColor::Rgb {
+                red: 1,
+                green: 2,
+                blue: 255,
+            },
+        )?;
+        roundtrip(
+            ColorType::Indexed,
+            BitDepth::Two,
+            BackgroundColor::Indexed(1),
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_background_color() {
+        let invalid = |color: ColorType, bkgd: BackgroundColor| {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_color(color);
+            encoder.set_depth(BitDepth::Eight);
+            encoder.set_palette(vec![0; 6]);
+            encoder.set_background_color(bkgd);
+            assert!(encoder.write_header().is_err());
+        };
+
+        invalid(ColorType::Rgb, BackgroundColor::Grayscale(0));
+        invalid(ColorType::Grayscale, BackgroundColor::Grayscale(256));
+        invalid(ColorType::Indexed, BackgroundColor::Indexed(2));
+    }
+
+    #[test]
+    fn sbit_roundtrip() -> Result<()> {
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_color(ColorType::GrayscaleAlpha);
+        encoder.set_depth(BitDepth::Sixteen);
+        encoder.set_sbit(&[12, 1][..]);
+        encoder.write_header()?.write_image_data(&[0; 4])?;
+
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().sbit.as_deref(), Some(&[12, 1][..]));
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_sbit() {
+        let invalid = |color: ColorType, sbit: &'static [u8]| {
+            let mut encoder = Encoder::new(Vec::new(), 1, 1);
+            encoder.set_color(color);
+            encoder.set_depth(BitDepth::Eight);
+            encoder.set_sbit(sbit);
+            assert!(encoder.write_header().is_err());
+        };
+
+        invalid(ColorType::Rgb, &[8, 8]);
+        invalid(ColorType::Indexed, &[8]);
+        invalid(ColorType::Grayscale, &[0]);
+        invalid(ColorType::Rgba, &[8, 8, 8, 9]);
+    }
+
+    #[test]
+    fn coding_independent_code_points_roundtrip() -> Result<()> {
+        // BT.2100 PQ in full range.
+        let cicp = CodingIndependentCodePoints {
+            color_primaries: 9,
+            transfer_function: 16,
+            matrix_coefficients: 0,
+            is_video_full_range_image: true,
+        };
+
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Sixteen);
+        encoder.set_coding_independent_code_points(cicp);
+        encoder.write_header()?.write_image_data(&[0; 6])?;
+
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().coding_independent_code_points, Some(cicp));
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_coding_independent_code_points(CodingIndependentCodePoints {
+            matrix_coefficients: 9,
+            ..cicp
+        });
+        assert!(encoder.write_header().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn color_space_precedence() -> Result<()> {
+        let decode = |configure: &dyn Fn(&mut Encoder<&mut Vec<u8>>)| -> Result<Info<'static>> {
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            configure(&mut encoder);
+            encoder.write_header()?.write_image_data(&[0])?;
+            let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+            Ok(reader.info().clone())
+        };
+        let cicp = CodingIndependentCodePoints {
+            color_primaries: 1,
+            transfer_function: 13,
+            matrix_coefficients: 0,
+            is_video_full_range_image: true,
+        };
+        let gamma = ScaledFloat::new(1.0 / 2.2);
+
+        let info = decode(&|_| {})?;
+        assert_eq!(info.color_space(), ColorSpace::Unspecified);
+
+        let info = decode(&|encoder| encoder.set_source_gamma(gamma))?;
+        assert_eq!(
+            info.color_space(),
+            ColorSpace::GammaChromaticities {
+                gamma: Some(gamma),
+                chromaticities: None,
+            }
+        );
+
+        // The decoder fills in the gamma and chromaticities of sRGB.
+        let info = decode(&|encoder| encoder.set_srgb(SrgbRenderingIntent::Saturation))?;
+        assert!(info.source_gamma.is_some());
+        assert_eq!(
+            info.color_space(),
+            ColorSpace::Srgb(SrgbRenderingIntent::Saturation)
+        );
+
+        let info = decode(&|encoder| {
+            encoder.set_source_gamma(gamma);
+            encoder.set_icc_profile("profile", &[1, 2, 3][..]).unwrap();
+        })?;
+        assert_eq!(info.color_space(), ColorSpace::IccProfile(&[1, 2, 3]));
+
+        let info = decode(&|encoder| {
+            encoder.set_icc_profile("profile", &[1, 2, 3][..]).unwrap();
+            encoder.set_coding_independent_code_points(cicp);
+        })?;
+        assert_eq!(
+            info.color_space(),
+            ColorSpace::CodingIndependentCodePoints(cicp)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn color_space_presets() -> Result<()> {
+        let decode = |color_space: ColorSpacePreset| -> Result<Info<'static>> {
+            let mut buffer = vec![];
+            let mut encoder = Encoder::new(&mut buffer, 1, 1);
+            encoder.set_color(ColorType::Rgb);
+            encoder.set_icc_profile("replaced", &[0][..])?;
+            encoder.set_color_space(color_space);
+            encoder.write_header()?.write_image_data(&[0; 3])?;
+            let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+            Ok(reader.info().clone())
+        };
+
+        let info = decode(ColorSpacePreset::Srgb(SrgbRenderingIntent::Perceptual))?;
+        assert_eq!(
+            info.color_space(),
+            ColorSpace::Srgb(SrgbRenderingIntent::Perceptual)
+        );
+        assert_eq!(info.icc_profile, None);
+        assert!(info.gama_chunk.is_some() && info.chrm_chunk.is_some());
+
+        for (preset, primaries, transfer) in [
+            (ColorSpacePreset::DisplayP3, 12, 13),
+            (ColorSpacePreset::Rec2020, 9, 1),
+            (ColorSpacePreset::LinearSrgb, 1, 8),
+        ] {
+            let info = decode(preset)?;
+            match info.color_space() {
+                ColorSpace::CodingIndependentCodePoints(cicp) => {
+                    assert_eq!(cicp.color_primaries, primaries);
+                    assert_eq!(cicp.transfer_function, transfer);
+                }
+                other => panic!("unexpected color space {:?}", other),
+            }
+            let profile = IccProfile::parse(info.icc_profile.as_deref().unwrap()).unwrap();
+            assert!(profile.srgb_transform().is_some());
+            assert_eq!(info.srgb, None);
+            assert!(info.gama_chunk.is_some() && info.chrm_chunk.is_some());
+        }
+
+        let gamma = ScaledFloat::new(1.0 / 1.8);
+        let chromaticities = SourceChromaticities::new(
+            (0.3457, 0.3585),
+            (0.7347, 0.2653),
+            (0.1596, 0.8404),
+            (0.0366, 0.0001),
+        );
+        let info = decode(ColorSpacePreset::Custom {
+            gamma,
+            chromaticities,
+        })?;
+        assert_eq!(info.coding_independent_code_points, None);
+        assert_eq!(info.icc_profile_name.as_deref(), Some("Custom"));
+        assert!(matches!(info.color_space(), ColorSpace::IccProfile(_)));
+        assert_eq!(info.gama_chunk, Some(gamma));
+        assert_eq!(info.chrm_chunk, Some(chromaticities));
+
+        Ok(())
+    }
+
+    /// Encodes RGBA `data` in two rows, either at once or through a stream writer.
+    fn encode_rgba(
+        data: &[u8],
+        bit_depth: BitDepth,
+        transformations: Transformations,
+        stream: bool,
+    ) -> Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let pixels = data.len() / (bit_depth as usize / 2);
+        let mut encoder = Encoder::new(&mut png, pixels as u32 / 2, 2);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(bit_depth);
+        encoder.set_transformations(transformations);
+        let mut writer = encoder.write_header()?;
+        if stream {
+            writer.stream_writer()?.write_all(data)?;
+        } else {
+            writer.write_image_data(data)?;
+        }
+        writer.finish()?;
+        Ok(png)
+    }
+
+    #[test]
+    fn swizzled_input() -> Result<()> {
+        let rgba: Vec<u8> = (0..64).collect();
+        let argb: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|p| [p[3], p[0], p[1], p[2]])
+            .collect();
+        let bgra_16: Vec<u8> = rgba
+            .chunks_exact(8)
+            .flat_map(|p| [&p[4..6], &p[2..4], &p[..2], &[!p[6], !p[7]]].concat())
+            .collect();
+        let swapped_16: Vec<u8> = rgba.chunks_exact(2).flat_map(|s| [s[1], s[0]]).collect();
+
+        for (data, bit_depth, transformations) in [
+            (&argb, BitDepth::Eight, Transformations::SWAP_ALPHA),
+            (
+                &bgra_16,
+                BitDepth::Sixteen,
+                Transformations::BGR | Transformations::INVERT_ALPHA,
+            ),
+            (&swapped_16, BitDepth::Sixteen, Transformations::SWAP_ENDIAN),
+        ] {
+            for stream in [false, true] {
+                let png = encode_rgba(data, bit_depth, transformations, stream)?;
+                let mut reader = Decoder::new(&*png).read_info().unwrap();
+                let mut decoded = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut decoded).unwrap();
+                assert_eq!(decoded, rgba);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn premultiplied_input() -> Result<()> {
+        // Premultiplied colors never exceed alpha.
+        let rgba: Vec<u8> = (0..=255u8)
+            .step_by(15)
+            .flat_map(|a| [a / 3, a / 2, a, a])
+            .collect();
+        let rgba_16: Vec<u8> = rgba
+            .iter()
+            .flat_map(|&s| [s, s.wrapping_mul(7) & s])
+            .collect();
+
+        for (data, bit_depth) in [(&rgba, BitDepth::Eight), (&rgba_16, BitDepth::Sixteen)] {
+            for stream in [false, true] {
+                let transformations = Transformations::PREMULTIPLY_ALPHA;
+                let png = encode_rgba(data, bit_depth, transformations, stream)?;
+
+                // The encoded samples are divided by alpha, blue is equal to alpha in the input.
+                let mut reader = Decoder::new(&*png).read_info().unwrap();
+                let mut straight = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut straight).unwrap();
+                let sample_size = bit_depth as usize / 8;
+                for (straight, pixel) in straight
+                    .chunks_exact(4 * sample_size)
+                    .zip(data.chunks_exact(4 * sample_size))
+                {
+                    let (blue, alpha) = straight[2 * sample_size..].split_at(sample_size);
+                    assert_eq!(alpha, &pixel[3 * sample_size..]);
+                    let expected = if alpha.iter().all(|&byte| byte == 0) {
+                        0
+                    } else {
+                        0xFF
+                    };
+                    assert!(blue.iter().all(|&byte| byte == expected));
+                }
+                assert_ne!(straight, *data);
+
+                // Premultiplying again when decoding restores the input.
+                let mut decoder = Decoder::new(&*png);
+                decoder.set_transformations(Transformations::PREMULTIPLY_ALPHA);
+                let mut reader = decoder.read_info().unwrap();
+                let mut decoded = vec![0; reader.output_buffer_size()];
+                reader.next_frame(&mut decoded).unwrap();
+                assert_eq!(decoded, *data);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn hdr_metadata_roundtrip() -> Result<()> {
+        // BT.2020 primaries with a D65 white point, as used by HDR10.
+        let mdcv = MasteringDisplayColorVolume {
+            chromaticities: SourceChromaticities::new(
+                (0.3127, 0.3290),
+                (0.708, 0.292),
+                (0.170, 0.797),
+                (0.131, 0.046),
+            ),
+            max_luminance: Luminance::new(1000.0),
+            min_luminance: Luminance::from_scaled(1),
+        };
+        let clli = ContentLightLevelInfo {
+            max_content_light_level: Luminance::new(800.0),
+            max_frame_average_light_level: Luminance::new(400.0),
+        };
+
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Sixteen);
+        encoder.set_mastering_display_color_volume(mdcv);
+        encoder.set_content_light_level_info(clli);
+        encoder.write_header()?.write_image_data(&[0; 6])?;
+
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().mastering_display_color_volume, Some(mdcv));
+        assert_eq!(reader.info().content_light_level, Some(clli));
+        assert_eq!(mdcv.max_luminance.into_value(), 1000.0);
+        assert_eq!(mdcv.min_luminance.into_value(), 0.0001);
+
+        Ok(())
+    }
+
+    #[test]
+    fn exif_metadata_roundtrip() -> Result<()> {
+        // A big endian TIFF header with a single IFD entry, orientation 6 (rotate 90° clockwise).
+        let exif: &[u8] = &[
+            b'M', b'M', 0, 42, 0, 0, 0, 8, // header, IFD at offset 8
+            0, 1, // 1 entry
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // orientation, SHORT, count 1, value 6
+            0, 0, 0, 0, // no next IFD
+        ];
+
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_exif_metadata(exif);
+        encoder.write_header()?.write_image_data(&[0])?;
+
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().exif_metadata.as_deref(), Some(exif));
+        assert_eq!(reader.info().exif_orientation(), Some(6));
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_exif_metadata(&exif[1..]);
+        assert!(encoder.write_header().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_chunks_roundtrip() -> Result<()> {
+        let unknown = |chunk_type: &[u8; 4], position| UnknownChunk {
+            chunk_type: ChunkType(*chunk_type),
+            data: chunk_type.to_vec(),
+            position,
+        };
+        let before_plte = unknown(b"prVt", ChunkPosition::BeforePlte);
+        let before_idat = unknown(b"clBr", ChunkPosition::BeforeIdat);
+        let unsafe_to_copy = unknown(b"prVT", ChunkPosition::BeforeIdat);
+        let after_idat = unknown(b"dtAl", ChunkPosition::AfterIdat);
+
+        let mut info = Info::with_size(1, 1);
+        info.color_type = ColorType::Indexed;
+        info.palette = Some(Cow::Borrowed(&[0, 0, 0]));
+        info.unknown_chunks = vec![
+            before_plte.clone(),
+            before_idat.clone(),
+            unsafe_to_copy,
+            after_idat.clone(),
+        ];
+
+        let mut buffer = vec![];
+        let mut writer = Encoder::with_info(&mut buffer, info)?.write_header()?;
+        writer.write_image_data(&[0])?;
+        writer.finish()?;
+
+        let mut decoder = Decoder::new(Cursor::new(buffer));
+        decoder.set_retain_unknown_chunks(true);
+        let mut reader = decoder.read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        reader.finish().unwrap();
+        assert_eq!(
+            reader.info().unknown_chunks,
+            [before_plte, before_idat, after_idat]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn icc_profile_roundtrip() -> Result<()> {
+        let profile: Vec<u8> = (0..=255).cycle().take(3000).collect();
+
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_icc_profile("Display P3", &profile[..])?;
+        encoder.write_header()?.write_image_data(&[0])?;
+
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().icc_profile.as_deref(), Some(&profile[..]));
+        assert_eq!(
+            reader.info().icc_profile_name.as_deref(),
+            Some("Display P3")
+        );
+        assert_eq!(reader.info().srgb, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_icc_profile_name() {
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        for name in ["", " P3", "P3 ", "Display  P3", "Display\nP3", "Display ✓"] {
+            assert!(
+                encoder.set_icc_profile(name, &[0][..]).is_err(),
+                "{:?}",
+                name
+            );
+        }
+        assert!(encoder.set_icc_profile(&"a".repeat(80), &[0][..]).is_err());
+        assert!(encoder.set_icc_profile(&"a".repeat(79), &[0][..]).is_ok());
+    }
+
+    #[test]
+    fn icc_profile_with_srgb() -> Result<()> {
+        // Decoded images may contain both, but they are not written together.
+        let mut info = Info::with_size(1, 1);
+        info.icc_profile = Some(Cow::Borrowed(&[0]));
+        info.srgb = Some(crate::SrgbRenderingIntent::Perceptual);
+        let encoder = Encoder::with_info(Vec::new(), info)?;
+        assert!(matches!(
+            encoder.write_header(),
+            Err(EncodingError::Format(_))
+        ));
+
+        // The setters replace each other.
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_icc_profile("ICC profile", &[0][..]).unwrap();
+        encoder.set_srgb(crate::SrgbRenderingIntent::Perceptual);
+        assert!(encoder.write_header().is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_modified_roundtrip() -> Result<()> {
+        let time = Time::new(2024, 2, 29, 23, 59, 60).unwrap();
+
+        // Written with the header.
+        let mut buffer = vec![];
+        let mut encoder = Encoder::new(&mut buffer, 1, 1);
+        encoder.set_last_modified(time);
+        encoder.write_header()?.write_image_data(&[0])?;
+        let reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().last_modified, Some(time));
+
+        // Written after the image data.
+        let mut buffer = vec![];
+        let mut writer = Encoder::new(&mut buffer, 1, 1).write_header()?;
+        writer.write_image_data(&[0])?;
+        writer.write_last_modified(time)?;
+        assert!(writer.write_last_modified(time).is_err());
+        writer.finish()?;
+        let mut reader = Decoder::new(Cursor::new(buffer)).read_info().unwrap();
+        assert_eq!(reader.info().last_modified, None);
+        reader.finish().unwrap();
+        assert_eq!(reader.info().last_modified, Some(time));
+
+        // The chunk is written only once.
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_last_modified(time);
+        let mut writer = encoder.write_header()?;
+        assert!(writer.write_last_modified(time).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn expect_error_on_invalid_time() -> Result<()> {
+        let time = Time {
+            year: 2024,
+            month: 2,
+            day: 0,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+
+        let mut encoder = Encoder::new(Vec::new(), 1, 1);
+        encoder.set_last_modified(time);
+        assert!(encoder.write_header().is_err());
+
+        let mut writer = Encoder::new(Vec::new(), 1, 1).write_header()?;
+        writer.write_image_data(&[0])?;
+        assert!(writer.write_last_modified(time).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn write_image_chunks_beyond_first() -> Result<()> {
         let width = 10;
@@ -0,0 +1,705 @@
+//! # Minimal interpretation of ICC profiles
+//!
+//! An image may describe its color space with an ICC profile embedded in the `iCCP` chunk, which
+//! is available as raw bytes in [`Info::icc_profile`](crate::Info::icc_profile). Fully
+//! interpreting them requires a color management system, but most profiles found in PNG images
+//! are simple display profiles. These describe RGB with the XYZ colorants of the primaries and a
+//! tone reproduction curve per channel, or grayscale with a single curve.
+//!
+//! This module parses the header and tag table of a profile and extracts the tags of such
+//! matrix/TRC profiles, which is enough to convert the image to sRGB.
+//!
+//! ```
+//! use png::icc::IccProfile;
+//!
+//! # fn convert(info: &png::Info) -> Option<()> {
+//! let profile = IccProfile::parse(info.icc_profile.as_deref()?)?;
+//! let transform = profile.srgb_transform()?;
+//! assert_eq!(transform.channels(), 3);
+//! let mut pixel = [0.2, 0.5, 0.8];
+//! transform.convert(&mut pixel);
+//! # Some(())
+//! # }
+//! ```
+//!
+//! Decoding with [`Decoder::set_convert_to_srgb`](crate::Decoder::set_convert_to_srgb) applies
+//! this conversion to the whole image.
+
+use crate::color_math::{
+    adaptation, apply, invert, multiply, rgb_to_xyz, xy_to_xyz, Matrix, SRGB_PRIMARIES,
+};
+
+/// The XYZ values of the D50 illuminant of the profile connection space.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+/// An entry of the tag table of a profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IccTag {
+    /// The four byte signature identifying the tag, such as `rXYZ`.
+    pub signature: [u8; 4],
+    /// The position of the tag data from the start of the profile.
+    pub offset: u32,
+    /// The length of the tag data.
+    pub size: u32,
+}
+
+/// A tone reproduction curve, mapping device values to linear light.
+///
+/// Both input and output are between 0 and 1.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ToneCurve {
+    /// A power law `y = x ^ gamma`, which includes the identity.
+    Gamma(f64),
+    /// Evenly spaced samples of the curve, to be interpolated linearly.
+    Sampled(Vec<u16>),
+    /// One of the parametric curves of the `para` tag type, with the parameters
+    /// `g`, `a`, `b`, `c`, `d`, `e` and `f` as far as they are used by the function type.
+    Parametric {
+        /// The function type between 0 and 4.
+        function_type: u16,
+        /// The parameters, unused ones being zero.
+        params: [f64; 7],
+    },
+}
+
+impl ToneCurve {
+    /// Evaluates the curve, converting a device value to linear light.
+    pub fn eval(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match self {
+            ToneCurve::Gamma(gamma) => x.powf(*gamma),
+            // A table of one entry is a constant, an empty one the identity.
+            ToneCurve::Sampled(samples) if samples.is_empty() => x,
+            ToneCurve::Sampled(samples) if samples.len() == 1 => f64::from(samples[0]) / 65535.0,
+            ToneCurve::Sampled(samples) => {
+                let position = x * (samples.len() - 1) as f64;
+                let index = (position.floor() as usize).min(samples.len() - 2);
+                let fraction = position - index as f64;
+                let (low, high) = (f64::from(samples[index]), f64::from(samples[index + 1]));
+                (low + (high - low) * fraction) / 65535.0
+            }
+            ToneCurve::Parametric {
+                function_type,
+                params: [g, a, b, c, d, e, f],
+            } => match function_type {
+                0 => x.powf(*g),
+                1 if x >= -b / a => (a * x + b).powf(*g),
+                1 => 0.0,
+                2 if x >= -b / a => (a * x + b).powf(*g) + c,
+                2 => *c,
+                3 if x >= *d => (a * x + b).powf(*g),
+                3 => c * x,
+                _ if x >= *d => (a * x + b).powf(*g) + e,
+                _ => c * x + f,
+            },
+        };
+        if y.is_finite() {
+            y.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        match self {
+            ToneCurve::Gamma(gamma) => {
+                data.extend_from_slice(b"para\0\0\0\0\0\0\0\0");
+                data.extend_from_slice(&((gamma * 65536.0).round() as i32).to_be_bytes());
+            }
+            ToneCurve::Sampled(samples) => {
+                data.extend_from_slice(b"curv\0\0\0\0");
+                data.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+                data.extend(samples.iter().flat_map(|v| v.to_be_bytes()));
+            }
+            ToneCurve::Parametric {
+                function_type,
+                params,
+            } => {
+                data.extend_from_slice(b"para\0\0\0\0");
+                data.extend_from_slice(&function_type.to_be_bytes());
+                data.extend_from_slice(&[0; 2]);
+                let count = [1, 3, 4, 5, 7][usize::from(*function_type).min(4)];
+                for param in &params[..count] {
+                    data.extend_from_slice(&((param * 65536.0).round() as i32).to_be_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        match data.get(..4)? {
+            b"curv" => {
+                let count = read_u32(data, 8)? as usize;
+                match count {
+                    0 => Some(ToneCurve::Gamma(1.0)),
+                    1 => Some(ToneCurve::Gamma(f64::from(read_u16(data, 12)?) / 256.0)),
+                    _ => {
+                        let end = count.checked_mul(2)?.checked_add(12)?;
+                        let samples = data.get(12..end)?;
+                        Some(ToneCurve::Sampled(
+                            samples
+                                .chunks_exact(2)
+                                .map(|v| u16::from_be_bytes([v[0], v[1]]))
+                                .collect(),
+                        ))
+                    }
+                }
+            }
+            b"para" => {
+                let function_type = read_u16(data, 8)?;
+                let count = match function_type {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let mut params = [0.0; 7];
+                for (i, param) in params.iter_mut().take(count).enumerate() {
+                    *param = read_s15_fixed16(data, 12 + 4 * i)?;
+                }
+                Some(ToneCurve::Parametric {
+                    function_type,
+                    params,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The parsed header, tag table and matrix/TRC tags of an ICC profile.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IccProfile {
+    /// The major and minor version of the profile format.
+    pub version: (u8, u8),
+    /// The profile class, `mntr` for display profiles.
+    pub class: [u8; 4],
+    /// The color space of the image data, `RGB ` or `GRAY` for the profiles understood here.
+    pub color_space: [u8; 4],
+    /// The profile connection space, `XYZ ` or `Lab `.
+    pub connection_space: [u8; 4],
+    /// All entries of the tag table.
+    pub tags: Vec<IccTag>,
+    /// The media white point of the `wtpt` tag.
+    pub white_point: Option<[f64; 3]>,
+    /// The XYZ colorant of the red primary from the `rXYZ` tag, adapted to D50.
+    pub red_colorant: Option<[f64; 3]>,
+    /// The XYZ colorant of the green primary from the `gXYZ` tag, adapted to D50.
+    pub green_colorant: Option<[f64; 3]>,
+    /// The XYZ colorant of the blue primary from the `bXYZ` tag, adapted to D50.
+    pub blue_colorant: Option<[f64; 3]>,
+    /// The curve of the red channel from the `rTRC` tag.
+    pub red_trc: Option<ToneCurve>,
+    /// The curve of the green channel from the `gTRC` tag.
+    pub green_trc: Option<ToneCurve>,
+    /// The curve of the blue channel from the `bTRC` tag.
+    pub blue_trc: Option<ToneCurve>,
+    /// The curve of a grayscale profile from the `kTRC` tag.
+    pub gray_trc: Option<ToneCurve>,
+}
+
+impl IccProfile {
+    /// Parses the profile, as stored in [`Info::icc_profile`](crate::Info::icc_profile).
+    ///
+    /// Returns `None` if the header or tag table are malformed. Tags of an unexpected type are
+    /// left as `None`.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 132 || data.get(36..40)? != b"acsp" {
+            return None;
+        }
+        let size = (read_u32(data, 0)? as usize).min(data.len());
+        let data = &data[..size];
+
+        let count = read_u32(data, 128)? as usize;
+        if count > (data.len() - 132) / 12 {
+            return None;
+        }
+        let tags: Vec<IccTag> = (0..count)
+            .map(|i| {
+                let entry = 132 + 12 * i;
+                Some(IccTag {
+                    signature: signature(data, entry)?,
+                    offset: read_u32(data, entry + 4)?,
+                    size: read_u32(data, entry + 8)?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        let tag_data = |name: &[u8; 4]| -> Option<&[u8]> {
+            let tag = tags.iter().find(|tag| &tag.signature == name)?;
+            let start = tag.offset as usize;
+            data.get(start..start.checked_add(tag.size as usize)?)
+        };
+        let xyz = |name: &[u8; 4]| -> Option<[f64; 3]> {
+            let data = tag_data(name)?;
+            if data.get(..4)? != b"XYZ " {
+                return None;
+            }
+            Some([
+                read_s15_fixed16(data, 8)?,
+                read_s15_fixed16(data, 12)?,
+                read_s15_fixed16(data, 16)?,
+            ])
+        };
+        let curve = |name: &[u8; 4]| ToneCurve::parse(tag_data(name)?);
+
+        Some(IccProfile {
+            version: (data[8], data[9] >> 4),
+            class: signature(data, 12)?,
+            color_space: signature(data, 16)?,
+            connection_space: signature(data, 20)?,
+            white_point: xyz(b"wtpt"),
+            red_colorant: xyz(b"rXYZ"),
+            green_colorant: xyz(b"gXYZ"),
+            blue_colorant: xyz(b"bXYZ"),
+            red_trc: curve(b"rTRC"),
+            green_trc: curve(b"gTRC"),
+            blue_trc: curve(b"bTRC"),
+            gray_trc: curve(b"kTRC"),
+            tags,
+        })
+    }
+
+    /// Whether the profile describes sRGB itself, such that converting to sRGB changes nothing.
+    ///
+    /// The colorants, the white point and the curves must match those of sRGB up to the
+    /// precision they are stored with. A grayscale profile matches if its curve does.
+    pub fn is_srgb(&self) -> bool {
+        /// The tolerance of colorants and white point, stored as fixed point numbers.
+        const XYZ_TOLERANCE: f64 = 0.003;
+        /// The tolerance of curves, after encoding their output with the sRGB curve again.
+        const CURVE_TOLERANCE: f64 = 0.001;
+
+        let near =
+            |a: [f64; 3], b: [f64; 3]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < XYZ_TOLERANCE);
+        let is_srgb_curve = |curve: &Option<ToneCurve>| {
+            curve.as_ref().map_or(false, |curve| {
+                (0..=256).all(|i| {
+                    let x = f64::from(i) / 256.0;
+                    (srgb_encode(curve.eval(x)) - x).abs() < CURVE_TOLERANCE
+                })
+            })
+        };
+
+        if &self.connection_space != b"XYZ " {
+            return false;
+        }
+        // Version 2 profiles may state the white point of the display instead of D50.
+        let srgb_white = match xy_to_xyz(SRGB_PRIMARIES[0]) {
+            Some(white) => white,
+            None => return false,
+        };
+        if let Some(white_point) = self.white_point {
+            if !near(white_point, D50) && !near(white_point, srgb_white) {
+                return false;
+            }
+        }
+
+        match &self.color_space {
+            b"RGB " => {
+                let colorants = match srgb_colorants() {
+                    Some(colorants) => colorants,
+                    None => return false,
+                };
+                let matches = [self.red_colorant, self.green_colorant, self.blue_colorant]
+                    .iter()
+                    .zip(colorants)
+                    .all(|(colorant, expected)| colorant.map_or(false, |c| near(c, expected)));
+                matches
+                    && is_srgb_curve(&self.red_trc)
+                    && is_srgb_curve(&self.green_trc)
+                    && is_srgb_curve(&self.blue_trc)
+            }
+            b"GRAY" => is_srgb_curve(&self.gray_trc),
+            _ => false,
+        }
+    }
+
+    /// Creates the transform of the image data described by this profile into sRGB.
+    ///
+    /// Returns `None` unless this is an RGB profile with the colorant and curve of every channel,
+    /// or a grayscale profile with a curve, and the connection space is XYZ.
+    pub fn srgb_transform(&self) -> Option<SrgbTransform> {
+        if &self.connection_space != b"XYZ " {
+            return None;
+        }
+
+        match &self.color_space {
+            b"RGB " => {
+                let (r, g, b) = (
+                    self.red_colorant?,
+                    self.green_colorant?,
+                    self.blue_colorant?,
+                );
+                let to_xyz = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+                let srgb_to_xyz = rgb_to_xyz(SRGB_PRIMARIES)?;
+                let adapt = adaptation(D50, xy_to_xyz(SRGB_PRIMARIES[0])?)?;
+                let matrix = multiply(&invert(&srgb_to_xyz)?, &multiply(&adapt, &to_xyz));
+                Some(SrgbTransform {
+                    curves: vec![
+                        self.red_trc.clone()?,
+                        self.green_trc.clone()?,
+                        self.blue_trc.clone()?,
+                    ],
+                    matrix: Some(matrix),
+                })
+            }
+            b"GRAY" => Some(SrgbTransform {
+                curves: vec![self.gray_trc.clone()?],
+                matrix: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Converts image data described by an ICC profile to sRGB, see [`IccProfile::srgb_transform`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SrgbTransform {
+    /// One curve per color channel.
+    pub(crate) curves: Vec<ToneCurve>,
+    /// Maps linear RGB of the profile to linear sRGB, `None` for grayscale.
+    pub(crate) matrix: Option<Matrix>,
+}
+
+impl SrgbTransform {
+    /// The number of color samples of a pixel, 3 for RGB and 1 for grayscale.
+    pub fn channels(&self) -> usize {
+        self.curves.len()
+    }
+
+    /// Converts the color samples of one pixel, each between 0 and 1, into sRGB.
+    ///
+    /// `samples` must hold [`SrgbTransform::channels`] values. Colors outside of the sRGB gamut
+    /// are clipped.
+    pub fn convert(&self, samples: &mut [f32]) {
+        let mut linear = [0.0; 3];
+        for ((linear, sample), curve) in linear.iter_mut().zip(&*samples).zip(&self.curves) {
+            *linear = curve.eval(f64::from(*sample));
+        }
+        if let Some(m) = &self.matrix {
+            linear = apply(m, linear);
+        }
+        for (sample, linear) in samples.iter_mut().zip(linear) {
+            *sample = srgb_encode(linear.clamp(0.0, 1.0)) as f32;
+        }
+    }
+}
+
+/// The XYZ colorants of the sRGB primaries, adapted to D50 as they are stored in a profile.
+fn srgb_colorants() -> Option<[[f64; 3]; 3]> {
+    let to_d50 = adaptation(xy_to_xyz(SRGB_PRIMARIES[0])?, D50)?;
+    let m = multiply(&to_d50, &rgb_to_xyz(SRGB_PRIMARIES)?);
+    Some([0, 1, 2].map(|i| [m[0][i], m[1][i], m[2][i]]))
+}
+
+/// Encodes linear light between 0 and 1 with the sRGB curve.
+fn srgb_encode(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Builds a version 4 display profile for RGB with the given primaries and curve, as written by
+/// [`Encoder::set_color_space`](crate::Encoder::set_color_space).
+///
+/// Returns `None` if the primaries don't describe a valid color space.
+pub(crate) fn matrix_trc_profile(
+    description: &str,
+    primaries: [(f64, f64); 4],
+    curve: &ToneCurve,
+) -> Option<Vec<u8>> {
+    let to_d50 = adaptation(xy_to_xyz(primaries[0])?, D50)?;
+    let colorants = multiply(&to_d50, &rgb_to_xyz(primaries)?);
+    let column = |i: usize| [colorants[0][i], colorants[1][i], colorants[2][i]];
+
+    let text = |text: &str| {
+        let mut data = b"mluc\0\0\0\0".to_vec();
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&12u32.to_be_bytes());
+        data.extend_from_slice(b"enUS");
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        data.extend_from_slice(&(utf16.len() as u32).to_be_bytes());
+        data.extend_from_slice(&28u32.to_be_bytes());
+        data.extend_from_slice(&utf16);
+        data
+    };
+    let numbers = |type_signature: &[u8; 4], values: &[f64]| {
+        let mut data = type_signature.to_vec();
+        data.extend_from_slice(&[0; 4]);
+        for value in values {
+            data.extend_from_slice(&((value * 65536.0).round() as i32).to_be_bytes());
+        }
+        data
+    };
+    let xyz = |xyz: [f64; 3]| numbers(b"XYZ ", &xyz);
+    let chad: Vec<f64> = to_d50.iter().flatten().copied().collect();
+
+    // The curve is shared by all three channels.
+    let tags: [(&[&[u8; 4]], Vec<u8>); 8] = [
+        (&[b"desc"], text(description)),
+        (&[b"cprt"], text("No copyright, use freely")),
+        (&[b"wtpt"], xyz(D50)),
+        (&[b"chad"], numbers(b"sf32", &chad)),
+        (&[b"rXYZ"], xyz(column(0))),
+        (&[b"gXYZ"], xyz(column(1))),
+        (&[b"bXYZ"], xyz(column(2))),
+        (&[b"rTRC", b"gTRC", b"bTRC"], curve.to_bytes()),
+    ];
+    let tag_count: usize = tags.iter().map(|(signatures, _)| signatures.len()).sum();
+
+    let mut profile = vec![0; 128];
+    profile[8] = 4;
+    profile[9] = 0x30;
+    profile[12..16].copy_from_slice(b"mntr");
+    profile[16..20].copy_from_slice(b"RGB ");
+    profile[20..24].copy_from_slice(b"XYZ ");
+    profile[36..40].copy_from_slice(b"acsp");
+    for (i, value) in D50.iter().enumerate() {
+        let value = (value * 65536.0).round() as i32;
+        profile[68 + 4 * i..72 + 4 * i].copy_from_slice(&value.to_be_bytes());
+    }
+    profile.extend_from_slice(&(tag_count as u32).to_be_bytes());
+
+    // Tag data follows the tag table, each aligned to 4 bytes.
+    let mut data = Vec::new();
+    for (signatures, tag) in &tags {
+        let offset = (132 + 12 * tag_count + data.len()) as u32;
+        for signature in *signatures {
+            profile.extend_from_slice(*signature);
+            profile.extend_from_slice(&offset.to_be_bytes());
+            profile.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        }
+        data.extend_from_slice(tag);
+        data.resize((data.len() + 3) & !3, 0);
+    }
+    profile.extend_from_slice(&data);
+
+    let size = profile.len() as u32;
+    profile[..4].copy_from_slice(&size.to_be_bytes());
+    Some(profile)
+}
+
+fn signature(data: &[u8], offset: usize) -> Option<[u8; 4]> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    signature(data, offset).map(u32::from_be_bytes)
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    read_u32(data, offset).map(|v| f64::from(v as i32) / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+    use std::fs::File;
+
+    fn embedded_profile(path: &str) -> Vec<u8> {
+        let mut decoder = Decoder::new(File::open(path).unwrap());
+        decoder.ignore_checksums(true);
+        let reader = decoder.read_info().unwrap();
+        reader.info().icc_profile.as_deref().unwrap().to_vec()
+    }
+
+    fn assert_near_identity(transform: &SrgbTransform) {
+        for &pixel in &[
+            [0.2, 0.5, 0.8],
+            [1.0, 1.0, 1.0],
+            [0.0, 0.0, 0.0],
+            [0.9, 0.1, 0.4],
+        ] {
+            let mut converted = pixel;
+            transform.convert(&mut converted);
+            for (converted, original) in converted.iter().zip(&pixel) {
+                assert!((converted - original).abs() < 0.01, "{:?}", converted);
+            }
+        }
+    }
+
+    #[test]
+    fn parametric_srgb_profile() {
+        let profile = IccProfile::parse(&embedded_profile("tests/bugfixes/issue#430.png")).unwrap();
+        assert_eq!(profile.version, (4, 3));
+        assert_eq!(&profile.class, b"mntr");
+        assert_eq!(&profile.color_space, b"RGB ");
+        assert_eq!(profile.tags.len(), 13);
+        assert!(matches!(
+            profile.red_trc,
+            Some(ToneCurve::Parametric {
+                function_type: 3,
+                ..
+            })
+        ));
+        assert_eq!(profile.red_trc, profile.blue_trc);
+        assert_near_identity(&profile.srgb_transform().unwrap());
+        assert!(profile.is_srgb());
+    }
+
+    #[test]
+    fn sampled_srgb_profile() {
+        let profile =
+            IccProfile::parse(&embedded_profile("tests/bugfixes/issue#1825.png")).unwrap();
+        assert_eq!(profile.version, (2, 1));
+        match &profile.green_trc {
+            Some(ToneCurve::Sampled(samples)) => assert_eq!(samples.len(), 1024),
+            other => panic!("unexpected curve {:?}", other),
+        }
+        let white = profile.white_point.unwrap();
+        assert!((white[1] - 1.0).abs() < 0.01);
+        assert_near_identity(&profile.srgb_transform().unwrap());
+        assert!(profile.is_srgb());
+    }
+
+    /// Builds a profile with the given tags, in the order given.
+    fn build_profile(color_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut profile = vec![0; 128];
+        profile[8] = 4;
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(color_space);
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+
+        let mut offset = 132 + 12 * tags.len();
+        for (signature, data) in tags {
+            profile.extend_from_slice(*signature);
+            profile.extend_from_slice(&(offset as u32).to_be_bytes());
+            profile.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            offset += data.len();
+        }
+        for (_, data) in tags {
+            profile.extend_from_slice(data);
+        }
+        let size = profile.len() as u32;
+        profile[..4].copy_from_slice(&size.to_be_bytes());
+        profile
+    }
+
+    fn xyz_tag(xyz: [f64; 3]) -> Vec<u8> {
+        let mut data = b"XYZ \0\0\0\0".to_vec();
+        for v in xyz {
+            data.extend_from_slice(&((v * 65536.0).round() as i32).to_be_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn linear_gamma_profile() {
+        let linear = b"curv\0\0\0\0\0\0\0\x01\x01\x00".to_vec();
+        let data = build_profile(
+            b"RGB ",
+            &[
+                (b"wtpt", xyz_tag(D50)),
+                (b"rXYZ", xyz_tag([0.4361, 0.2225, 0.0139])),
+                (b"gXYZ", xyz_tag([0.3851, 0.7169, 0.0971])),
+                (b"bXYZ", xyz_tag([0.1431, 0.0606, 0.7141])),
+                (b"rTRC", linear.clone()),
+                (b"gTRC", linear.clone()),
+                (b"bTRC", linear),
+            ],
+        );
+
+        let profile = IccProfile::parse(&data).unwrap();
+        assert_eq!(profile.red_trc, Some(ToneCurve::Gamma(1.0)));
+        assert!(!profile.is_srgb());
+        let transform = profile.srgb_transform().unwrap();
+        let mut pixel = [0.5, 0.5, 0.5];
+        transform.convert(&mut pixel);
+        for sample in pixel {
+            assert!((sample - 0.7354).abs() < 0.005, "{}", sample);
+        }
+    }
+
+    #[test]
+    fn gray_profile() {
+        let mut gamma_2 = b"para\0\0\0\0\0\0\0\0".to_vec();
+        gamma_2.extend_from_slice(&0x0002_0000u32.to_be_bytes());
+        let data = build_profile(b"GRAY", &[(b"kTRC", gamma_2)]);
+
+        let transform = IccProfile::parse(&data).unwrap().srgb_transform().unwrap();
+        assert_eq!(transform.channels(), 1);
+        let mut pixel = [0.5];
+        transform.convert(&mut pixel);
+        assert!((pixel[0] - 0.5371).abs() < 0.005, "{}", pixel[0]);
+    }
+
+    #[test]
+    fn written_profile_roundtrip() {
+        use crate::color_math::{cicp_primaries, to_srgb_matrix};
+
+        let curve = ToneCurve::Parametric {
+            function_type: 3,
+            params: [
+                2.4,
+                1.0 / 1.055,
+                0.055 / 1.055,
+                1.0 / 12.92,
+                0.04045,
+                0.0,
+                0.0,
+            ],
+        };
+        let primaries = cicp_primaries(12).unwrap();
+        let data = matrix_trc_profile("Display P3", primaries, &curve).unwrap();
+
+        let profile = IccProfile::parse(&data).unwrap();
+        assert_eq!(profile.version, (4, 3));
+        assert_eq!(profile.tags.len(), 10);
+        let white = profile.white_point.unwrap();
+        assert!(white.iter().zip(D50).all(|(v, d50)| (v - d50).abs() < 1e-4));
+        assert_eq!(profile.red_trc, profile.blue_trc);
+        assert!(!profile.is_srgb());
+        let trc = profile.green_trc.as_ref().unwrap();
+        assert!((trc.eval(0.5) - curve.eval(0.5)).abs() < 1e-4);
+
+        let transform = profile.srgb_transform().unwrap();
+        let mut pixel = [0.6, 0.5, 0.4];
+        transform.convert(&mut pixel);
+
+        let linear = [0.6, 0.5, 0.4].map(|v| curve.eval(v));
+        let expected = apply(&to_srgb_matrix(primaries).unwrap(), linear);
+        for (converted, expected) in pixel.iter().zip(expected) {
+            let expected = 1.055 * expected.powf(1.0 / 2.4) - 0.055;
+            assert!((f64::from(*converted) - expected).abs() < 0.002);
+        }
+    }
+
+    #[test]
+    fn short_sampled_curves() {
+        assert_eq!(ToneCurve::Sampled(vec![]).eval(0.25), 0.25);
+        assert_eq!(
+            ToneCurve::Sampled(vec![0x8000]).eval(0.25),
+            32768.0 / 65535.0
+        );
+        assert_eq!(ToneCurve::Sampled(vec![0, 0xffff]).eval(0.25), 0.25);
+    }
+
+    #[test]
+    fn malformed_profiles() {
+        assert_eq!(IccProfile::parse(&[0; 64]), None);
+        let mut data = build_profile(b"RGB ", &[(b"rTRC", b"curv".to_vec())]);
+        // Truncated curve data is ignored.
+        let profile = IccProfile::parse(&data).unwrap();
+        assert_eq!(profile.red_trc, None);
+        assert_eq!(profile.srgb_transform(), None);
+        // A tag table larger than the profile is not.
+        data[128..132].copy_from_slice(&1000u32.to_be_bytes());
+        assert_eq!(IccProfile::parse(&data), None);
+    }
+}
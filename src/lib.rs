@@ -63,17 +63,21 @@
 
 mod adam7;
 pub mod chunk;
+mod color_math;
 mod common;
 mod decoder;
 mod encoder;
 mod filter;
+pub mod icc;
+mod pixel;
 mod srgb;
 pub mod text_metadata;
 mod traits;
 
 pub use crate::common::*;
 pub use crate::decoder::{
-    DecodeOptions, Decoded, Decoder, DecodingError, Limits, OutputInfo, Reader, StreamingDecoder,
+    CompositedFrameInfo, DecodeOptions, Decoded, Decoder, DecodingError, Limits, OutputInfo,
+    Reader, StreamingDecoder,
 };
 pub use crate::encoder::{Encoder, EncodingError, StreamWriter, Writer};
 pub use crate::filter::{AdaptiveFilterType, FilterType};
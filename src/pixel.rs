@@ -0,0 +1,5 @@
+//! Operations on the pixels of rows shared by the decoder, which applies the requested
+//! transformations, and the encoder, which reverts them on its input.
+
+pub(crate) mod alpha;
+pub(crate) mod swizzle;
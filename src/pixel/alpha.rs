@@ -0,0 +1,152 @@
+//! Multiplying the color samples of pixels with their alpha, for `PREMULTIPLY_ALPHA`, and the
+//! inverse for the encoder.
+
+use crate::{BitDepth, ColorType};
+
+/// Returns whether rows of the given layout have an alpha channel that can be premultiplied.
+pub(crate) fn has_alpha(color_type: ColorType, bit_depth: BitDepth) -> bool {
+    matches!(color_type, ColorType::GrayscaleAlpha | ColorType::Rgba)
+        && matches!(bit_depth, BitDepth::Eight | BitDepth::Sixteen)
+}
+
+/// Multiplies the color samples of each pixel in a row with its alpha, rounding to the nearest
+/// value. Rows without alpha are left unchanged.
+pub(crate) fn premultiply_row(row: &mut [u8], color_type: ColorType, bit_depth: BitDepth) {
+    if !has_alpha(color_type, bit_depth) {
+        return;
+    }
+    let channels = color_type.samples();
+    match bit_depth {
+        BitDepth::Sixteen => {
+            for pixel in row.chunks_exact_mut(channels * 2) {
+                let (color, alpha) = pixel.split_at_mut((channels - 1) * 2);
+                let alpha = u32::from(u16::from_be_bytes([alpha[0], alpha[1]]));
+                for sample in color.chunks_exact_mut(2) {
+                    let value = u32::from(u16::from_be_bytes([sample[0], sample[1]]));
+                    sample
+                        .copy_from_slice(&(premultiply(value, alpha, 0xFFFF) as u16).to_be_bytes());
+                }
+            }
+        }
+        _ => {
+            for pixel in row.chunks_exact_mut(channels) {
+                let (color, alpha) = pixel.split_at_mut(channels - 1);
+                let alpha = u32::from(alpha[0]);
+                for sample in color {
+                    *sample = premultiply(u32::from(*sample), alpha, 0xFF) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Divides the color samples of each premultiplied pixel in a row by its alpha, rounding to the
+/// nearest value. Fully transparent pixels become black. Rows without alpha are left unchanged.
+pub(crate) fn unpremultiply_row(row: &mut [u8], color_type: ColorType, bit_depth: BitDepth) {
+    if !has_alpha(color_type, bit_depth) {
+        return;
+    }
+    let channels = color_type.samples();
+    match bit_depth {
+        BitDepth::Sixteen => {
+            for pixel in row.chunks_exact_mut(channels * 2) {
+                let (color, alpha) = pixel.split_at_mut((channels - 1) * 2);
+                let alpha = u32::from(u16::from_be_bytes([alpha[0], alpha[1]]));
+                for sample in color.chunks_exact_mut(2) {
+                    let value = u32::from(u16::from_be_bytes([sample[0], sample[1]]));
+                    sample.copy_from_slice(
+                        &(unpremultiply(value, alpha, 0xFFFF) as u16).to_be_bytes(),
+                    );
+                }
+            }
+        }
+        _ => {
+            for pixel in row.chunks_exact_mut(channels) {
+                let (color, alpha) = pixel.split_at_mut(channels - 1);
+                let alpha = u32::from(alpha[0]);
+                for sample in color {
+                    *sample = unpremultiply(u32::from(*sample), alpha, 0xFF) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// Computes `value * alpha / max` rounded to the nearest integer. As `max` is odd the quotient is
+/// never exactly halfway between two integers, so adding half of `max` rounds correctly. The sum
+/// stays below `2^32` for 16-bit samples.
+fn premultiply(value: u32, alpha: u32, max: u32) -> u32 {
+    (value * alpha + max / 2) / max
+}
+
+/// Computes `value * max / alpha` rounded to the nearest integer and clamped to `max`, or zero if
+/// `alpha` is zero.
+fn unpremultiply(value: u32, alpha: u32, max: u32) -> u32 {
+    if alpha == 0 {
+        0
+    } else {
+        let value = u64::from(value.min(alpha));
+        ((value * u64::from(max) + u64::from(alpha / 2)) / u64::from(alpha)) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiply_rounds_to_nearest() {
+        for max in [0xFFu32, 0xFFFF] {
+            let step = if max == 0xFF { 1 } else { 257 };
+            for alpha in (0..=max).step_by(step) {
+                for value in (0..=max).step_by(step) {
+                    let exact = f64::from(value) * f64::from(alpha) / f64::from(max);
+                    assert_eq!(premultiply(value, alpha, max), exact.round() as u32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unpremultiply_inverts_premultiply() {
+        for alpha in 1..=0xFFu32 {
+            for value in 0..=0xFF {
+                let premultiplied = premultiply(value, alpha, 0xFF);
+                let restored = unpremultiply(premultiplied, alpha, 0xFF);
+                // The precision lost when premultiplying grows as alpha shrinks.
+                let tolerance = (0xFF + alpha - 1) / alpha / 2 + 1;
+                assert!(
+                    (restored as i32 - value as i32).unsigned_abs() <= tolerance,
+                    "value {}, alpha {}: restored {}",
+                    value,
+                    alpha,
+                    restored
+                );
+                // Opaque pixels are restored exactly.
+                if alpha == 0xFF {
+                    assert_eq!(restored, value);
+                }
+            }
+        }
+        assert_eq!(unpremultiply(0x7F, 0, 0xFF), 0);
+    }
+
+    #[test]
+    fn rows() {
+        let mut row = vec![0xFF, 0x80, 0x40, 0x80, 0x12, 0x34, 0x56, 0x00];
+        premultiply_row(&mut row, ColorType::Rgba, BitDepth::Eight);
+        assert_eq!(row, [0x80, 0x40, 0x20, 0x80, 0, 0, 0, 0]);
+        unpremultiply_row(&mut row, ColorType::Rgba, BitDepth::Eight);
+        assert_eq!(row, [0xFF, 0x80, 0x40, 0x80, 0, 0, 0, 0]);
+
+        let mut row = vec![0xFF, 0xFF, 0x80, 0x00];
+        premultiply_row(&mut row, ColorType::GrayscaleAlpha, BitDepth::Sixteen);
+        assert_eq!(row, [0x80, 0x00, 0x80, 0x00]);
+        unpremultiply_row(&mut row, ColorType::GrayscaleAlpha, BitDepth::Sixteen);
+        assert_eq!(row, [0xFF, 0xFF, 0x80, 0x00]);
+
+        let mut row = vec![0x12, 0x34, 0x56];
+        premultiply_row(&mut row, ColorType::Rgb, BitDepth::Eight);
+        assert_eq!(row, [0x12, 0x34, 0x56]);
+    }
+}
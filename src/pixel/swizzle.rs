@@ -0,0 +1,110 @@
+//! Reordering and inverting the channels of pixels, for `BGR`, `SWAP_ALPHA` and `INVERT_ALPHA`,
+//! and the byte order of samples for `SWAP_ENDIAN`.
+
+use crate::{BitDepth, ColorType, Transformations};
+
+/// The channel layout requested instead of the order of the samples in PNG.
+///
+/// The decoder applies it to the output rows, the encoder reverts it on the input rows.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Swizzle {
+    bgr: bool,
+    swap_alpha: bool,
+    invert_alpha: bool,
+    swap_endian: bool,
+    /// Bytes per pixel.
+    pixel_size: usize,
+    /// Bytes per sample.
+    sample_size: usize,
+}
+
+impl Swizzle {
+    /// Returns the swizzle requested by `transform` for rows of the given layout, or `None` if
+    /// it leaves them unchanged.
+    pub(crate) fn new(
+        color_type: ColorType,
+        bit_depth: BitDepth,
+        transform: Transformations,
+    ) -> Option<Self> {
+        let sample_size = match bit_depth {
+            BitDepth::Eight => 1,
+            BitDepth::Sixteen => 2,
+            _ => return None,
+        };
+        let has_alpha = matches!(color_type, ColorType::GrayscaleAlpha | ColorType::Rgba);
+        let is_rgb = matches!(color_type, ColorType::Rgb | ColorType::Rgba);
+
+        let swizzle = Swizzle {
+            bgr: is_rgb && transform.contains(Transformations::BGR),
+            swap_alpha: has_alpha && transform.contains(Transformations::SWAP_ALPHA),
+            invert_alpha: has_alpha && transform.contains(Transformations::INVERT_ALPHA),
+            swap_endian: sample_size == 2 && transform.contains(Transformations::SWAP_ENDIAN),
+            pixel_size: color_type.samples() * sample_size,
+            sample_size,
+        };
+        if swizzle.bgr || swizzle.swap_alpha || swizzle.invert_alpha || swizzle.swap_endian {
+            Some(swizzle)
+        } else {
+            None
+        }
+    }
+
+    /// Converts a row from the order of PNG, e.g. RGBA, to the requested layout, e.g. ABGR.
+    pub(crate) fn apply(&self, row: &mut [u8]) {
+        let n = self.sample_size;
+        for pixel in row.chunks_exact_mut(self.pixel_size) {
+            if self.invert_alpha {
+                invert(&mut pixel[self.pixel_size - n..]);
+            }
+            if self.bgr {
+                swap_samples(pixel, n);
+            }
+            if self.swap_alpha {
+                pixel.rotate_right(n);
+            }
+            if self.swap_endian {
+                swap_bytes(pixel);
+            }
+        }
+    }
+
+    /// Converts a row from the requested layout back to the order of PNG, the inverse of `apply`.
+    pub(crate) fn revert(&self, row: &mut [u8]) {
+        let n = self.sample_size;
+        for pixel in row.chunks_exact_mut(self.pixel_size) {
+            if self.swap_endian {
+                swap_bytes(pixel);
+            }
+            if self.swap_alpha {
+                pixel.rotate_left(n);
+            }
+            if self.bgr {
+                swap_samples(pixel, n);
+            }
+            if self.invert_alpha {
+                invert(&mut pixel[self.pixel_size - n..]);
+            }
+        }
+    }
+}
+
+/// Swaps the first and third sample of a pixel.
+fn swap_samples(pixel: &mut [u8], sample_size: usize) {
+    for i in 0..sample_size {
+        pixel.swap(i, 2 * sample_size + i);
+    }
+}
+
+/// Swaps the bytes of each 16-bit sample of a pixel.
+fn swap_bytes(pixel: &mut [u8]) {
+    for sample in pixel.chunks_exact_mut(2) {
+        sample.swap(0, 1);
+    }
+}
+
+/// Inverts a sample, turning opacity into transparency and vice versa.
+fn invert(sample: &mut [u8]) {
+    for byte in sample {
+        *byte = !*byte;
+    }
+}
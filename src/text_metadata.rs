@@ -5,9 +5,9 @@
 //! chunks. There are three kinds of text chunks.
 //!  -   `tEXt`: This has a `keyword` and `text` field, and is ISO 8859-1 encoded.
 //!  -   `zTXt`: This is semantically the same as `tEXt`, i.e. it has the same fields and
-//!       encoding, but the `text` field is compressed before being written into the PNG file.
+//!      encoding, but the `text` field is compressed before being written into the PNG file.
 //!  -   `iTXt`: This chunk allows for its `text` field to be any valid UTF-8, and supports
-//!        compression of the text field as well.
+//!      compression of the text field as well.
 //!
 //!  The `ISO 8859-1` encoding technically doesn't allow any control characters
 //!  to be used, but in practice these values are encountered anyway. This can
@@ -158,11 +158,11 @@ pub struct TEXtChunk {
     pub text: String,
 }
 
-fn decode_iso_8859_1(text: &[u8]) -> String {
+pub(crate) fn decode_iso_8859_1(text: &[u8]) -> String {
     text.iter().map(|&b| b as char).collect()
 }
 
-fn encode_iso_8859_1(text: &str) -> Result<Vec<u8>, TextEncodingError> {
+pub(crate) fn encode_iso_8859_1(text: &str) -> Result<Vec<u8>, TextEncodingError> {
     encode_iso_8859_1_iter(text).collect()
 }
 
@@ -178,6 +178,17 @@ fn encode_iso_8859_1_iter(text: &str) -> impl Iterator<Item = Result<u8, TextEnc
         .map(|c| u8::try_from(c as u32).map_err(|_| TextEncodingError::Unrepresentable))
 }
 
+/// Checks the restrictions the PNG specification places on keywords: 1-79 printable Latin-1
+/// characters without leading, trailing or consecutive spaces.
+pub(crate) fn is_valid_keyword(keyword: &str) -> bool {
+    let printable = |c: char| matches!(c as u32, 0x20..=0x7e | 0xa1..=0xff);
+    (1..=79).contains(&keyword.chars().count())
+        && keyword.chars().all(printable)
+        && !keyword.starts_with(' ')
+        && !keyword.ends_with(' ')
+        && !keyword.contains("  ")
+}
+
 fn decode_ascii(text: &[u8]) -> Result<&str, TextDecodingError> {
     if text.is_ascii() {
         // `from_utf8` cannot panic because we're already checked that `text` is ASCII-7.